@@ -0,0 +1,300 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Helpers for running a generator from a cargo build script.
+//!
+//! Most generators end up being run from `build.rs`, which needs some standard wiring to
+//! play nicely with cargo: the output should go into `OUT_DIR`, cargo should be told which
+//! input files to watch for changes, and any problems should be reported as warnings rather
+//! than vanishing into the build log. A [`Build`] takes care of those details:
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() -> std::io::Result<()> {
+//!     let mut build = codemaker::build::Build::new();
+//!     let spec: ApiSpec = serde_yaml::from_str(&build.read_input("api.yaml")?).unwrap();
+//!     let output = RustApiMaker::default().make_from(&spec);
+//!     for problem in output.lint() {
+//!         build.warning(problem);
+//!     }
+//!     build.write_output(&output)?;
+//!     build.write_include_stub("api.rs", &output)?;
+//!     Ok(())
+//! }
+//!
+//! // src/lib.rs
+//! codemaker::include_generated!("api.rs");
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{OutputFile, OutputFileSet, RenderOptions};
+
+/// The state of a generator running in a build script.
+///
+/// Directives for cargo are written as soon as they're known, to stdout by default.
+pub struct Build<W: Write = std::io::Stdout> {
+    out_dir: PathBuf,
+    directives: W,
+    render: RenderOptions,
+}
+
+impl Build {
+    /// Start a build, writing into the `OUT_DIR` that cargo gave us.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the `OUT_DIR` environment variable isn't set, which usually
+    /// means that it's not being run from a build script.
+    pub fn new() -> Self {
+        let out_dir = std::env::var_os("OUT_DIR")
+            .expect("OUT_DIR is not set; codemaker::build should only be used from a build script");
+        Build::with_out_dir(out_dir, std::io::stdout())
+    }
+}
+
+impl Default for Build {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Write> Build<W> {
+    /// Start a build writing into a specific directory, and sending cargo directives to
+    /// a specific writer. This is mostly useful for testing.
+    pub fn with_out_dir<P: Into<PathBuf>>(out_dir: P, directives: W) -> Self {
+        Build {
+            out_dir: out_dir.into(),
+            directives,
+            render: RenderOptions::default(),
+        }
+    }
+
+    /// How to format the output files, for generators with a house style.
+    pub fn render_options(mut self, options: RenderOptions) -> Self {
+        self.render = options;
+        self
+    }
+
+    /// The directory into which output is written.
+    pub fn out_dir(&self) -> &Path {
+        &self.out_dir
+    }
+
+    /// Finish the build, returning the writer to which cargo directives were sent.
+    pub fn into_directives(self) -> W {
+        self.directives
+    }
+
+    fn directive(&mut self, name: &str, value: &str) {
+        // There's nowhere useful to report a failure to talk to cargo.
+        let _ = writeln!(self.directives, "cargo:{}={}", name, value);
+    }
+
+    /// Tell cargo to re-run the build script if the given input file changes.
+    ///
+    /// Note that once any file has been tracked, cargo will *only* re-run the build script
+    /// when a tracked file changes, so you'll want to track every input the generator reads.
+    pub fn track_input<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        self.directive("rerun-if-changed", &path);
+    }
+
+    /// Read an input file as a string, and tell cargo to re-run the build script if it changes.
+    pub fn read_input<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<String> {
+        self.track_input(path.as_ref());
+        std::fs::read_to_string(path)
+    }
+
+    /// Report a problem as a cargo warning, which will be shown to whoever is running the build.
+    ///
+    /// Cargo warnings are a single line each, so multi-line messages are split into one
+    /// warning per line.
+    pub fn warning<D: std::fmt::Display>(&mut self, diagnostic: D) {
+        for line in diagnostic.to_string().lines() {
+            self.directive("warning", line);
+        }
+    }
+
+    /// Write a set of output files into the output directory.
+    ///
+    /// Files whose contents haven't changed are left untouched, so that they don't
+    /// needlessly trigger a rebuild of anything that includes them. Returns the full
+    /// paths of all the output files.
+    ///
+    /// If any file's path isn't inside the output directory, an error of kind `InvalidInput`
+    /// is returned and nothing is written.
+    pub fn write_output<S: OutputFileSet>(&mut self, output: &S) -> std::io::Result<Vec<PathBuf>> {
+        let files = output.files();
+        let paths = files
+            .iter()
+            .map(|file| self.output_path(file.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        for (file, path) in files.iter().zip(&paths) {
+            let mut contents = vec![];
+            file.write_into_with_options(&mut contents, &self.render)?;
+            write_if_changed(path, &contents)?;
+        }
+        Ok(paths)
+    }
+
+    fn output_path(&self, file_path: &Path) -> std::io::Result<PathBuf> {
+        let escapes = file_path
+            .components()
+            .any(|c| c == std::path::Component::ParentDir);
+        if !file_path.is_relative() || escapes {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("output file path {:?} is not inside the output directory", file_path),
+            ));
+        }
+        Ok(self.out_dir.join(file_path))
+    }
+
+    /// Generate Rust source that pulls the generated `.rs` files into the including crate.
+    ///
+    /// Each `.rs` file becomes a module named after the file, with directories becoming nested
+    /// modules, and its contents pulled in using `include!`. As in a crate's own source tree,
+    /// a `mod.rs` file provides the body of the module for its directory. Files with other
+    /// extensions are skipped. So output files `types.rs`, `client/mod.rs` and `client/v1.rs`
+    /// would produce something like:
+    ///
+    /// ```ignore
+    /// pub mod client {
+    ///     include!("/path/to/out/client/mod.rs");
+    ///     pub mod v1 { include!("/path/to/out/client/v1.rs"); }
+    /// }
+    /// pub mod types { include!("/path/to/out/types.rs"); }
+    /// ```
+    ///
+    /// Names that aren't valid identifiers are adjusted to make them so: keywords are written
+    /// as raw identifiers, and names that can't be raw identifiers (such as `self`) get a
+    /// trailing underscore.
+    ///
+    /// As with [`write_output`](Build::write_output), an error of kind `InvalidInput` is
+    /// returned if any file's path isn't inside the output directory.
+    pub fn include_stub<S: OutputFileSet>(&self, output: &S) -> std::io::Result<String> {
+        let mut root = StubModule::default();
+        for file in output.files() {
+            let file_path = file.path();
+            if file_path.extension() != Some(std::ffi::OsStr::new("rs")) {
+                continue;
+            }
+            let mut module = &mut root;
+            if let Some(parent) = file_path.parent() {
+                for dir in parent.components() {
+                    let name = module_name(dir.as_os_str());
+                    module = module.children.entry(name).or_default();
+                }
+            }
+            let include = self.output_path(file_path)?.to_string_lossy().into_owned();
+            if file_path.file_stem() != Some(std::ffi::OsStr::new("mod")) {
+                let name = module_name(file_path.file_stem().unwrap_or_default());
+                module = module.children.entry(name).or_default();
+            }
+            module.include = Some(include);
+        }
+        let mut stub = String::from("// @generated by codemaker; do not edit.\n");
+        if let Some(include) = &root.include {
+            stub.push_str(&format!("include!({:?});\n", include));
+        }
+        root.write_children(&mut stub, 0);
+        Ok(stub)
+    }
+
+    /// Write an [`include_stub`](Build::include_stub) for the output into a file in the output
+    /// directory, for use with [`include_generated!`](crate::include_generated).
+    pub fn write_include_stub<S: OutputFileSet>(&mut self, name: &str, output: &S) -> std::io::Result<PathBuf> {
+        let path = self.output_path(Path::new(name))?;
+        write_if_changed(&path, self.include_stub(output)?.as_bytes())?;
+        Ok(path)
+    }
+}
+
+fn write_if_changed(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Ok(existing) = std::fs::read(path) {
+        if existing == contents {
+            return Ok(());
+        }
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)
+}
+
+/// Turn a file or directory name into a Rust module name.
+fn module_name(name: &std::ffi::OsStr) -> String {
+    let name: String = name
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    match name.as_str() {
+        // These can't be used as raw identifiers, so they get a suffix instead.
+        "" | "_" => "__".to_string(),
+        "crate" | "self" | "super" | "Self" => format!("{}_", name),
+        "as" | "break" | "const" | "continue" | "else" | "enum" | "extern" | "false" | "fn" | "for"
+        | "if" | "impl" | "in" | "let" | "loop" | "match" | "mod" | "move" | "mut" | "pub" | "ref"
+        | "return" | "static" | "struct" | "trait" | "true" | "type" | "unsafe" | "use" | "where"
+        | "while" | "async" | "await" | "dyn" | "abstract" | "become" | "box" | "do" | "final"
+        | "macro" | "override" | "priv" | "typeof" | "unsized" | "virtual" | "yield" | "try" | "gen" => {
+            format!("r#{}", name)
+        }
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => format!("_{}", name),
+        _ => name,
+    }
+}
+
+#[derive(Default)]
+struct StubModule {
+    include: Option<String>,
+    children: BTreeMap<String, StubModule>,
+}
+
+impl StubModule {
+    fn write_children(&self, out: &mut String, depth: usize) {
+        let indent = "    ".repeat(depth);
+        for (name, child) in &self.children {
+            if child.children.is_empty() {
+                if let Some(include) = &child.include {
+                    out.push_str(&format!("{}pub mod {} {{ include!({:?}); }}\n", indent, name, include));
+                }
+                continue;
+            }
+            out.push_str(&format!("{}pub mod {} {{\n", indent, name));
+            if let Some(include) = &child.include {
+                out.push_str(&format!("{}    include!({:?});\n", indent, include));
+            }
+            child.write_children(out, depth + 1);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+    }
+}
+
+/// Include a stub written by [`Build::write_include_stub`] from the build script's output directory.
+///
+/// ```ignore
+/// codemaker::include_generated!("api.rs");
+/// ```
+#[macro_export]
+macro_rules! include_generated {
+    ($name:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $name));
+    };
+}
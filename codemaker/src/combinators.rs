@@ -0,0 +1,226 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Combinators for building rules out of other rules.
+//!
+//! Each of the combinators here wraps one or more existing [`CodeMakerRule`]s and is itself
+//! a [`CodeMakerRule`], so that a complex pipeline can be assembled from small rules that
+//! are each tested on their own:
+//!
+//! ```ignore
+//! use codemaker::{CodeMakerRuleExt, FnRule};
+//!
+//! // Make an assignment for each constant that we know how to render, or a comment
+//! // for the ones that we don't, skipping any that are marked as private.
+//! let constant = FnRule(|c: &Constant| c.as_assignment())
+//!     .or_else(FnRule(|c: &Constant| py::Statement::new_raw(format!("# unknown: {}", c.name))))
+//!     .filter(|c: &&Constant| !c.private);
+//! let module_body = FnRule(|s: &Spec| &s.constants).then(constant.flat_map());
+//! ```
+//!
+//! A rule "declines" to handle its input by returning `None`, which lets [`Filter`],
+//! [`Or`] and [`OrElse`] be combined to try several rules in turn.
+
+use std::marker::PhantomData;
+
+use crate::CodeMakerRule;
+
+/// A rule that borrows another rule, made by [`CodeMakerRuleExt::by_ref`].
+///
+/// The combinators take ownership of the rules that they combine, so this lets them
+/// borrow a maker instead. It implements every rule that the borrowed maker does.
+pub struct ByRef<'a, R: ?Sized>(pub &'a R);
+
+impl<'a, Input, Output, R> CodeMakerRule<Input, Output> for ByRef<'a, R>
+where
+    R: CodeMakerRule<Input, Output> + ?Sized,
+{
+    fn make_from(&self, input: Input) -> Output {
+        self.0.make_from(input)
+    }
+}
+
+/// Extension methods for combining [`CodeMakerRule`]s.
+///
+/// This is implemented for every [`CodeMakerRule`]. Since a maker will typically implement
+/// many rules, you may need to say which one you mean, e.g. with a [`FnRule`](crate::FnRule)
+/// closure or by calling the methods via the trait as `CodeMakerRuleExt::<In, Out>::then(...)`.
+pub trait CodeMakerRuleExt<Input, Output>: CodeMakerRule<Input, Output> + Sized {
+    /// Borrow this rule, so that it can be combined without giving up ownership.
+    fn by_ref(&self) -> ByRef<'_, Self> {
+        ByRef(self)
+    }
+
+    /// Chain this rule with another, feeding the output of this rule into the input of the next.
+    fn then<Next, R>(self, next: R) -> Chain<Self, R, Output>
+    where
+        R: CodeMakerRule<Output, Next>,
+    {
+        Chain {
+            first: self,
+            second: next,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Only apply this rule to inputs that match a predicate, declining the others.
+    fn filter<P>(self, predicate: P) -> Filter<Self, P>
+    where
+        P: Fn(&Input) -> bool,
+    {
+        Filter {
+            rule: self,
+            predicate,
+        }
+    }
+
+    /// Apply this rule to each item of a collection of inputs, and flatten the outputs.
+    ///
+    /// This is for rules that yield many outputs from each input, such as a rule that makes
+    /// a list of statements. The resulting rule accepts anything that can be iterated over
+    /// to produce the input type, and collects the flattened output into a `Vec`.
+    fn flat_map(self) -> FlatMap<Self, Output> {
+        FlatMap {
+            rule: self,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Use another rule to make the output whenever this rule declines its input.
+    ///
+    /// The input is cloned so that it can be passed to the fallback rule, which is cheap
+    /// for the borrowed inputs that rules usually deal with.
+    fn or_else<R, Inner>(self, fallback: R) -> OrElse<Self, R>
+    where
+        Self: CodeMakerRule<Input, Option<Inner>>,
+        R: CodeMakerRule<Input, Inner>,
+    {
+        OrElse {
+            first: self,
+            fallback,
+        }
+    }
+
+    /// Try another rule whenever this rule declines its input, declining if both do.
+    fn or<R, Inner>(self, other: R) -> Or<Self, R>
+    where
+        Self: CodeMakerRule<Input, Option<Inner>>,
+        R: CodeMakerRule<Input, Option<Inner>>,
+    {
+        Or {
+            first: self,
+            second: other,
+        }
+    }
+}
+
+impl<Input, Output, T> CodeMakerRuleExt<Input, Output> for T where T: CodeMakerRule<Input, Output> {}
+
+/// A rule that feeds the output of one rule into another, made by [`CodeMakerRuleExt::then`].
+pub struct Chain<A, B, Mid> {
+    first: A,
+    second: B,
+    phantom: PhantomData<fn(Mid) -> Mid>,
+}
+
+impl<Input, Mid, Output, A, B> CodeMakerRule<Input, Output> for Chain<A, B, Mid>
+where
+    A: CodeMakerRule<Input, Mid>,
+    B: CodeMakerRule<Mid, Output>,
+{
+    fn make_from(&self, input: Input) -> Output {
+        self.second.make_from(self.first.make_from(input))
+    }
+}
+
+/// A rule that declines inputs not matching a predicate, made by [`CodeMakerRuleExt::filter`].
+pub struct Filter<R, P> {
+    rule: R,
+    predicate: P,
+}
+
+impl<Input, Output, R, P> CodeMakerRule<Input, Option<Output>> for Filter<R, P>
+where
+    R: CodeMakerRule<Input, Output>,
+    P: Fn(&Input) -> bool,
+{
+    fn make_from(&self, input: Input) -> Option<Output> {
+        if (self.predicate)(&input) {
+            Some(self.rule.make_from(input))
+        } else {
+            None
+        }
+    }
+}
+
+/// A rule that flattens the outputs of another rule, made by [`CodeMakerRuleExt::flat_map`].
+pub struct FlatMap<R, Out> {
+    rule: R,
+    phantom: PhantomData<fn() -> Out>,
+}
+
+impl<Inputs, Item, Out, Output, R> CodeMakerRule<Inputs, Vec<Output>> for FlatMap<R, Out>
+where
+    Inputs: IntoIterator<Item = Item>,
+    R: CodeMakerRule<Item, Out>,
+    Out: IntoIterator<Item = Output>,
+{
+    fn make_from(&self, inputs: Inputs) -> Vec<Output> {
+        inputs
+            .into_iter()
+            .flat_map(|input| self.rule.make_from(input))
+            .collect()
+    }
+}
+
+/// A rule that falls back to another when the first declines, made by [`CodeMakerRuleExt::or_else`].
+pub struct OrElse<A, B> {
+    first: A,
+    fallback: B,
+}
+
+impl<Input, Output, A, B> CodeMakerRule<Input, Output> for OrElse<A, B>
+where
+    Input: Clone,
+    A: CodeMakerRule<Input, Option<Output>>,
+    B: CodeMakerRule<Input, Output>,
+{
+    fn make_from(&self, input: Input) -> Output {
+        match self.first.make_from(input.clone()) {
+            Some(output) => output,
+            None => self.fallback.make_from(input),
+        }
+    }
+}
+
+/// A rule that tries another when the first declines, made by [`CodeMakerRuleExt::or`].
+pub struct Or<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<Input, Output, A, B> CodeMakerRule<Input, Option<Output>> for Or<A, B>
+where
+    Input: Clone,
+    A: CodeMakerRule<Input, Option<Output>>,
+    B: CodeMakerRule<Input, Option<Output>>,
+{
+    fn make_from(&self, input: Input) -> Option<Output> {
+        self.first
+            .make_from(input.clone())
+            .or_else(|| self.second.make_from(input))
+    }
+}
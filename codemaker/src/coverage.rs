@@ -0,0 +1,208 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Measuring which rules are exercised by a set of inputs.
+//!
+//! A generator's test suite will typically run it over a corpus of example inputs, and it's
+//! useful to know whether those examples actually exercise all of the rules. A [`Coverage`]
+//! counts the invocations of each rule while making some output, and can report on the
+//! rules that were never run:
+//!
+//! ```ignore
+//! let mut coverage = Coverage::for_maker::<PythonStatusModuleMaker>();
+//! for example in load_example_inputs() {
+//!     coverage.record(|| maker.make(example));
+//! }
+//! println!("{}", coverage);
+//! assert!(coverage.never_run().is_empty());
+//! ```
+//!
+//! Like [`RuleGraph`](crate::RuleGraph), this only knows about rules that were defined
+//! using the rule-definition macros.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::graph::{registered_rules, rule_key, RuleInfo};
+
+thread_local! {
+    static RECORDING: RefCell<Option<HashMap<usize, RuleCount>>> = const { RefCell::new(None) };
+}
+
+/// Check whether a [`Coverage`] is currently recording on this thread.
+pub(crate) fn recording() -> bool {
+    RECORDING.with(|r| r.borrow().is_some())
+}
+
+/// Record that a rule was called, either directly or by mapping over an iterator.
+pub(crate) fn record_call(rule: &'static RuleInfo, from_iter: bool) {
+    RECORDING.with(|r| {
+        if let Some(counts) = r.borrow_mut().as_mut() {
+            let count = counts.entry(rule_key(rule)).or_default();
+            count.calls += 1;
+            if from_iter {
+                count.from_iter += 1;
+            }
+        }
+    })
+}
+
+/// The number of times that a rule was run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuleCount {
+    /// The total number of times the rule was run.
+    pub calls: usize,
+    /// How many of those were for items produced by `make_from_iter`.
+    pub from_iter: usize,
+}
+
+impl RuleCount {
+    fn add(&mut self, other: RuleCount) {
+        self.calls += other.calls;
+        self.from_iter += other.from_iter;
+    }
+}
+
+/// Counts of how many times each rule was run.
+pub struct Coverage {
+    rules: Vec<(&'static RuleInfo, RuleCount)>,
+    show_maker: bool,
+}
+
+impl Coverage {
+    /// Coverage of all the rules registered in the program.
+    pub fn all() -> Self {
+        Coverage {
+            rules: registered_rules(|_| true)
+                .into_iter()
+                .map(|r| (r, RuleCount::default()))
+                .collect(),
+            show_maker: true,
+        }
+    }
+
+    /// Coverage of the rules defined on the given maker type.
+    pub fn for_maker<M: ?Sized>() -> Self {
+        let maker_type = std::any::type_name::<M>();
+        Coverage {
+            rules: registered_rules(|rule| rule.maker_type() == maker_type)
+                .into_iter()
+                .map(|r| (r, RuleCount::default()))
+                .collect(),
+            show_maker: false,
+        }
+    }
+
+    /// Run a function, counting the rules that it runs.
+    ///
+    /// This may be called many times, e.g. once for each example input, and the counts
+    /// will accumulate. Rules that aren't part of this coverage report are ignored.
+    ///
+    /// Calls to `record` can be nested, in which case the rules run by the inner call are
+    /// also counted by the outer one.
+    pub fn record<T, F: FnOnce() -> T>(&mut self, func: F) -> T {
+        // Put back the outer recording when we're done, even if `func` panics,
+        // and make sure that it includes anything we counted.
+        struct Restore(Option<HashMap<usize, RuleCount>>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                RECORDING.with(|r| {
+                    let mut r = r.borrow_mut();
+                    let inner = std::mem::replace(&mut *r, self.0.take());
+                    if let (Some(outer), Some(inner)) = (r.as_mut(), inner) {
+                        for (key, new) in inner {
+                            outer.entry(key).or_default().add(new);
+                        }
+                    }
+                });
+            }
+        }
+        let _restore = Restore(RECORDING.with(|r| r.replace(Some(HashMap::new()))));
+        let result = func();
+        RECORDING.with(|r| {
+            let r = r.borrow();
+            let counts = r.as_ref().expect("recording was started above");
+            for (rule, count) in self.rules.iter_mut() {
+                if let Some(new) = counts.get(&rule_key(rule)) {
+                    count.add(*new);
+                }
+            }
+        });
+        result
+    }
+
+    /// All the rules in this coverage report, along with how many times they've been run.
+    pub fn counts(&self) -> &[(&'static RuleInfo, RuleCount)] {
+        &self.rules
+    }
+
+    /// How many times the given rule has been run.
+    pub fn count(&self, rule: &RuleInfo) -> RuleCount {
+        self.rules
+            .iter()
+            .find(|(r, _)| std::ptr::eq(*r, rule))
+            .map(|(_, count)| *count)
+            .unwrap_or_default()
+    }
+
+    /// The rules that have never been run.
+    pub fn never_run(&self) -> Vec<&'static RuleInfo> {
+        self.rules
+            .iter()
+            .filter(|(_, count)| count.calls == 0)
+            .map(|(rule, _)| *rule)
+            .collect()
+    }
+
+    fn label(&self, rule: &RuleInfo) -> String {
+        if self.show_maker {
+            format!("{}: {}", rule.maker_type(), rule)
+        } else {
+            rule.to_string()
+        }
+    }
+}
+
+/// A human-readable report of the coverage, listing the rules that were never run.
+impl std::fmt::Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let never_run = self.never_run();
+        writeln!(
+            f,
+            "{} of {} rules were run:",
+            self.rules.len() - never_run.len(),
+            self.rules.len()
+        )?;
+        for (rule, count) in &self.rules {
+            if count.calls > 0 {
+                writeln!(
+                    f,
+                    "  {:>6} calls ({} from make_from_iter)  {}",
+                    count.calls,
+                    count.from_iter,
+                    self.label(rule)
+                )?;
+            }
+        }
+        if !never_run.is_empty() {
+            writeln!(f, "{} rules were never run:", never_run.len())?;
+            for rule in never_run {
+                writeln!(f, "  {}", self.label(rule))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Checking that a maker produces the same output every time.
+//!
+//! It's easy for the iteration order of a `HashMap` to leak into generated code, which then
+//! changes from run to run and causes spurious diffs. [`check_determinism`] makes the output
+//! twice, with different hash seeds for any `HashMap`s and `HashSet`s made along the way, and
+//! reports the first place where the two runs disagree:
+//!
+//! ```ignore
+//! #[test]
+//! fn test_output_is_deterministic() {
+//!     let codes = load_test_codes();
+//!     codemaker::check_determinism(&PythonStatusModuleMaker::new(), &codes).unwrap();
+//! }
+//! ```
+//!
+//! Rules that need to consume a map can use [`IterSorted`] to do so in a stable order.
+//! Note that any state that the maker keeps between runs, such as a [`MemoCache`](crate::MemoCache),
+//! may hide nondeterminism in the rules that it caches.
+//!
+//! Only the seeds of the standard library's `RandomState` can be changed between runs, and
+//! only for maps created on the current thread. Nondeterminism won't be found in maps that
+//! use a fixed hasher, like `BuildHasherDefault` (and so `FxHashMap`) or `ahash` with fixed
+//! keys, nor in maps created on other threads, since their order doesn't change between the
+//! runs. The second run is deliberately not moved onto a fresh thread, which would get fresh
+//! seeds, since that would require makers and their inputs to be `Send`.
+//!
+//! Generators using `codemaker_cli` can check their output in the same way by running them
+//! with `--check-determinism`, which uses [`perturb_hash_seeds`] and [`compare_outputs`].
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::{CodeMaker, OutputFile, OutputFileSet, RenderOptions};
+
+/// Make the output twice from the same input, and check that both runs render identically.
+///
+/// Passing is not proof that the maker is deterministic, only that these two runs agreed.
+/// Changing the hash seeds is best-effort (see [`perturb_hash_seeds`]), and nondeterminism
+/// from anywhere else, such as maps with a fixed hasher or made on other threads, or that
+/// only shows up in some runs, can still get through. A failure, on the other hand, always
+/// means that the output varies.
+pub fn check_determinism<'a, M>(maker: &M, input: M::Input) -> Result<(), Nondeterminism>
+where
+    M: CodeMaker<'a>,
+    M::Input: Clone,
+{
+    let first = maker.make(input.clone());
+    perturb_hash_seeds();
+    let second = maker.make(input);
+    compare_outputs(&first, &second)
+}
+
+/// Check that two outputs render identically, reporting the first place where they differ.
+pub fn compare_outputs<A: OutputFileSet, B: OutputFileSet>(first: &A, second: &B) -> Result<(), Nondeterminism> {
+    compare(render_files(first), render_files(second))
+}
+
+/// Try to make maps created from now on in this thread get different hash seeds to those created earlier.
+///
+/// This is best-effort. Each new `RandomState` in a thread uses the next in a sequence of
+/// seeds, so creating a varying number of them shifts the seeds used by the next run along
+/// the sequence; that relies on how the standard library currently seeds `RandomState`,
+/// which it doesn't guarantee. This has no effect on maps with other hashers, or on other
+/// threads.
+pub fn perturb_hash_seeds() {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    for _ in 0..=(nanos % 61) {
+        let _ = RandomState::new();
+    }
+}
+
+fn render_files<S: OutputFileSet>(output: &S) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files: Vec<_> = output
+        .files()
+        .into_iter()
+        .map(|file| {
+            let mut contents = vec![];
+            file.write_into_with_options(&mut contents, &RenderOptions::default())
+                .expect("writing into a Vec should not fail");
+            (file.path().to_path_buf(), contents)
+        })
+        .collect();
+    // The order in which files are written doesn't matter, only their contents.
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    files
+}
+
+fn compare(first: Vec<(PathBuf, Vec<u8>)>, second: Vec<(PathBuf, Vec<u8>)>) -> Result<(), Nondeterminism> {
+    let mut first = first.into_iter().peekable();
+    let mut second = second.into_iter().peekable();
+    loop {
+        let (a, b) = match (first.peek(), second.peek()) {
+            (None, None) => return Ok(()),
+            (Some((path, _)), None) => return Err(Nondeterminism::missing(path, true)),
+            (None, Some((path, _))) => return Err(Nondeterminism::missing(path, false)),
+            (Some((a, _)), Some((b, _))) if a < b => return Err(Nondeterminism::missing(a, true)),
+            (Some((a, _)), Some((b, _))) if a > b => return Err(Nondeterminism::missing(b, false)),
+            _ => (first.next().unwrap(), second.next().unwrap()),
+        };
+        if a.1 == b.1 {
+            continue;
+        }
+        let first_text = String::from_utf8_lossy(&a.1);
+        let second_text = String::from_utf8_lossy(&b.1);
+        let mut first_lines = first_text.split('\n');
+        let mut second_lines = second_text.split('\n');
+        let mut line = 1;
+        loop {
+            match (first_lines.next(), second_lines.next()) {
+                (Some(x), Some(y)) if x == y => line += 1,
+                (x, y) => {
+                    return Err(Nondeterminism {
+                        path: a.0,
+                        line: Some(line),
+                        first: x.map(String::from),
+                        second: y.map(String::from),
+                        only_in_first: false,
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// The first difference found between two runs of a maker over the same input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nondeterminism {
+    path: PathBuf,
+    line: Option<usize>,
+    first: Option<String>,
+    second: Option<String>,
+    only_in_first: bool,
+}
+
+impl Nondeterminism {
+    fn missing(path: &Path, only_in_first: bool) -> Self {
+        Nondeterminism {
+            path: path.to_path_buf(),
+            line: None,
+            first: None,
+            second: None,
+            only_in_first,
+        }
+    }
+
+    /// The path of the first output file that differed between the runs.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The first line number at which the file differed, or `None` if the file was only made by one run.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The differing line from the first run, or `None` if the first run's file ended before it.
+    ///
+    /// This is also `None` if the file was only made by one of the runs.
+    pub fn first(&self) -> Option<&str> {
+        self.first.as_deref()
+    }
+
+    /// The differing line from the second run, or `None` if the second run's file ended before it.
+    pub fn second(&self) -> Option<&str> {
+        self.second.as_deref()
+    }
+}
+
+impl std::fmt::Display for Nondeterminism {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |line: &Option<String>| match line {
+            Some(line) => format!("{:?}", line),
+            None => "end of file".to_string(),
+        };
+        match self.line {
+            None if self.only_in_first => {
+                write!(f, "{} was only made by the first run", self.path.display())
+            }
+            None => write!(f, "{} was only made by the second run", self.path.display()),
+            Some(line) => write!(
+                f,
+                "{}:{} differs between runs\n  first:  {}\n  second: {}",
+                self.path.display(),
+                line,
+                describe(&self.first),
+                describe(&self.second)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Nondeterminism {}
+
+/// Iterate over the contents of a hashed collection in sorted order, so that output made from it is stable.
+pub trait IterSorted {
+    type Item;
+
+    /// The items of the collection, sorted by key.
+    fn iter_sorted(self) -> std::vec::IntoIter<Self::Item>;
+}
+
+impl<'a, K: Ord, V, S> IterSorted for &'a HashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+
+    fn iter_sorted(self) -> std::vec::IntoIter<Self::Item> {
+        let mut items: Vec<_> = self.iter().collect();
+        items.sort_by(|a, b| a.0.cmp(b.0));
+        items.into_iter()
+    }
+}
+
+impl<'a, T: Ord, S> IterSorted for &'a HashSet<T, S> {
+    type Item = &'a T;
+
+    fn iter_sorted(self) -> std::vec::IntoIter<Self::Item> {
+        let mut items: Vec<_> = self.iter().collect();
+        items.sort();
+        items.into_iter()
+    }
+}
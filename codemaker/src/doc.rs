@@ -0,0 +1,264 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Line-width-aware layout of code, using a Wadler/Oppen-style document algebra.
+//!
+//! Rather than writing text directly, a target crate can describe a fragment of code as a
+//! [`Doc`] made up of text, places where the line may be broken, and groups that should be
+//! kept on one line if they fit. The renderer then picks the layout, breaking the outermost
+//! groups first until each line fits within the [`RenderOptions`] maximum width:
+//!
+//! ```ignore
+//! let args = Doc::join(args.iter().map(Doc::text), Doc::text(",").append(Doc::line()));
+//! let call = Doc::text("print(")
+//!     .append(Doc::softline().append(args).append(Doc::if_break(Doc::text(","), Doc::nil())).nest())
+//!     .append(Doc::softline())
+//!     .append(Doc::text(")"))
+//!     .group();
+//! ```
+//!
+//! which renders as `print(a, b)` when it fits on the line, and otherwise as:
+//!
+//! ```text
+//! print(
+//!     a,
+//!     b,
+//! )
+//! ```
+
+use std::io::Write;
+
+use crate::{Render, RenderOptions};
+
+/// A document describing the possible layouts of a fragment of code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Doc {
+    /// The empty document.
+    Nil,
+    /// Some text, which must not contain any line breaks.
+    Text(String),
+    /// A line break, or a space if the enclosing group fits on one line.
+    Line,
+    /// A line break, or nothing if the enclosing group fits on one line.
+    SoftLine,
+    /// A line break, always.
+    HardLine,
+    /// A sequence of documents, one after the other.
+    Concat(Vec<Doc>),
+    /// A document whose line breaks are indented by an additional number of levels.
+    Nest(usize, Box<Doc>),
+    /// A document that is laid out on a single line if it fits.
+    Group(Box<Doc>),
+    /// Different documents depending on whether the enclosing group was broken, or not.
+    IfBreak(Box<Doc>, Box<Doc>),
+}
+
+impl Doc {
+    pub fn nil() -> Doc {
+        Doc::Nil
+    }
+
+    pub fn text<T: ToString + ?Sized>(text: &T) -> Doc {
+        let text = text.to_string();
+        debug_assert!(!text.contains('\n'), "Doc text must not contain line breaks: {:?}", text);
+        Doc::Text(text)
+    }
+
+    pub fn line() -> Doc {
+        Doc::Line
+    }
+
+    pub fn softline() -> Doc {
+        Doc::SoftLine
+    }
+
+    pub fn hardline() -> Doc {
+        Doc::HardLine
+    }
+
+    pub fn concat<I: IntoIterator<Item = Doc>>(docs: I) -> Doc {
+        Doc::Concat(docs.into_iter().collect())
+    }
+
+    /// Concatenate documents with a separator between each of them.
+    pub fn join<I: IntoIterator<Item = Doc>>(docs: I, separator: Doc) -> Doc {
+        let mut joined = vec![];
+        for doc in docs {
+            if !joined.is_empty() {
+                joined.push(separator.clone());
+            }
+            joined.push(doc);
+        }
+        Doc::Concat(joined)
+    }
+
+    /// Use `broken` if the enclosing group is broken across lines, and `flat` if it isn't.
+    pub fn if_break(broken: Doc, flat: Doc) -> Doc {
+        Doc::IfBreak(Box::new(broken), Box::new(flat))
+    }
+
+    /// Follow this document with another.
+    pub fn append(self, other: Doc) -> Doc {
+        match self {
+            Doc::Concat(mut docs) => {
+                docs.push(other);
+                Doc::Concat(docs)
+            }
+            Doc::Nil => other,
+            doc => Doc::Concat(vec![doc, other]),
+        }
+    }
+
+    /// Indent any line breaks in this document by one more level.
+    pub fn nest(self) -> Doc {
+        Doc::Nest(1, Box::new(self))
+    }
+
+    /// Lay this document out on a single line if it fits, or break its lines if not.
+    pub fn group(self) -> Doc {
+        Doc::Group(Box::new(self))
+    }
+
+    /// Render the document, laid out to fit within the maximum width from the given options.
+    ///
+    /// The writer is assumed to be positioned at the start of a line that has already been
+    /// indented by `indent` levels, and any line breaks will be indented to at least that level.
+    /// Lines are always ended with `\n`; use a [`RenderOptions::writer`] to apply other line endings.
+    pub fn write_into<W: Write>(&self, writer: &mut W, options: &RenderOptions, indent: usize) -> std::io::Result<()> {
+        let mut printer = Printer {
+            writer,
+            options,
+            column: options.indentation_width(indent),
+            pending_indent: None,
+        };
+        printer.print(vec![(indent, Mode::Break, self)])
+    }
+
+    /// Render the document to a string, starting without any indentation.
+    pub fn pretty(&self, options: &RenderOptions) -> String {
+        let mut buf = vec![];
+        self.write_into(&mut buf, options, 0)
+            .expect("writing into a Vec should not fail");
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+impl From<&str> for Doc {
+    fn from(text: &str) -> Doc {
+        Doc::text(text)
+    }
+}
+
+impl From<String> for Doc {
+    fn from(text: String) -> Doc {
+        Doc::text(&text)
+    }
+}
+
+/// Rendering a document on its own lays it out without any indentation.
+impl Render for Doc {
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.write_into(writer, &RenderOptions::default(), 0)
+    }
+
+    fn render_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        self.write_into(writer, options, 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+struct Printer<'a, W: Write> {
+    writer: &'a mut W,
+    options: &'a RenderOptions,
+    column: usize,
+    // Indentation is only written once there's some text on the line, to avoid trailing whitespace.
+    pending_indent: Option<usize>,
+}
+
+impl<W: Write> Printer<'_, W> {
+    fn print(&mut self, mut stack: Vec<(usize, Mode, &Doc)>) -> std::io::Result<()> {
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Nil => (),
+                Doc::Text(text) => self.text(text)?,
+                Doc::Line if mode == Mode::Flat => self.text(" ")?,
+                Doc::SoftLine if mode == Mode::Flat => (),
+                Doc::Line | Doc::SoftLine | Doc::HardLine => self.newline(indent)?,
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (indent, mode, d))),
+                Doc::Nest(levels, doc) => stack.push((indent + levels, mode, doc)),
+                Doc::Group(doc) => {
+                    let remaining = self.options.width() as isize - self.column as isize;
+                    let mode = if mode == Mode::Flat || fits(remaining, (indent, Mode::Flat, doc), &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((indent, mode, doc));
+                }
+                Doc::IfBreak(broken, flat) => stack.push((indent, mode, if mode == Mode::Break { broken } else { flat })),
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> std::io::Result<()> {
+        if let Some(indent) = self.pending_indent.take() {
+            self.writer.write_all(self.options.indentation(indent).as_bytes())?;
+        }
+        self.writer.write_all(text.as_bytes())?;
+        self.column += text.chars().count();
+        Ok(())
+    }
+
+    fn newline(&mut self, indent: usize) -> std::io::Result<()> {
+        self.writer.write_all(b"\n")?;
+        self.pending_indent = Some(indent);
+        self.column = self.options.indentation_width(indent);
+        Ok(())
+    }
+}
+
+/// Check whether a document fits in the remaining width, along with whatever follows it up to the next line break.
+fn fits(mut remaining: isize, next: (usize, Mode, &Doc), rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut rest = rest.iter().rev();
+    let mut stack = vec![next];
+    while remaining >= 0 {
+        let (indent, mode, doc) = match stack.pop() {
+            Some(item) => item,
+            None => match rest.next() {
+                Some(item) => *item,
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Nil => (),
+            Doc::Text(text) => remaining -= text.chars().count() as isize,
+            Doc::Line if mode == Mode::Flat => remaining -= 1,
+            Doc::SoftLine if mode == Mode::Flat => (),
+            Doc::Line | Doc::SoftLine | Doc::HardLine => return true,
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (indent, mode, d))),
+            Doc::Nest(levels, doc) => stack.push((indent + levels, mode, doc)),
+            Doc::Group(doc) => stack.push((indent, mode, doc)),
+            Doc::IfBreak(broken, flat) => stack.push((indent, mode, if mode == Mode::Break { broken } else { flat })),
+        }
+    }
+    false
+}
@@ -0,0 +1,404 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Introspecting the rules of a maker, and how they fit together.
+//!
+//! Every rule defined using [`define_codemaker_rules!`](crate::define_codemaker_rules)
+//! or its stateless equivalents registers a [`RuleInfo`] describing the rule, which can
+//! be collected into a [`RuleGraph`] for review:
+//!
+//! ```ignore
+//! let mut graph = RuleGraph::for_maker::<PythonStatusModuleMaker>();
+//! // Find any delegations that weren't declared by running the maker
+//! // over some representative input.
+//! graph.record(|| maker.make(input));
+//! std::fs::write("rules.dot", graph.to_dot())?;
+//! for rule in graph.unreachable() {
+//!     println!("never reached: {}", rule);
+//! }
+//! ```
+//!
+//! The types that a rule passes to `make_from` are usually inferred, so the macros can't
+//! tell which other rules it delegates to. A rule can declare them with an attribute,
+//! which the macros register along with the rest of its metadata:
+//!
+//! ```ignore
+//! define_codemaker_rules! {
+//!     MyCodeMaker as self {
+//!         #[delegates(StatusCode => py::Statement)]
+//!         Vec<StatusCode> as input => py::Module {
+//!             py::Module::new("codes").extend(self.make_from_iter(input))
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Other delegations can be found by running the maker under [`RuleGraph::record`].
+//! Rules that implement [`CodeMakerRule`](crate::CodeMakerRule) by hand are not included,
+//! since there's nothing to register them.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use crate::provenance::json_string;
+
+/// Metadata about an individual rule, registered by the rule-definition macros.
+#[derive(Debug)]
+pub struct RuleInfo {
+    maker_type: fn() -> &'static str,
+    input_type: &'static str,
+    output_type: &'static str,
+    doc: &'static str,
+    delegates: &'static [(&'static str, &'static str)],
+}
+
+impl RuleInfo {
+    #[doc(hidden)]
+    pub const fn new(
+        maker_type: fn() -> &'static str,
+        input_type: &'static str,
+        output_type: &'static str,
+        doc: &'static str,
+        delegates: &'static [(&'static str, &'static str)],
+    ) -> Self {
+        RuleInfo {
+            maker_type,
+            input_type,
+            output_type,
+            doc,
+            delegates,
+        }
+    }
+
+    /// The fully-qualified name of the type on which the rule is defined.
+    pub fn maker_type(&self) -> &'static str {
+        (self.maker_type)()
+    }
+
+    /// The input type of the rule, as written in its definition.
+    pub fn input_type(&self) -> &'static str {
+        self.input_type
+    }
+
+    /// The output type of the rule, as written in its definition.
+    pub fn output_type(&self) -> &'static str {
+        self.output_type
+    }
+
+    /// The doc comment on the rule, if any.
+    ///
+    /// For `match` rules this includes the doc comments on each of the variant rules.
+    pub fn doc(&self) -> String {
+        self.doc
+            .lines()
+            .map(|ln| ln.strip_prefix(' ').unwrap_or(ln))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string()
+    }
+
+    /// The input and output types of the rules that this rule declares it delegates to.
+    ///
+    /// These come from any `#[delegates(InputType => OutputType, ...)]` attributes on
+    /// the rule, and are spelled as written there.
+    pub fn declared_delegates(&self) -> &'static [(&'static str, &'static str)] {
+        self.delegates
+    }
+}
+
+impl std::fmt::Display for RuleInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} => {}", self.input_type, self.output_type)
+    }
+}
+
+/// Wrapper for registering rules with `inventory`.
+#[doc(hidden)]
+pub struct RegisteredRule(pub &'static RuleInfo);
+
+inventory::collect!(RegisteredRule);
+
+thread_local! {
+    static RECORDING: RefCell<Option<Recording>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct Recording {
+    calls: BTreeSet<(usize, usize)>,
+    entry_points: BTreeSet<usize>,
+}
+
+pub(crate) fn rule_key(rule: &'static RuleInfo) -> usize {
+    rule as *const RuleInfo as usize
+}
+
+/// Check whether a [`RuleGraph`] is currently recording on this thread.
+pub(crate) fn recording() -> bool {
+    RECORDING.with(|r| r.borrow().is_some())
+}
+
+/// Record that a rule was called, either by another rule or from outside of any rule.
+pub(crate) fn record_call(caller: Option<&'static RuleInfo>, callee: &'static RuleInfo) {
+    RECORDING.with(|r| {
+        if let Some(recording) = r.borrow_mut().as_mut() {
+            match caller {
+                Some(caller) => recording.calls.insert((rule_key(caller), rule_key(callee))),
+                None => recording.entry_points.insert(rule_key(callee)),
+            };
+        }
+    })
+}
+
+/// Find the registered rules that match a predicate, sorted by maker type and then by
+/// input and output type.
+pub(crate) fn registered_rules<P: Fn(&RuleInfo) -> bool>(predicate: P) -> Vec<&'static RuleInfo> {
+    let mut rules: Vec<&'static RuleInfo> = inventory::iter::<RegisteredRule>
+        .into_iter()
+        .map(|r| r.0)
+        .filter(|r| predicate(r))
+        .collect();
+    rules.sort_by_key(|r| (r.maker_type(), r.input_type, r.output_type));
+    rules
+}
+
+/// A graph of rules and the other rules that they delegate to.
+///
+/// The nodes of the graph are found from the rules registered by the rule-definition macros.
+/// The edges of the graph start out as the delegations declared with `#[delegates(...)]`
+/// on those rules, and any others can be found by running the maker under
+/// [`RuleGraph::record`].
+pub struct RuleGraph {
+    rules: Vec<&'static RuleInfo>,
+    calls: BTreeSet<(usize, usize)>,
+    entry_points: BTreeSet<usize>,
+}
+
+impl RuleGraph {
+    /// A graph of all the rules registered in the program.
+    pub fn all() -> Self {
+        Self::from_rules(|_| true)
+    }
+
+    /// A graph of the rules defined on the given maker type.
+    pub fn for_maker<M: ?Sized>() -> Self {
+        let maker_type = std::any::type_name::<M>();
+        Self::from_rules(|rule| rule.maker_type() == maker_type)
+    }
+
+    fn from_rules<P: Fn(&RuleInfo) -> bool>(predicate: P) -> Self {
+        let rules = registered_rules(predicate);
+        let mut calls = BTreeSet::new();
+        for (caller, rule) in rules.iter().enumerate() {
+            for (input_type, output_type) in rule.delegates {
+                let callee = rules.iter().position(|r| {
+                    r.maker_type() == rule.maker_type()
+                        && r.input_type == *input_type
+                        && r.output_type == *output_type
+                });
+                if let Some(callee) = callee {
+                    calls.insert((caller, callee));
+                }
+            }
+        }
+        RuleGraph {
+            rules,
+            calls,
+            entry_points: BTreeSet::new(),
+        }
+    }
+
+    /// Run a function, recording which rules it calls and which other rules they delegate to.
+    ///
+    /// This may be called several times with different inputs, in order to get better
+    /// coverage of the delegations between rules. Calls to rules that are not part of this
+    /// graph are ignored, and any rule that is called other than from another rule in this
+    /// graph is considered to be an entry point.
+    pub fn record<T, F: FnOnce() -> T>(&mut self, func: F) -> T {
+        struct Restore(Option<Recording>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                RECORDING.with(|r| r.replace(self.0.take()));
+            }
+        }
+        let _restore = Restore(RECORDING.with(|r| r.replace(Some(Recording::default()))));
+        let result = func();
+        let recording = RECORDING
+            .with(|r| r.borrow_mut().take())
+            .expect("recording was started above");
+        let index_of = |key: usize| self.rules.iter().position(|r| rule_key(r) == key);
+        let mut calls = vec![];
+        let mut entry_points: Vec<usize> = recording
+            .entry_points
+            .iter()
+            .filter_map(|key| index_of(*key))
+            .collect();
+        for (caller, callee) in recording.calls {
+            match (index_of(caller), index_of(callee)) {
+                (Some(caller), Some(callee)) => calls.push((caller, callee)),
+                (None, Some(callee)) => entry_points.push(callee),
+                _ => (),
+            }
+        }
+        self.calls.extend(calls);
+        self.entry_points.extend(entry_points);
+        result
+    }
+
+    /// All the rules in the graph, sorted by maker type and then by input and output type.
+    pub fn rules(&self) -> &[&'static RuleInfo] {
+        &self.rules
+    }
+
+    /// The rules that the given rule declares, or has been seen, to delegate to.
+    pub fn delegates_of(&self, rule: &RuleInfo) -> Vec<&'static RuleInfo> {
+        self.calls
+            .iter()
+            .filter(|(caller, _)| std::ptr::eq(self.rules[*caller], rule))
+            .map(|(_, callee)| self.rules[*callee])
+            .collect()
+    }
+
+    /// All delegations that have been seen, as `(caller, callee)` pairs.
+    pub fn delegations(&self) -> Vec<(&'static RuleInfo, &'static RuleInfo)> {
+        self.calls
+            .iter()
+            .map(|(caller, callee)| (self.rules[*caller], self.rules[*callee]))
+            .collect()
+    }
+
+    /// The rules that have been seen to be called from outside of the graph.
+    pub fn entry_points(&self) -> Vec<&'static RuleInfo> {
+        self.entry_points.iter().map(|i| self.rules[*i]).collect()
+    }
+
+    /// The rules that can't be reached from any entry point by following delegations.
+    ///
+    /// This is only meaningful after calling [`RuleGraph::record`] with representative
+    /// input; until then, every rule is unreachable.
+    pub fn unreachable(&self) -> Vec<&'static RuleInfo> {
+        let reachable = self.reachable();
+        (0..self.rules.len())
+            .filter(|i| !reachable.contains(i))
+            .map(|i| self.rules[i])
+            .collect()
+    }
+
+    fn reachable(&self) -> BTreeSet<usize> {
+        let mut reachable = BTreeSet::new();
+        let mut todo: Vec<usize> = self.entry_points.iter().cloned().collect();
+        while let Some(i) = todo.pop() {
+            if reachable.insert(i) {
+                todo.extend(
+                    self.calls
+                        .iter()
+                        .filter(|(caller, _)| *caller == i)
+                        .map(|(_, callee)| *callee),
+                );
+            }
+        }
+        reachable
+    }
+
+    /// Render the graph in Graphviz DOT format.
+    ///
+    /// Each rule is a node labelled with its input and output types, and has its doc
+    /// comment as a tooltip. Entry points are drawn in bold and, once something has
+    /// been recorded, unreachable rules are drawn dashed.
+    pub fn to_dot(&self) -> String {
+        let reachable = self.reachable();
+        let mut dot = String::from("digraph rules {\n    node [shape=box];\n");
+        for (i, rule) in self.rules.iter().enumerate() {
+            let mut attrs = format!(
+                "label={}, tooltip={}",
+                dot_string(&rule.to_string()),
+                dot_string(&rule.doc())
+            );
+            if self.entry_points.contains(&i) {
+                attrs.push_str(", style=bold");
+            } else if !self.entry_points.is_empty() && !reachable.contains(&i) {
+                attrs.push_str(", style=dashed");
+            }
+            dot.push_str(&format!("    r{} [{}];\n", i, attrs));
+        }
+        for (caller, callee) in &self.calls {
+            dot.push_str(&format!("    r{} -> r{};\n", caller, callee));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the graph as JSON.
+    ///
+    /// The output is a list of rules, each of which lists the other rules that it
+    /// delegates to by their index in the list:
+    ///
+    /// ```json
+    /// {"rules": [
+    ///   {"maker": "my_crate::Maker", "input_type": "Vec<u32>", "output_type": "String", "doc": "",
+    ///    "entry_point": true, "reachable": true, "delegates_to": [1]},
+    ///   ...
+    /// ]}
+    /// ```
+    pub fn to_json(&self) -> String {
+        let reachable = self.reachable();
+        let mut json = String::from("{\"rules\": [");
+        for (i, rule) in self.rules.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let delegates: Vec<String> = self
+                .calls
+                .iter()
+                .filter(|(caller, _)| *caller == i)
+                .map(|(_, callee)| callee.to_string())
+                .collect();
+            json.push_str(&format!(
+                "\n  {{\"maker\": {}, \"input_type\": {}, \"output_type\": {}, \"doc\": {}, \
+                 \"entry_point\": {}, \"reachable\": {}, \"delegates_to\": [{}]}}",
+                json_string(rule.maker_type()),
+                json_string(rule.input_type),
+                json_string(rule.output_type),
+                json_string(&rule.doc()),
+                self.entry_points.contains(&i),
+                reachable.contains(&i),
+                delegates.join(", "),
+            ));
+        }
+        json.push_str("\n]}\n");
+        json
+    }
+}
+
+/// Quote a string for use as a DOT attribute value.
+///
+/// Only quotes and backslashes need escaping inside a quoted DOT string; newlines are
+/// written as `\n` so each node stays on one line, and other characters are passed
+/// through as-is.
+fn dot_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
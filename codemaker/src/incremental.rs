@@ -0,0 +1,366 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Incremental generation, reusing the output made from inputs that haven't changed.
+//!
+//! For large inputs, re-running every rule on every build can take long enough to hurt
+//! the edit-compile cycle. An [`IncrementalCache`] splits the work up by input subtree:
+//! each subtree is identified by a key, and the files made from it are recorded on disk
+//! along with a hash of the subtree. On the next run, subtrees whose hash hasn't changed
+//! reuse the recorded files rather than running the rules again:
+//!
+//! ```ignore
+//! let mut cache = IncrementalCache::open("target/api-codegen", env!("CARGO_PKG_VERSION"))?;
+//! let mut output = CachedFiles::default();
+//! for endpoint in &api.endpoints {
+//!     output.extend(cache.make(&endpoint.name, endpoint, &options, |e| maker.make_from(e))?);
+//! }
+//! cache.save()?;
+//! output.write_into_dir("python/api")?;
+//! ```
+//!
+//! The input subtrees must implement `Hash`, and any other state that affects the output
+//! (such as configuration held by the maker) must be captured in the generator version
+//! given when opening the cache. Entries recorded with a different version are discarded.
+//! The [`RenderOptions`] are part of each entry's key, so rendering with different options
+//! also makes the files afresh.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{OutputFile, OutputFileSet, RenderOptions};
+
+const INDEX_FILE: &str = "index";
+const BLOBS_DIR: &str = "blobs";
+
+/// A 128-bit FNV-1a hasher, used because its output is stable between runs of the program.
+///
+/// The hasher itself is deterministic, but the bytes that a `Hash` implementation feeds it
+/// are not guaranteed to be: the standard library may change how its types hash between
+/// Rust versions, and integers are written in the platform's byte order and width. A hash
+/// is therefore only meaningful to the same build of the generator, and anything that
+/// persists it (such as an [`IncrementalCache`]) must be invalidated when the toolchain or
+/// target platform changes, for example by including them in the generator version.
+///
+/// The cache trusts that equal hashes mean equal contents, so this uses a hash wide enough
+/// that an accidental collision isn't a realistic concern. The full hash is available from
+/// [`StableHasher::finish128`]; [`Hasher::finish`] only gives the low 64 bits of it.
+#[derive(Debug, Clone)]
+pub struct StableHasher(u128);
+
+impl StableHasher {
+    /// The full 128-bit hash of the bytes written so far.
+    pub fn finish128(&self) -> u128 {
+        self.0
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0x6c62_272e_07bb_0142_62b8_2175_6295_c58d)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u128::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0000_0100_0000_0000_0000_0000_013b);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+/// Hash a value with a [`StableHasher`].
+pub fn stable_hash<T: Hash + ?Sized>(value: &T) -> u128 {
+    let mut hasher = StableHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish128()
+}
+
+struct Entry {
+    input_hash: u128,
+    files: Vec<(PathBuf, u128)>,
+}
+
+/// An on-disk cache of the files made from each input subtree.
+pub struct IncrementalCache {
+    dir: PathBuf,
+    version: String,
+    entries: BTreeMap<String, Entry>,
+    used: BTreeSet<String>,
+    reused: usize,
+    regenerated: usize,
+}
+
+impl IncrementalCache {
+    /// Open the cache in the given directory, creating it if necessary.
+    ///
+    /// The `version` should change whenever the generator changes in a way that affects
+    /// its output; if it doesn't match the version that the cache was saved with, then
+    /// everything will be regenerated. Since input hashes come from `Hash` implementations,
+    /// which aren't stable across Rust versions or platforms, the version should also change
+    /// whenever the generator is built with a different toolchain or for a different target.
+    pub fn open<P: AsRef<Path>>(dir: P, version: &str) -> std::io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(dir.join(BLOBS_DIR))?;
+        let mut cache = IncrementalCache {
+            dir,
+            version: version.to_string(),
+            entries: BTreeMap::new(),
+            used: BTreeSet::new(),
+            reused: 0,
+            regenerated: 0,
+        };
+        match std::fs::read_to_string(cache.dir.join(INDEX_FILE)) {
+            Ok(index) => cache.load_index(&index)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        Ok(cache)
+    }
+
+    fn load_index(&mut self, index: &str) -> std::io::Result<()> {
+        let mut lines = index.lines();
+        match lines.next() {
+            Some(line) if line.strip_prefix("version\t").map(unescape) == Some(self.version.clone()) => (),
+            // Saved by a different version of the generator, so none of it can be trusted.
+            _ => return Ok(()),
+        }
+        let mut current = None;
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["entry", key, hash] => {
+                    let entry = Entry {
+                        input_hash: parse_hash(hash)?,
+                        files: vec![],
+                    };
+                    self.entries.insert(unescape(key), entry);
+                    current = Some(unescape(key));
+                }
+                ["file", path, blob] => match current.as_ref().and_then(|key| self.entries.get_mut(key)) {
+                    Some(entry) => entry.files.push((PathBuf::from(unescape(path)), parse_hash(blob)?)),
+                    None => return Err(invalid_index(line)),
+                },
+                _ => return Err(invalid_index(line)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Make the files for an input subtree, reusing the cached files if the input is unchanged.
+    ///
+    /// The `key` identifies the subtree between runs, and must be unique within a run; using
+    /// the same key twice is an error of kind `InvalidInput`. If the cache has files for that
+    /// key made from an input with the same hash and rendered with the same options, they are
+    /// returned without calling `make`. Otherwise `make` is called to make the files afresh,
+    /// and they are rendered with `options` and recorded in the cache.
+    pub fn make<I, S, F>(
+        &mut self,
+        key: &str,
+        input: &I,
+        options: &RenderOptions,
+        make: F,
+    ) -> std::io::Result<CachedFiles>
+    where
+        I: Hash + ?Sized,
+        S: OutputFileSet,
+        F: FnOnce(&I) -> S,
+    {
+        if !self.used.insert(key.to_string()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("incremental cache key {:?} was used more than once", key),
+            ));
+        }
+        let input_hash = {
+            let mut hasher = StableHasher::default();
+            input.hash(&mut hasher);
+            options.hash(&mut hasher);
+            hasher.finish128()
+        };
+        if let Some(entry) = self.entries.get(key) {
+            if entry.input_hash == input_hash {
+                if let Ok(files) = self.read_files(entry) {
+                    self.reused += 1;
+                    return Ok(files);
+                }
+                // If the cached files have gone missing or been corrupted, just make them again.
+            }
+        }
+        self.regenerated += 1;
+        let output = make(input);
+        let mut files = CachedFiles::default();
+        let mut entry = Entry {
+            input_hash,
+            files: vec![],
+        };
+        for file in output.files() {
+            let mut contents = vec![];
+            file.write_into_with_options(&mut contents, options)?;
+            let blob = stable_hash(&contents);
+            std::fs::write(self.blob_path(blob), &contents)?;
+            entry.files.push((file.path().to_path_buf(), blob));
+            files.files.push(CachedFile {
+                path: file.path().to_path_buf(),
+                contents,
+            });
+        }
+        self.entries.insert(key.to_string(), entry);
+        Ok(files)
+    }
+
+    fn read_files(&self, entry: &Entry) -> std::io::Result<CachedFiles> {
+        let mut files = CachedFiles::default();
+        for (path, blob) in &entry.files {
+            let contents = std::fs::read(self.blob_path(*blob))?;
+            if stable_hash(&contents) != *blob {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("cached contents of {:?} do not match their hash", path),
+                ));
+            }
+            files.files.push(CachedFile {
+                path: path.clone(),
+                contents,
+            });
+        }
+        Ok(files)
+    }
+
+    fn blob_path(&self, blob: u128) -> PathBuf {
+        self.dir.join(BLOBS_DIR).join(format!("{:032x}", blob))
+    }
+
+    /// How many input subtrees reused their cached files during this run.
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+
+    /// How many input subtrees had their files made afresh during this run.
+    pub fn regenerated(&self) -> usize {
+        self.regenerated
+    }
+
+    /// Save the cache to disk, for use by the next run.
+    ///
+    /// Entries for keys that weren't used during this run are forgotten, on the assumption
+    /// that the corresponding subtrees have been removed from the input.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let used = &self.used;
+        self.entries.retain(|key, _| used.contains(key));
+        let mut index = vec![];
+        writeln!(index, "version\t{}", escape(&self.version))?;
+        let mut blobs = BTreeSet::new();
+        for (key, entry) in &self.entries {
+            writeln!(index, "entry\t{}\t{:032x}", escape(key), entry.input_hash)?;
+            for (path, blob) in &entry.files {
+                writeln!(index, "file\t{}\t{:032x}", escape(&path.to_string_lossy()), blob)?;
+                blobs.insert(format!("{:032x}", blob));
+            }
+        }
+        std::fs::write(self.dir.join(INDEX_FILE), index)?;
+        // Clean up the contents of files that are no longer referenced.
+        for blob in std::fs::read_dir(self.dir.join(BLOBS_DIR))? {
+            let blob = blob?;
+            if !blobs.contains(&*blob.file_name().to_string_lossy()) {
+                std::fs::remove_file(blob.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\t', "%09")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("%0D", "\r")
+        .replace("%0A", "\n")
+        .replace("%09", "\t")
+        .replace("%25", "%")
+}
+
+fn parse_hash(s: &str) -> std::io::Result<u128> {
+    u128::from_str_radix(s, 16).map_err(|_| invalid_index(s))
+}
+
+fn invalid_index(line: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid line in incremental cache index: {:?}", line),
+    )
+}
+
+/// Files made by an [`IncrementalCache`], either afresh or reused from an earlier run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CachedFiles {
+    files: Vec<CachedFile>,
+}
+
+impl OutputFileSet for CachedFiles {
+    type OutputFile = CachedFile;
+    fn files(&self) -> Vec<&Self::OutputFile> {
+        self.files.iter().collect()
+    }
+}
+
+impl std::iter::Extend<CachedFile> for CachedFiles {
+    fn extend<T: IntoIterator<Item = CachedFile>>(&mut self, iter: T) {
+        self.files.extend(iter)
+    }
+}
+
+impl IntoIterator for CachedFiles {
+    type Item = CachedFile;
+    type IntoIter = std::vec::IntoIter<CachedFile>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.into_iter()
+    }
+}
+
+/// A single file made by an [`IncrementalCache`], already rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedFile {
+    path: PathBuf,
+    contents: Vec<u8>,
+}
+
+impl CachedFile {
+    /// The rendered contents of the file.
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+}
+
+impl OutputFile for CachedFile {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn write_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.contents)
+    }
+}
@@ -0,0 +1,134 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Support for two-phase code generation.
+//!
+//! Some outputs need global knowledge about the input before any code can be emitted,
+//! such as a list of forward declarations or an `__all__` list at the top of a module.
+//! The [`CodeMaker`](crate::CodeMaker) trait supports this by running an optional
+//! collection pass over the input to fill an [`Index`], before running the usual
+//! rules to make the output. The rules can then query the index as they go.
+
+use std::any::{Any, TypeId};
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+
+/// A typed index of facts collected from the input before making any output.
+///
+/// This is a little like a map from types to lists, which lets a collection pass
+/// record facts of any number of different types without having to define a
+/// single struct to hold them all:
+///
+/// ```ignore
+/// index.insert(ForwardDecl { name: "Foo".into() });
+/// index.insert(ExportedName("Foo".into()));
+/// // ...and then later...
+/// for decl in index.all::<ForwardDecl>() {
+///     ...
+/// }
+/// ```
+#[derive(Default)]
+pub struct Index {
+    entries: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Index {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Add an entry to the index.
+    ///
+    /// Entries of each type are kept in the order they were inserted.
+    pub fn insert<T: 'static>(&mut self, entry: T) {
+        self.entries_mut::<T>().push(entry)
+    }
+
+    /// Get all the entries of a given type, in the order they were inserted.
+    pub fn all<T: 'static>(&self) -> &[T] {
+        match self.entries.get(&TypeId::of::<T>()) {
+            None => &[],
+            Some(entries) => entries
+                .downcast_ref::<Vec<T>>()
+                .expect("index entries are keyed by their type"),
+        }
+    }
+
+    /// Find the first entry of a given type that matches a predicate.
+    pub fn find<T: 'static, P: Fn(&T) -> bool>(&self, predicate: P) -> Option<&T> {
+        self.all::<T>().iter().find(|entry| predicate(entry))
+    }
+
+    /// Check whether the index contains an entry equal to the one given.
+    pub fn contains<T: PartialEq + 'static>(&self, entry: &T) -> bool {
+        self.all::<T>().contains(entry)
+    }
+
+    /// Get mutable access to all entries of a given type.
+    ///
+    /// This can be useful for post-processing the index at the end of the collection
+    /// pass, for example to sort definitions so that dependencies come first.
+    pub fn entries_mut<T: 'static>(&mut self) -> &mut Vec<T> {
+        self.entries
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Vec::<T>::new()))
+            .downcast_mut::<Vec<T>>()
+            .expect("index entries are keyed by their type")
+    }
+}
+
+/// A place for a [`CodeMaker`](crate::CodeMaker) to keep its [`Index`].
+///
+/// Rules take `&self`, so a maker that wants to query an index from its rules needs to
+/// store it somewhere that can be filled in after the maker has been constructed. This
+/// is that somewhere. It's filled in by [`CodeMaker::make`](crate::CodeMaker::make)
+/// at the end of the collection pass, and can then be queried by rules using
+/// [`IndexCell::get`].
+#[derive(Default)]
+pub struct IndexCell {
+    index: RefCell<Option<Index>>,
+}
+
+impl IndexCell {
+    pub fn new() -> Self {
+        IndexCell {
+            index: RefCell::new(None),
+        }
+    }
+
+    /// Get the index produced by the collection pass.
+    ///
+    /// Panics if called before the collection pass has been run.
+    pub fn get(&self) -> Ref<'_, Index> {
+        Ref::map(self.index.borrow(), |index| {
+            index
+                .as_ref()
+                .expect("index has not been filled by a collection pass")
+        })
+    }
+
+    /// Store the index produced by the collection pass, replacing any previous index.
+    pub fn set(&self, index: Index) {
+        self.index.replace(Some(index));
+    }
+
+    /// Check whether the collection pass has been run.
+    pub fn is_set(&self) -> bool {
+        self.index.borrow().is_some()
+    }
+}
@@ -47,7 +47,7 @@
 //!   * A single target crate can be used by multiple different consumers who want to target
 //!     the same output format.
 //!   * A single consumer can target multiple output formats by adding transformation rules
-//!     for multiple target crates.
+//!     for multiple target crates, and combine their outputs using [`Targets`].
 //!
 //! ## Writing a Target crate
 //!
@@ -62,6 +62,13 @@
 //!
 //! The top-level data structures provided by a target crate will typically implement the
 //! [`OutputFileSet`] trait, so that consumers can easily render the final output to disk.
+//! Their files should honour the consumer's formatting preferences from [`RenderOptions`].
+//! Smaller fragments of output, like individual statements, should implement [`Render`] so
+//! that consumers can test each of their rules on its own using [`assert_makes!`].
+//!
+//! Much of the builder API tends to be small, repetitive methods for constructing each type and
+//! adding things to it. The `codemaker_derive` crate provides a `#[derive(FluentBuilder)]` macro
+//! that can generate the common ones for you.
 //!
 //! ## Writing a Consumer crate
 //!
@@ -185,6 +192,66 @@
 #[cfg(test)]
 mod tests;
 
+mod index;
+pub use index::{Index, IndexCell};
+
+mod memo;
+pub use memo::{ByAddress, EmitOnce, MemoCache};
+
+mod combinators;
+pub use combinators::{ByRef, Chain, CodeMakerRuleExt, Filter, FlatMap, Or, OrElse};
+
+mod coverage;
+pub use coverage::{Coverage, RuleCount};
+
+mod determinism;
+pub use determinism::{check_determinism, compare_outputs, perturb_hash_seeds, IterSorted, Nondeterminism};
+
+mod doc;
+pub use doc::Doc;
+
+mod graph;
+pub use graph::{RuleGraph, RuleInfo};
+
+mod incremental;
+pub use incremental::{stable_hash, CachedFile, CachedFiles, IncrementalCache, StableHasher};
+
+mod registry;
+pub use registry::{FnRule, RuleRegistry, UnknownRuleSet};
+
+mod render;
+pub use render::{IndentStyle, LineEnding, Render, RenderOptions, RenderWriter};
+
+mod streaming;
+pub use streaming::StreamingFile;
+
+mod targets;
+pub use targets::{TargetFile, TargetOptions, Targets};
+
+mod testing;
+pub use testing::diff_lines;
+
+mod postprocess;
+pub use postprocess::{PostProcess, PostProcessError};
+
+mod provenance;
+pub use provenance::{
+    provenance_enabled, with_provenance, Origin, SourceMap, SourceMapEntry, SourceWriter,
+};
+
+pub mod build;
+
+/// Implementation details used by our macros, which are not part of the public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use super::graph::RegisteredRule;
+    pub use super::provenance::{enter_item, enter_rule, ProvenanceGuard};
+    pub use super::testing::{assert_rendered, assert_structurally_eq};
+    pub use inventory;
+    #[cfg(feature = "tracing")]
+    pub use tracing;
+}
+
 /// A convenience module for bringing `codemaker` traits into scope.
 ///
 /// Consumers of this module are encourated to use-all from this submodule
@@ -194,7 +261,9 @@ mod tests;
 /// use codemaker::traits::*;
 /// ```
 pub mod traits {
-    pub use super::{CodeMaker, CodeMakerRule, Extend, FluentAPI, StatelessCodeMakerRule};
+    pub use super::{
+        CodeMaker, CodeMakerRule, Extend, FluentAPI, IterSorted, Render, StatelessCodeMakerRule,
+    };
 }
 
 /// A set of files produced by making some code.
@@ -222,19 +291,107 @@ pub trait OutputFileSet {
     /// Any missing directories will be created automatically, and I/O
     /// may result in partially-written output being left on disk.
     fn write_into_dir<P: AsRef<std::path::Path>>(&self, base_directory: P) -> std::io::Result<()> {
-        let base_directory = base_directory.as_ref();
-        for file in self.files() {
-            let file_path = file.path();
-            if !file_path.is_relative() {
-                panic!("OutputFile returned non-relative path {:?}", file_path);
+        self.write_into_dir_with_options(base_directory, &RenderOptions::default())
+    }
+
+    /// Write the output into the given base directory, formatted according to the given options.
+    fn write_into_dir_with_options<P: AsRef<std::path::Path>>(
+        &self,
+        base_directory: P,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        write_files_into_dir(self, base_directory.as_ref(), |file, full_path| {
+            let mut f = create_output_file(full_path)?;
+            file.write_into_with_options(&mut f, options)?;
+            std::io::Write::flush(&mut f)
+        })
+    }
+
+    /// Write the output into the given base directory, running each file through a post-processing pipeline.
+    ///
+    /// Files that fail to be post-processed are not written, and the failures are returned
+    /// so that they can be reported for each file. I/O errors stop the writing altogether.
+    fn write_into_dir_with_post_process<P: AsRef<std::path::Path>>(
+        &self,
+        base_directory: P,
+        options: &RenderOptions,
+        post_process: &PostProcess,
+    ) -> std::io::Result<Vec<PostProcessError>> {
+        let mut failures = vec![];
+        write_files_into_dir(self, base_directory.as_ref(), |file, full_path| {
+            let mut contents = vec![];
+            file.write_into_with_options(&mut contents, options)?;
+            match post_process.process(file.path(), contents) {
+                Ok(contents) => {
+                    let mut f = create_output_file(full_path)?;
+                    std::io::Write::write_all(&mut f, &contents)?;
+                    std::io::Write::flush(&mut f)
+                }
+                Err(failure) => {
+                    failures.push(failure);
+                    Ok(())
+                }
             }
-            let file_path = base_directory.join(file.path());
-            std::fs::create_dir_all(file_path.parent().unwrap_or(base_directory))?;
-            let mut f = std::fs::File::create(file_path)?;
-            file.write_into(&mut f)?;
+        })?;
+        Ok(failures)
+    }
+
+    /// Write the output into the given base directory, along with a sidecar source map for each file.
+    ///
+    /// This behaves like [`OutputFileSet::write_into_dir_with_options`], but also writes a file
+    /// named like `<filename>.map.json` next to each output file, containing the [`SourceMap`]
+    /// produced by [`OutputFile::write_into_with_source_map`]. The maps will only contain
+    /// anything interesting if the output was made using [`with_provenance`].
+    fn write_into_dir_with_source_maps<P: AsRef<std::path::Path>>(
+        &self,
+        base_directory: P,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        write_files_into_dir(self, base_directory.as_ref(), |file, full_path| {
+            let mut f = create_output_file(full_path)?;
+            let map = file.write_into_with_source_map(&mut f, options)?;
+            std::io::Write::flush(&mut f)?;
+            let mut map_path = full_path.as_os_str().to_owned();
+            map_path.push(".map.json");
+            std::fs::write(map_path, map.to_json(file.path()))
+        })
+    }
+}
+
+/// The loop shared by the various ways of writing an [`OutputFileSet`] into a directory.
+///
+/// This calls `write` with each file and the full path at which it should be written. Paths
+/// that would escape the base directory are reported as an error before anything is written.
+fn write_files_into_dir<S, F>(files: &S, base_directory: &std::path::Path, mut write: F) -> std::io::Result<()>
+where
+    S: OutputFileSet + ?Sized,
+    F: FnMut(&S::OutputFile, &std::path::Path) -> std::io::Result<()>,
+{
+    let files = files.files();
+    for file in &files {
+        let file_path = file.path();
+        let escapes = file_path
+            .components()
+            .any(|c| c == std::path::Component::ParentDir);
+        if !file_path.is_relative() || escapes {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("output file path {:?} is not inside the output directory", file_path),
+            ));
         }
-        Ok(())
     }
+    for file in files {
+        write(file, &base_directory.join(file.path()))?;
+    }
+    Ok(())
+}
+
+/// Create a file for writing output, along with any missing parent directories.
+fn create_output_file(path: &std::path::Path) -> std::io::Result<std::io::BufWriter<std::fs::File>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(std::io::BufWriter::new(std::fs::File::create(path)?))
 }
 
 /// An individual file produced by making some code.
@@ -248,8 +405,8 @@ pub trait OutputFileSet {
 pub trait OutputFile {
     /// The path at which to write the file, relative to base output directory.
     ///
-    /// Trait implementors must ensure that this returns a *relative* path, as trait
-    /// consumers may panic if it does not.
+    /// Trait implementors must ensure that this returns a *relative* path that stays inside
+    /// the base directory, as trait consumers may panic or fail if it does not.
     fn path(&self) -> &std::path::Path;
 
     /// Write the contents of this file into the given Writer.
@@ -257,6 +414,39 @@ pub trait OutputFile {
     /// Trait consumers should implement this method to render the actual
     /// contents of the file.
     fn write_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()>;
+
+    /// Write the contents of this file into the given Writer, formatted according to the given options.
+    ///
+    /// The default implementation applies the options that don't depend on the structure of
+    /// the output, like line endings, to the text produced by [`OutputFile::write_into`].
+    /// Trait implementors should override this to also honour the indent style and line width,
+    /// typically by rendering into [`RenderOptions::writer`] with those options in hand.
+    fn write_into_with_options<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        let mut writer = options.writer(writer);
+        self.write_into(&mut writer)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Write the contents of this file into the given Writer, formatted according to the given
+    /// options, and report where each part came from.
+    ///
+    /// Trait implementors that capture an [`Origin`] for the nodes of their output should
+    /// override this to render through a [`SourceWriter`] and return the resulting map.
+    /// The default implementation just calls [`OutputFile::write_into_with_options`] and
+    /// returns an empty map.
+    fn write_into_with_source_map<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        options: &RenderOptions,
+    ) -> std::io::Result<SourceMap> {
+        self.write_into_with_options(writer, options)?;
+        Ok(SourceMap::new())
+    }
 }
 
 /// Automatic impl of [`OutputFileSet`] for any [`OutputFile`].
@@ -311,7 +501,7 @@ pub trait FluentAPI: Sized {
     /// some helper methods around those closures, it can make a very nice builder interface.
     fn edit<F: FnOnce(&mut Self)>(mut self, func: F) -> Self {
         func(&mut self);
-        return self;
+        self
     }
 }
 
@@ -375,13 +565,65 @@ impl<T, Item> Extend<Item> for T where T: std::iter::Extend<Item> + FluentAPI {}
 /// This trait doesn't actually *do* very much just yet, it's mostly designed to
 /// help consumers form a correct mental model of how the pieces fit together.
 /// The real action happens in the [`CodeMakerRule`] trait.
+///
+/// Some outputs need global knowledge about the input before any code can be emitted,
+/// such as forward declarations or a list of exported names. For these cases a maker
+/// can opt in to a two-phase protocol by implementing [`CodeMaker::collect`] to fill an
+/// [`Index`] from the input, and [`CodeMaker::index_cell`] to say where the index should
+/// be stored. Calling [`CodeMaker::make`] will then run the collection pass before making
+/// the output, and the rules can query the index via the [`IndexCell`]:
+///
+/// ```ignore
+/// struct PythonStatusModuleMaker {
+///     index: IndexCell,
+/// }
+///
+/// impl<'a> CodeMaker<'a> for PythonStatusModuleMaker {
+///     type Input = &'a StatusCodes;
+///     type Output = py::Module;
+///
+///     fn collect(&self, input: &Self::Input, index: &mut Index) {
+///         for (_, name) in &input.codes {
+///             index.insert(ExportedName(name.clone()));
+///         }
+///     }
+///
+///     fn index_cell(&self) -> Option<&IndexCell> {
+///         Some(&self.index)
+///     }
+/// }
+/// ```
 pub trait CodeMaker<'a>: CodeMakerRule<Self::Input, Self::Output> {
     type Input: 'a;
     type Output: OutputFileSet + 'a;
 
+    /// Make the output from the given input.
+    ///
+    /// If the maker provides an [`IndexCell`], this will first run the collection pass
+    /// and store the resulting [`Index`] before delegating to the [`CodeMakerRule`] impls.
     fn make(&self, input: Self::Input) -> Self::Output {
+        if let Some(cell) = self.index_cell() {
+            let mut index = Index::new();
+            self.collect(&input, &mut index);
+            cell.set(index);
+        }
         CodeMakerRule::<Self::Input, Self::Output>::make_from(self, input)
     }
+
+    /// The collection pass, for finding out things about the input before making any output.
+    ///
+    /// The default implementation does nothing. Makers that need global knowledge about
+    /// their input can override this to record it in the given [`Index`].
+    fn collect(&self, _input: &Self::Input, _index: &mut Index) {}
+
+    /// Where to store the [`Index`] produced by the collection pass.
+    ///
+    /// The default implementation returns `None`, in which case the collection pass is
+    /// skipped entirely. Makers that implement [`CodeMaker::collect`] will need to return
+    /// an [`IndexCell`] from here in order for their rules to be able to query it.
+    fn index_cell(&self) -> Option<&IndexCell> {
+        None
+    }
 }
 
 /// An individual rule for making code by structural matching.
@@ -433,6 +675,7 @@ pub trait CodeMakerRule<Input, Output> {
         CodeMakerRuleMap {
             maker: self,
             iter: input.into_iter(),
+            index: 0,
             phantom: std::marker::PhantomData,
         }
     }
@@ -453,6 +696,7 @@ where
 {
     maker: &'a T,
     iter: I,
+    index: usize,
     // Not sure why this is needed, but Rust complains at me
     // about `Output` being unconstrained if I dont have it...
     phantom: std::marker::PhantomData<Output>,
@@ -467,7 +711,10 @@ where
 {
     type Item = Output;
     fn next(&mut self) -> Option<Output> {
-        self.iter.next().map(|i| self.maker.make_from(i))
+        let input = self.iter.next()?;
+        let _guard = __private::enter_item(self.index);
+        self.index += 1;
+        Some(self.maker.make_from(input))
     }
 }
 
@@ -500,15 +747,105 @@ where
 /// start of the rule and the output type at the end. (The caller has to specify the
 /// argument names explicitly because of Rust's macro hygiene trules, which prevent
 /// the macro from injecting variables into your code).
+///
+/// When the input is an enum, it's often convenient to write a separate rule for each
+/// of its variants rather than one big `match` expression. You can do that by prefixing
+/// the rule with `match` and giving a pattern and body for each variant in turn:
+///
+/// ```ignore
+/// define_codemaker_rules!{
+///     MyCodeMaker as self {
+///         match InputEnum => OutputType {
+///             /// Each variant rule can have its own doc comment.
+///             InputEnum::Int(value) => {
+///                 self.make_int(value)
+///             }
+///             InputEnum::Record(record) => {
+///                 self.make_from(record)
+///             }
+///         }
+///     }
+/// }
+/// ```
+///
+/// This produces a single [`CodeMakerRule`] implementation for `InputEnum` that matches
+/// on its input and dispatches to the corresponding variant rule. Since it's an ordinary
+/// `match` expression, forgetting to write a rule for one of the variants will give you
+/// a compile error. Any other attributes on the variant rules (such as `#[cfg(...)]`)
+/// are applied to the corresponding arms of the `match`. If the variant rules need to
+/// refer to the input as a whole, you can give it a name with `match InputEnum as input`.
+///
+/// Ordinary rules are expanded all at once, but rules using `match` are expanded one at
+/// a time. If you have a lot of rules, you may need to put the `match` rules at the end
+/// to avoid hitting the compiler's `recursion_limit`.
+///
+/// The generated rules also keep track of which rule is running, so that output made
+/// inside [`with_provenance`] can record its [`Origin`], and register a [`RuleInfo`] with
+/// their input and output types, doc comments and any delegations declared with a
+/// `#[delegates(InputType => OutputType, ...)]` attribute for introspection via [`RuleGraph`].
+/// If the `tracing` feature of this crate is enabled, each rule will also run inside a
+/// trace-level span named after its input and output types, with target `codemaker::rules`.
 #[macro_export]
 macro_rules! define_codemaker_rules {
+    // Ordinary definition syntax. This is expanded in one go rather than munched
+    // a rule at a time, so that makers with lots of rules don't run into the
+    // recursion limit.
     ($CM:ty as $self:ident {
         $( $(#[$($attr:tt)+])* $In:ty as $input:pat => $Out:ty $body:block )*
     }) => {
         $(
-            $(#[$($attr)+])*
+            $crate::__codemaker_rule! {
+                $CM as $self [$(#[$($attr)+])*] $In as $input => $Out $body
+            }
+        )*
+    };
+    // Rules that dispatch on each variant of an enum.
+    ($CM:ty as $self:ident {
+        $(#[$($attr:tt)+])* match $In:ty $(as $input:ident)? => $Out:ty {
+            $($variants:tt)*
+        } $($tail:tt)*
+    }) => {
+        $crate::__codemaker_match_rule! {
+            $CM as $self [$(#[$($attr)+])*] [$($input)?] $In => $Out { $($variants)* }
+        }
+        $crate::define_codemaker_rules! { $CM as $self { $($tail)* } }
+    };
+    // A run of ordinary rules followed by some rules that dispatch on variants.
+    ($CM:ty as $self:ident {
+        $( $(#[$($attr:tt)+])* $In:ty as $input:pat => $Out:ty $body:block )+
+        match $($tail:tt)*
+    }) => {
+        $(
+            $crate::__codemaker_rule! {
+                $CM as $self [$(#[$($attr)+])*] $In as $input => $Out $body
+            }
+        )+
+        $crate::define_codemaker_rules! { $CM as $self { match $($tail)* } }
+    };
+    // An ordinary rule followed by a variant rule with attributes, which we can't
+    // tell apart from the attributes of another ordinary rule in a single arm.
+    ($CM:ty as $self:ident {
+        $(#[$($attr:tt)+])* $In:ty as $input:pat => $Out:ty $body:block $($tail:tt)*
+    }) => {
+        $crate::__codemaker_rule! {
+            $CM as $self [$(#[$($attr)+])*] $In as $input => $Out $body
+        }
+        $crate::define_codemaker_rules! { $CM as $self { $($tail)* } }
+    };
+}
+
+/// Internal helper for defining a single ordinary rule in [`define_codemaker_rules!`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_rule {
+    (@rule [$(#[$($attr:tt)+])*] $delegates:tt $CM:ty as $self:ident $In:ty as $input:pat => $Out:ty $body:block) => {
+        $(#[$($attr)+])*
+        const _: () = {
+            $crate::__codemaker_register_rule! { $CM, $In, $Out, [$(#[$($attr)+])*] $delegates }
             impl $crate::CodeMakerRule<$In, $Out> for $CM {
                 fn make_from(&$self, $input: $In) -> $Out {
+                    let _provenance = $crate::__private::enter_rule(&__CODEMAKER_RULE);
+                    $crate::__codemaker_rule_span!($In, $Out);
                     // Let the method body use any of our traits.
                     // This seems unhygienic, but works, and is almost
                     // certainly what the consumer wants.
@@ -517,10 +854,343 @@ macro_rules! define_codemaker_rules {
                     $body
                 }
             }
+        };
+    };
+    ($CM:ty as $self:ident $attrs:tt $In:ty as $input:pat => $Out:ty $body:block) => {
+        $crate::__codemaker_rule_attrs! {
+            $attrs [] [] $crate::__codemaker_rule { $CM as $self $In as $input => $Out $body }
+        }
+    };
+}
+
+/// Internal helper for defining a single rule that dispatches on each variant of an enum.
+///
+/// If the rule doesn't give a name for its input then we make one up, which macro hygiene
+/// will keep out of reach of the variant rules. Attributes on the variant rules are passed
+/// through to the arms of the generated `match`, and any doc comments among them are
+/// included in the docs of the rule.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_match_rule {
+    (@rule $attrs:tt $delegates:tt $CM:ty as $self:ident [] $In:ty => $Out:ty { $($variants:tt)* }) => {
+        $crate::__codemaker_match_rule! {
+            @rule $attrs $delegates $CM as $self [input] $In => $Out { $($variants)* }
+        }
+    };
+    (@rule [$(#[$($attr:tt)+])*] $delegates:tt $CM:ty as $self:ident [$input:ident] $In:ty => $Out:ty {
+        $( $(#[$($vattr:tt)+])* $($variant:pat)|+ => $body:block )*
+    }) => {
+        $(#[$($attr)+])*
+        const _: () = {
+            $crate::__codemaker_register_rule! {
+                $CM, $In, $Out, [$(#[$($attr)+])* $($(#[$($vattr)+])*)*] $delegates
+            }
+            impl $crate::CodeMakerRule<$In, $Out> for $CM {
+                #[allow(unused_doc_comments)]
+                fn make_from(&$self, $input: $In) -> $Out {
+                    let _provenance = $crate::__private::enter_rule(&__CODEMAKER_RULE);
+                    $crate::__codemaker_rule_span!($In, $Out);
+                    // Let the method body use any of our traits.
+                    // This seems unhygienic, but works, and is almost
+                    // certainly what the consumer wants.
+                    #[allow(unused_imports)]
+                    use $crate::traits::*;
+                    match $input {
+                        $( $(#[$($vattr)+])* $($variant)|+ => $body )*
+                    }
+                }
+            }
+        };
+    };
+    ($CM:ty as $self:ident $attrs:tt $input:tt $In:ty => $Out:ty { $($variants:tt)* }) => {
+        $crate::__codemaker_rule_attrs! {
+            $attrs [] [] $crate::__codemaker_match_rule { $CM as $self $input $In => $Out { $($variants)* } }
+        }
+    };
+}
+
+/// Internal helper for separating the `#[delegates(...)]` attributes of a rule from the rest.
+///
+/// This munches through the given attributes, then calls back into the given macro with
+/// `@rule`, the remaining attributes and the declared delegations ahead of its arguments.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_rule_attrs {
+    ([] $attrs:tt $delegates:tt $callback:path { $($args:tt)* }) => {
+        $callback! { @rule $attrs $delegates $($args)* }
+    };
+    (
+        [#[delegates($($In:ty => $Out:ty),* $(,)?)] $($rest:tt)*]
+        $attrs:tt [$($delegates:tt)*] $callback:path { $($args:tt)* }
+    ) => {
+        $crate::__codemaker_rule_attrs! {
+            [$($rest)*] $attrs [$($delegates)* $(($In, $Out))*] $callback { $($args)* }
+        }
+    };
+    (
+        [#[$($attr:tt)+] $($rest:tt)*]
+        [$($attrs:tt)*] $delegates:tt $callback:path { $($args:tt)* }
+    ) => {
+        $crate::__codemaker_rule_attrs! {
+            [$($rest)*] [$($attrs)* #[$($attr)+]] $delegates $callback { $($args)* }
+        }
+    };
+}
+
+/// Internal helper for registering a [`RuleInfo`] for a generated rule.
+///
+/// This defines a static `__CODEMAKER_RULE` describing the rule, and submits it to the
+/// registry used by [`RuleGraph`]. The doc comment is pieced together from any `#[doc]`
+/// attributes among the given attributes. It's intended to be expanded at item level,
+/// alongside the impl of the rule.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_register_rule {
+    ($CM:ty, $In:ty, $Out:ty, [$($attrs:tt)*] [$(($DIn:ty, $DOut:ty))*]) => {
+        fn __codemaker_maker_type() -> &'static str {
+            ::std::any::type_name::<$CM>()
+        }
+        static __CODEMAKER_RULE: $crate::RuleInfo = $crate::RuleInfo::new(
+            __codemaker_maker_type,
+            stringify!($In),
+            stringify!($Out),
+            $crate::__codemaker_rule_doc!([] $($attrs)*),
+            &[$((stringify!($DIn), stringify!($DOut))),*],
+        );
+        $crate::__private::inventory::submit! {
+            $crate::__private::RegisteredRule(&__CODEMAKER_RULE)
+        }
+    };
+}
+
+/// Internal helper for opening a `tracing` span for the duration of a rule.
+///
+/// This is defined differently depending on whether our `tracing` feature is enabled,
+/// so that it's the features of this crate that matter rather than those of the crate
+/// in which the rules are being defined.
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_rule_span {
+    ($In:ty, $Out:ty) => {
+        let __codemaker_span = $crate::__private::tracing::trace_span!(
+            target: "codemaker::rules",
+            concat!(stringify!($In), " => ", stringify!($Out))
+        )
+        .entered();
+    };
+}
+
+/// Internal helper for opening a `tracing` span for the duration of a rule.
+///
+/// Our `tracing` feature is not enabled, so this does nothing.
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_rule_span {
+    ($In:ty, $Out:ty) => {};
+}
+
+/// Internal helper for concatenating the `#[doc]` attributes from a list of attributes.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_rule_doc {
+    ([$($doc:expr)*]) => {
+        concat!($($doc, "\n",)*)
+    };
+    ([$($doc:expr)*] #[doc = $next:expr] $($rest:tt)*) => {
+        $crate::__codemaker_rule_doc!([$($doc)* $next] $($rest)*)
+    };
+    ($docs:tt #[$($_attr:tt)*] $($rest:tt)*) => {
+        $crate::__codemaker_rule_doc!($docs $($rest)*)
+    };
+}
+
+/// Macro for defining a suite of [`CodeMakerRule`] implementations that can be inherited.
+///
+/// Sometimes you want several variants of a maker that differ in only a handful of rules,
+/// such as a "debug" variant that adds logging to each generated function. This macro
+/// accepts the same rule syntax as [`define_codemaker_rules!`] and defines the rules on the
+/// given type in exactly the same way, but it also defines a new macro with the given name
+/// that can be used to copy those rules onto another type, like so:
+///
+/// ```ignore
+/// define_inheritable_codemaker_rules!{
+///     my_rules for MyCodeMaker as self {
+///         InputType1 as input => OutputType1 {
+///             self.make_from_iter(input.items)
+///         }
+///         InputType2 as input => OutputType2 {
+///             self.do_the_making(input)
+///         }
+///     }
+/// }
+///
+/// my_rules!{
+///     MyDebugCodeMaker as self {
+///         // Overrides the rule from `MyCodeMaker`.
+///         InputType2 as input => OutputType2 {
+///             self.do_the_making_with_logging(input)
+///         }
+///     }
+/// }
+/// ```
+///
+/// The child type gets its own copy of every inherited rule, except for those where it
+/// provides an override for the same `InputType`/`OutputType` pair. Because the inherited
+/// rules are copied rather than delegated, any nested `self.make_from(...)` calls in their
+/// bodies will dispatch through the child type, and hence through its overrides. If the
+/// inherited rules refer to fields of the parent type, the child type will need to make
+/// them available, for example by implementing `Deref<Target = MyCodeMaker>`.
+///
+/// There are some limitations that come from doing this with declarative macros:
+///
+///  * Overrides are matched on the tokens of the input and output types, so they
+///    must be spelled the same way in both places (e.g. `py::Module` in both, not
+///    `py::Module` in one and `codemaker_python::Module` in the other).
+///  * Paths in the inherited rule bodies are resolved where the child rules are defined,
+///    so any names used by the parent rules must also be in scope there.
+///  * The rule bodies can't contain a literal `$` token, since they end up inside
+///    the definition of the generated macro.
+///
+/// The generated macro is an ordinary `macro_rules!` macro, so you can put attributes like
+/// `#[macro_export]` before its name in order to use it from other modules or crates.
+#[macro_export]
+macro_rules! define_inheritable_codemaker_rules {
+    ($(#[$($mattr:tt)+])* $name:ident for $CM:ty as $self:ident {
+        $($rules:tt)*
+    }) => {
+        $crate::define_codemaker_rules! { $CM as $self { $($rules)* } }
+        $crate::__codemaker_inheritable! { ($) $(#[$($mattr)+])* $name $self { $($rules)* } }
+    };
+}
+
+/// Internal helper for [`define_inheritable_codemaker_rules!`].
+///
+/// We need a literal `$` token in order to define metavariables in the generated
+/// macro, and the only way to get one is to have it passed in as an argument.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_inheritable {
+    (($d:tt) $(#[$($mattr:tt)+])* $name:ident $self:ident { $($rules:tt)* }) => {
+        $(#[$($mattr)+])*
+        macro_rules! $name {
+            ($d child:ty as $d child_self:ident { $d($d overrides:tt)* }) => {
+                $crate::__codemaker_inherit! {
+                    $d child as $d child_self { $d($d overrides)* } from $self { $($rules)* }
+                }
+            };
+        }
+    };
+}
+
+/// Internal helper for [`define_inheritable_codemaker_rules!`].
+///
+/// This defines the overriding rules on the child type, then copies over each of the
+/// parent rules whose input/output types do not match one of the overrides. The trick
+/// for comparing types is to generate a little local macro that has an arm matching the
+/// exact tokens of each override, and feed it the tokens of each parent rule in turn.
+/// The whole thing happens inside an anonymous `const` block so that said little local
+/// macro can't collide with any others.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_inherit {
+    ($child:ty as $child_self:ident { $($overrides:tt)* } from $self:ident { $($parent:tt)* }) => {
+        $crate::define_codemaker_rules! { $child as $child_self { $($overrides)* } }
+        const _: () = {
+            $crate::__codemaker_split_rules! {
+                [@overrides ($) [$child] [$self] [$($parent)*]] [] $($overrides)*
+            }
+        };
+    };
+    (@overrides ($d:tt) [$child:ty] [$self:ident] [$($parent:tt)*] [$(
+        { [$($in:tt)*] [$($out:tt)*] $($_override:tt)* }
+    )*]) => {
+        macro_rules! __codemaker_inherited_rule {
+            $(
+                ($d _child:ty, $d _self:ident, [$($in)*] [$($out)*] $d($d rule:tt)*) => {};
+            )*
+            ($d child:ty, $d self:ident, [$d($d in:tt)*] [$d($d out:tt)*] $d($d rule:tt)*) => {
+                $crate::define_codemaker_rules! { $d child as $d self { $d($d rule)* } }
+            };
+        }
+        $crate::__codemaker_split_rules! { [@parent [$child] [$self]] [] $($parent)* }
+    };
+    (@parent [$child:ty] [$self:ident] [$(
+        { [$($in:tt)*] [$($out:tt)*] [$($rule:tt)*] }
+    )*]) => {
+        $(
+            __codemaker_inherited_rule! { $child, $self, [$($in)*] [$($out)*] $($rule)* }
         )*
     };
 }
 
+/// Internal helper for splitting a list of rules into their component tokens.
+///
+/// This munches through the tokens of a list of rules in [`define_codemaker_rules!`]
+/// syntax, and splits each one into a record of the form:
+///
+/// ```ignore
+/// { [InputType] [OutputType] [all the tokens of the rule] }
+/// ```
+///
+/// Then passes the list of records to [`__codemaker_inherit!`] with the given prefix
+/// tokens. This lets us compare the input and output types of different rules as plain
+/// token trees, which we can't do once they've been parsed as `$In:ty`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __codemaker_split_rules {
+    // Collect leading attributes.
+    (@rule $prefix:tt $done:tt [$($rule:tt)*] # [$($attr:tt)*] $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @rule $prefix $done [$($rule)* #[$($attr)*]] $($rest)* }
+    };
+    (@rule $prefix:tt $done:tt [$($rule:tt)*] match $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @match $prefix $done [$($rule)* match] [] $($rest)* }
+    };
+    (@rule $prefix:tt $done:tt $rule:tt $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @in $prefix $done $rule [] $($rest)* }
+    };
+    // Collect the input type of an enum rule, up to the `=>` or the `as` before its name.
+    (@match $prefix:tt $done:tt [$($rule:tt)*] $in:tt => $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @out $prefix $done [$($rule)* =>] $in [] $($rest)* }
+    };
+    (@match $prefix:tt $done:tt [$($rule:tt)*] $in:tt as $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @input $prefix $done [$($rule)* as] $in $($rest)* }
+    };
+    (@match $prefix:tt $done:tt [$($rule:tt)*] [$($in:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @match $prefix $done [$($rule)* $next] [$($in)* $next] $($rest)* }
+    };
+    // Collect the input type of an ordinary rule, up to the `as`.
+    (@in $prefix:tt $done:tt [$($rule:tt)*] $in:tt as $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @input $prefix $done [$($rule)* as] $in $($rest)* }
+    };
+    (@in $prefix:tt $done:tt [$($rule:tt)*] [$($in:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @in $prefix $done [$($rule)* $next] [$($in)* $next] $($rest)* }
+    };
+    // Skip over the input pattern, up to the `=>`.
+    (@input $prefix:tt $done:tt [$($rule:tt)*] $in:tt => $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @out $prefix $done [$($rule)* =>] $in [] $($rest)* }
+    };
+    (@input $prefix:tt $done:tt [$($rule:tt)*] $in:tt $next:tt $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @input $prefix $done [$($rule)* $next] $in $($rest)* }
+    };
+    // Collect the output type, up to the body block.
+    (@out $prefix:tt [$($done:tt)*] [$($rule:tt)*] $in:tt $out:tt { $($body:tt)* } $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { $prefix [$($done)* { $in $out [$($rule)* { $($body)* }] }] $($rest)* }
+    };
+    (@out $prefix:tt $done:tt [$($rule:tt)*] $in:tt [$($out:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__codemaker_split_rules! { @out $prefix $done [$($rule)* $next] $in [$($out)* $next] $($rest)* }
+    };
+    // Base case, hand the results back.
+    ([$($prefix:tt)*] $done:tt) => {
+        $crate::__codemaker_inherit! { $($prefix)* $done }
+    };
+    // Start of the next rule.
+    ([$($prefix:tt)*] $done:tt $($rest:tt)+) => {
+        $crate::__codemaker_split_rules! { @rule [$($prefix)*] $done [] $($rest)+ }
+    };
+}
+
 /// An individual rule for statelessly making code by structural matching.
 ///
 /// Much like the [`CodeMakerRule`] trait, each impl of [`StatelessCodeMakerRule`]
@@ -560,6 +1230,7 @@ pub trait StatelessCodeMakerRule<Input, Output> {
     {
         StatelessCodeMakerRuleMap {
             iter: input.into_iter(),
+            index: 0,
             phantom: std::marker::PhantomData,
         }
     }
@@ -577,6 +1248,7 @@ where
     I: Iterator<Item = Input>,
 {
     iter: I,
+    index: usize,
     // Not sure why this is needed, but Rust complains at me
     // about `Output` and `T` being unconstrained if I dont have it...
     phantom: std::marker::PhantomData<(Output, &'static T)>,
@@ -589,7 +1261,10 @@ where
 {
     type Item = Output;
     fn next(&mut self) -> Option<Output> {
-        self.iter.next().map(|i| T::make_from(i))
+        let input = self.iter.next()?;
+        let _guard = __private::enter_item(self.index);
+        self.index += 1;
+        Some(T::make_from(input))
     }
 }
 
@@ -652,9 +1327,29 @@ macro_rules! define_stateless_codemaker {
 /// ```
 ///
 /// Into a suite of [`StatelessCodeMakerRule`] implementations on that type, one for each of
-/// the provided `InputType`/`OutputType` pairs.
+/// the provided `InputType`/`OutputType` pairs. Like [`define_codemaker_rules!`], the generated
+/// rules also support provenance tracking, introspection and `tracing` spans.
 #[macro_export]
 macro_rules! define_stateless_codemaker_rules {
+    // A single rule, called back from `__codemaker_rule_attrs!`.
+    (@rule [$(#[$($attr:tt)+])*] $delegates:tt $CM:ty, $In:ty as $input:pat => $Out:ty $body:block) => {
+        $(#[$($attr)+])*
+        const _: () = {
+            $crate::__codemaker_register_rule! { $CM, $In, $Out, [$(#[$($attr)+])*] $delegates }
+            impl $crate::StatelessCodeMakerRule<$In, $Out> for $CM {
+                fn make_from($input: $In) -> $Out {
+                    let _provenance = $crate::__private::enter_rule(&__CODEMAKER_RULE);
+                    $crate::__codemaker_rule_span!($In, $Out);
+                    // Let the method body use any of our traits.
+                    // This seems unhygienic, but works, and is almost
+                    // certainly what the consumer wants.
+                    #[allow(unused_imports)]
+                    use $crate::traits::*;
+                    $body
+                }
+            }
+        };
+    };
     // Base case.
     ($CM:ty { }) => {};
     // Fiddly quoting macro syntax
@@ -671,16 +1366,8 @@ macro_rules! define_stateless_codemaker_rules {
     ($CM:ty {
         $(#[$($attr:tt)+])* $In:ty as $input:pat => $Out:ty $body:block $($tail:tt)*
     }) => {
-        $(#[$($attr)+])*
-        impl $crate::StatelessCodeMakerRule<$In, $Out> for $CM {
-            fn make_from($input: $In) -> $Out {
-                // Let the method body use any of our traits.
-                // This seems unhygienic, but works, and is almost
-                // certainly what the consumer wants.
-                #[allow(unused_imports)]
-                use $crate::traits::*;
-                $body
-            }
+        $crate::__codemaker_rule_attrs! {
+            [$(#[$($attr)+])*] [] [] $crate::define_stateless_codemaker_rules { $CM, $In as $input => $Out $body }
         }
         $crate::define_stateless_codemaker_rules! { $CM { $($tail)* } }
     };
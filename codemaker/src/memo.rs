@@ -0,0 +1,169 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Helpers for memoizing the results of [`CodeMakerRule`](crate::CodeMakerRule)s.
+//!
+//! When the input data structure is a DAG rather than a tree, the same node may be
+//! reachable from many different places, and naively calling `make_from` on each
+//! reference will do the work multiple times and may emit duplicate definitions.
+//! The types in this module can be stored in a maker and used from its rule bodies
+//! to make sure that each shared node is only processed once:
+//!
+//! ```ignore
+//! struct MyCodeMaker {
+//!     names: MemoCache<ByAddress, String>,
+//!     defined: EmitOnce<String>,
+//! }
+//!
+//! define_codemaker_rules!{
+//!     MyCodeMaker as self {
+//!         // Computing the name is expensive, so only do it once per input node.
+//!         &TypeDef as input => String {
+//!             self.names.get_or_make(ByAddress::of(input), || expensive_name_for(input))
+//!         }
+//!         // Only emit a definition the first time we see each name.
+//!         &TypeDef as input => Option<py::Statement> {
+//!             let name: String = self.make_from(input);
+//!             self.defined.emit_once(name, || make_the_definition(input))
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Nodes can be keyed by any `Hash + Eq` value that the rule provides, or by the
+//! identity of a borrowed input using the [`ByAddress`] wrapper.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// A cache of rule outputs, keyed by some property of the input.
+///
+/// Rules take `&self`, so this uses interior mutability to let rule bodies update
+/// the cache. It's not thread-safe, but then again neither is anything else in
+/// here at the moment.
+pub struct MemoCache<K, V> {
+    entries: RefCell<HashMap<K, V>>,
+}
+
+impl<K: Hash + Eq, V: Clone> MemoCache<K, V> {
+    pub fn new() -> Self {
+        MemoCache {
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Get the cached output for the given key, or make and cache it if not present.
+    ///
+    /// The cache is not borrowed while `make` is running, so it's safe for `make` to
+    /// recursively use the same cache for other keys. It's *not* safe for it to
+    /// recursively use the same key, which will just recurse forever; if you have
+    /// cycles in your input then you may need [`EmitOnce`] instead.
+    pub fn get_or_make<F: FnOnce() -> V>(&self, key: K, make: F) -> V {
+        if let Some(value) = self.entries.borrow().get(&key) {
+            return value.clone();
+        }
+        let value = make();
+        self.entries.borrow_mut().insert(key, value.clone());
+        value
+    }
+
+    /// Get the cached output for the given key, if present.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.entries.borrow().get(key).cloned()
+    }
+
+    /// Check whether an output has been cached for the given key.
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.borrow().contains_key(key)
+    }
+
+    /// Discard all cached outputs, e.g. before re-using a maker on new input.
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear()
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> Default for MemoCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A record of which input nodes have already had their output emitted.
+///
+/// This is for rules where the output should appear exactly once no matter how many
+/// times the input is referenced, such as the definition of a shared type. The first
+/// call to [`EmitOnce::emit_once`] for a given key makes the output, and subsequent
+/// calls return `None` so that later references can see it was already emitted.
+pub struct EmitOnce<K> {
+    emitted: RefCell<HashSet<K>>,
+}
+
+impl<K: Hash + Eq> EmitOnce<K> {
+    pub fn new() -> Self {
+        EmitOnce {
+            emitted: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// Make the output for the given key if it hasn't already been emitted.
+    ///
+    /// The key is recorded as emitted *before* calling `make`, so if the input contains
+    /// cycles then any recursive references back to the same key will return `None`
+    /// rather than recursing forever.
+    pub fn emit_once<V, F: FnOnce() -> V>(&self, key: K, make: F) -> Option<V> {
+        if !self.emitted.borrow_mut().insert(key) {
+            return None;
+        }
+        Some(make())
+    }
+
+    /// Check whether the output for the given key has already been emitted.
+    pub fn was_emitted(&self, key: &K) -> bool {
+        self.emitted.borrow().contains(key)
+    }
+
+    /// Forget about everything that has been emitted, e.g. before re-using a maker on new input.
+    pub fn clear(&self) {
+        self.emitted.borrow_mut().clear()
+    }
+}
+
+impl<K: Hash + Eq> Default for EmitOnce<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A memoization key based on the identity of a borrowed value.
+///
+/// Two `ByAddress` keys are equal if they were made from references to the same object
+/// in memory, regardless of whether the objects they refer to are equal. This is useful
+/// when the input is a DAG of borrowed nodes that don't have any natural key.
+///
+/// The key doesn't borrow the value, so it's easy to store in a maker, but that also
+/// means it's up to you to ensure that the input stays alive (and hence its addresses
+/// stay unique) for as long as the keys are in use. Zero-sized values don't have a
+/// unique address and shouldn't be used with this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ByAddress(usize);
+
+impl ByAddress {
+    pub fn of<T: ?Sized>(value: &T) -> Self {
+        ByAddress(value as *const T as *const u8 as usize)
+    }
+}
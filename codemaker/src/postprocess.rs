@@ -0,0 +1,195 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Post-processing output files after they've been rendered, for example with an external formatter.
+//!
+//! Teams often have a formatter configuration that they'd like generated code to follow, and
+//! the easiest way to do that is to run the formatter over it. A [`PostProcess`] pipeline maps
+//! file extensions to a series of steps, each of which is either an external command that
+//! reads the file on stdin and writes the processed file to stdout, or a text transform
+//! implemented in Rust:
+//!
+//! ```ignore
+//! let post = PostProcess::new()
+//!     .command("py", "black", &["--quiet", "--stdin-filename", "{path}", "-"])
+//!     .command("rs", "rustfmt", &["--edition", "2018"])
+//!     .transform("py", |_path, text| Ok(text.replace("\t", "    ")));
+//! let failures = output.write_into_dir_with_post_process("generated", &options, &post)?;
+//! ```
+//!
+//! Since the steps work on the contents of each file rather than on the file on disk, the
+//! same pipeline can be used to check that existing output is up-to-date.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+
+type TransformFn = dyn Fn(&Path, &str) -> Result<String, String>;
+
+#[derive(Clone)]
+enum Step {
+    Command { program: String, args: Vec<String> },
+    Transform(Rc<TransformFn>),
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        match self {
+            Step::Command { program, .. } => program.clone(),
+            Step::Transform(_) => "transform".into(),
+        }
+    }
+
+    fn run(&self, path: &Path, contents: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self {
+            Step::Command { program, args } => {
+                let path = path.to_string_lossy();
+                let args = args.iter().map(|arg| arg.replace("{path}", &path));
+                run_command(program, args, contents)
+            }
+            Step::Transform(transform) => {
+                let text = String::from_utf8(contents).map_err(|_| "file is not valid UTF-8".to_string())?;
+                transform(path, &text).map(String::into_bytes)
+            }
+        }
+    }
+}
+
+/// A pipeline of steps for post-processing output files, selected by file extension.
+#[derive(Clone, Default)]
+pub struct PostProcess {
+    steps: Vec<(String, Step)>,
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pipe files with the given extension through an external command.
+    ///
+    /// The command is given the file contents on stdin and must write the processed contents
+    /// to stdout. Any `{path}` in the arguments is replaced with the path of the output file,
+    /// relative to the output directory, which is handy for formatters that look for
+    /// configuration based on the file path. An extension of `*` matches every file.
+    pub fn command<E: Into<String>, P: Into<String>>(mut self, extension: E, program: P, args: &[&str]) -> Self {
+        self.steps.push((
+            extension.into(),
+            Step::Command {
+                program: program.into(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+            },
+        ));
+        self
+    }
+
+    /// Transform the text of files with the given extension, reporting failure as an error message.
+    pub fn transform<E, F>(mut self, extension: E, transform: F) -> Self
+    where
+        E: Into<String>,
+        F: Fn(&Path, &str) -> Result<String, String> + 'static,
+    {
+        self.steps.push((extension.into(), Step::Transform(Rc::new(transform))));
+        self
+    }
+
+    /// Check whether there are no steps in the pipeline.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Run each matching step of the pipeline over the contents of a file, in the order they were added.
+    pub fn process(&self, path: &Path, mut contents: Vec<u8>) -> Result<Vec<u8>, PostProcessError> {
+        let extension = path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+        for (selector, step) in &self.steps {
+            if selector != "*" && *selector != extension {
+                continue;
+            }
+            contents = step.run(path, contents).map_err(|message| PostProcessError {
+                path: path.to_path_buf(),
+                step: step.describe(),
+                message,
+            })?;
+        }
+        Ok(contents)
+    }
+}
+
+impl std::fmt::Debug for PostProcess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let steps: Vec<_> = self.steps.iter().map(|(ext, step)| (ext, step.describe())).collect();
+        f.debug_struct("PostProcess").field("steps", &steps).finish()
+    }
+}
+
+fn run_command<I: Iterator<Item = String>>(program: &str, args: I, contents: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run {}: {}", program, e))?;
+    // Feed stdin from another thread, so that a large file can't fill up the output pipes and deadlock.
+    let mut stdin = child.stdin.take().expect("stdin should be piped");
+    let feeder = std::thread::spawn(move || stdin.write_all(&contents));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("could not run {}: {}", program, e))?;
+    let fed = feeder.join().expect("writing to the command should not panic");
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => format!("exited with {}", output.status),
+            stderr => format!("exited with {}: {}", output.status, stderr),
+        });
+    }
+    fed.map_err(|e| format!("could not write to {}: {}", program, e))?;
+    Ok(output.stdout)
+}
+
+/// A failure to post-process an individual output file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostProcessError {
+    path: PathBuf,
+    step: String,
+    message: String,
+}
+
+impl PostProcessError {
+    /// The path of the output file, relative to the output directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The name of the command that failed, or `transform` for a failed text transform.
+    pub fn step(&self) -> &str {
+        &self.step
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for PostProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} failed: {}", self.path.display(), self.step, self.message)
+    }
+}
+
+impl std::error::Error for PostProcessError {}
@@ -0,0 +1,401 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Tracking which rule made each fragment of the output.
+//!
+//! When some generated code looks wrong, it can be hard to figure out which of the many
+//! rules was responsible for it. As an opt-in debugging aid, the rules generated by
+//! [`define_codemaker_rules!`](crate::define_codemaker_rules) keep track of which rule
+//! is currently running and how it was reached from the top-level input. Target crates
+//! can capture this as an [`Origin`] when constructing each node of their output, and
+//! then report it as a [`SourceMap`] when rendering:
+//!
+//! ```ignore
+//! let module = codemaker::with_provenance(|| maker.make(input));
+//! let mut f = std::fs::File::create("output.py")?;
+//! let map = module.write_into_with_source_map(&mut f, &RenderOptions::default())?;
+//! for entry in map.entries() {
+//!     println!("lines {}-{}: {}", entry.start_line(), entry.end_line(), entry.origin());
+//! }
+//! ```
+//!
+//! Tracking is off by default, in which case the overhead is a couple of checks of
+//! thread-local flags on each rule invocation.
+
+use std::cell::{Cell, RefCell};
+
+use crate::coverage;
+use crate::graph::{self, RuleInfo};
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+enum Frame {
+    Rule(&'static RuleInfo),
+    Item(usize),
+}
+
+/// Run a function with provenance tracking enabled on the current thread.
+///
+/// Any output nodes constructed while the function is running will be able to
+/// capture their [`Origin`] using [`Origin::current`].
+pub fn with_provenance<T, F: FnOnce() -> T>(func: F) -> T {
+    let previous = ENABLED.with(|enabled| enabled.replace(true));
+    // Restore the previous state even if `func` panics.
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            ENABLED.with(|enabled| enabled.set(self.0));
+        }
+    }
+    let _restore = Restore(previous);
+    func()
+}
+
+/// Check whether provenance tracking is enabled on the current thread.
+pub fn provenance_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Where a fragment of the output came from.
+///
+/// This identifies the rule that was running when the fragment was made, by its input
+/// and output types as written in the rule definition, along with the path through the
+/// input by which that rule was reached. The path lists the input type of each enclosing
+/// rule, with the position of the item for rules called via `make_from_iter`, so it will
+/// look something like `StatusCodes[3] > (String, u32)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    input_type: &'static str,
+    output_type: &'static str,
+    input_path: String,
+}
+
+impl Origin {
+    /// The origin of any output made right now, if provenance tracking is enabled.
+    ///
+    /// Target crates should call this when constructing each node of their output, and
+    /// store the result for use when rendering. Returns `None` if tracking is not enabled
+    /// or if no rule is currently running.
+    pub fn current() -> Option<Origin> {
+        if !provenance_enabled() {
+            return None;
+        }
+        STACK.with(|stack| {
+            let stack = stack.borrow();
+            let mut input_path = String::new();
+            let mut rule = None;
+            for frame in stack.iter() {
+                match frame {
+                    Frame::Rule(info) => {
+                        if !input_path.is_empty() {
+                            input_path.push_str(" > ");
+                        }
+                        input_path.push_str(info.input_type());
+                        rule = Some((info.input_type(), info.output_type()));
+                    }
+                    Frame::Item(index) => input_path.push_str(&format!("[{}]", index)),
+                }
+            }
+            rule.map(|(input_type, output_type)| Origin {
+                input_type,
+                output_type,
+                input_path,
+            })
+        })
+    }
+
+    /// The input type of the rule, as written in its definition.
+    pub fn input_type(&self) -> &str {
+        self.input_type
+    }
+
+    /// The output type of the rule, as written in its definition.
+    pub fn output_type(&self) -> &str {
+        self.output_type
+    }
+
+    /// The path through the input by which the rule was reached.
+    pub fn input_path(&self) -> &str {
+        &self.input_path
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} => {} (at {})",
+            self.input_type, self.output_type, self.input_path
+        )
+    }
+}
+
+/// Guard for an entry on the provenance stack, which is popped when dropped.
+#[doc(hidden)]
+pub struct ProvenanceGuard {
+    pushed: bool,
+}
+
+impl Drop for ProvenanceGuard {
+    fn drop(&mut self) {
+        if self.pushed {
+            STACK.with(|stack| stack.borrow_mut().pop());
+        }
+    }
+}
+
+fn push_frame(frame: Frame) -> ProvenanceGuard {
+    STACK.with(|stack| stack.borrow_mut().push(frame));
+    ProvenanceGuard { pushed: true }
+}
+
+/// Record that a rule is running, for the duration of the returned guard.
+///
+/// This is called from the rules generated by our macros, and isn't intended
+/// to be called directly.
+#[doc(hidden)]
+pub fn enter_rule(rule: &'static RuleInfo) -> ProvenanceGuard {
+    if !tracking() {
+        return ProvenanceGuard { pushed: false };
+    }
+    STACK.with(|stack| {
+        let stack = stack.borrow();
+        if graph::recording() {
+            let caller = stack.iter().rev().find_map(|frame| match frame {
+                Frame::Rule(info) => Some(*info),
+                Frame::Item(_) => None,
+            });
+            graph::record_call(caller, rule);
+        }
+        if coverage::recording() {
+            coverage::record_call(rule, matches!(stack.last(), Some(Frame::Item(_))));
+        }
+    });
+    push_frame(Frame::Rule(rule))
+}
+
+/// Record that the given item of an iterator is being made, for the duration of the returned guard.
+#[doc(hidden)]
+pub fn enter_item(index: usize) -> ProvenanceGuard {
+    if !tracking() {
+        return ProvenanceGuard { pushed: false };
+    }
+    push_frame(Frame::Item(index))
+}
+
+/// Check whether anything is interested in the stack of running rules.
+fn tracking() -> bool {
+    provenance_enabled() || graph::recording() || coverage::recording()
+}
+
+/// A map from ranges of lines in a rendered file to the [`Origin`] of the code on those lines.
+///
+/// Ranges may be nested, for example if a function definition was made by one rule and
+/// the statements in its body by another. Entries are listed in the order in which
+/// they started, so outer ranges come before any ranges nested within them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    entries: Vec<SourceMapEntry>,
+}
+
+/// An individual entry in a [`SourceMap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapEntry {
+    start_line: usize,
+    end_line: usize,
+    origin: Origin,
+}
+
+impl SourceMapEntry {
+    /// The first line of the range, counting from 1.
+    pub fn start_line(&self) -> usize {
+        self.start_line
+    }
+
+    /// The last line of the range, inclusive.
+    pub fn end_line(&self) -> usize {
+        self.end_line
+    }
+
+    pub fn origin(&self) -> &Origin {
+        &self.origin
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { entries: vec![] }
+    }
+
+    pub fn entries(&self) -> &[SourceMapEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Find the innermost entry covering the given line, if any.
+    pub fn find_line(&self, line: usize) -> Option<&SourceMapEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.start_line <= line && line <= e.end_line)
+    }
+
+    /// Render the source map as JSON, for writing to a sidecar file.
+    ///
+    /// The output looks like this, with the `file` field naming the rendered file:
+    ///
+    /// ```json
+    /// {"file": "pkg/mod.py", "mappings": [
+    ///   {"lines": [1, 3], "input_type": "StatusCodes", "output_type": "py::Module", "input_path": "StatusCodes"}
+    /// ]}
+    /// ```
+    pub fn to_json(&self, file: &std::path::Path) -> String {
+        let mut json = format!(
+            "{{\"file\": {}, \"mappings\": [",
+            json_string(&file.to_string_lossy())
+        );
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!(
+                "\n  {{\"lines\": [{}, {}], \"input_type\": {}, \"output_type\": {}, \"input_path\": {}}}",
+                entry.start_line,
+                entry.end_line,
+                json_string(entry.origin.input_type),
+                json_string(entry.origin.output_type),
+                json_string(&entry.origin.input_path),
+            ));
+        }
+        json.push_str("\n]}\n");
+        json
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A writer that keeps track of line numbers, for building a [`SourceMap`].
+///
+/// Target crates can render their output through one of these, wrapping the code for
+/// each node in a call to [`SourceWriter::track`] in order to record which lines it
+/// was written on. If the writer was created without a source map then tracking is
+/// a no-op, so the same rendering code can be used in both cases.
+pub struct SourceWriter<'w, W: std::io::Write> {
+    inner: &'w mut W,
+    line: usize,
+    at_line_start: bool,
+    map: Option<SourceMap>,
+}
+
+impl<'w, W: std::io::Write> SourceWriter<'w, W> {
+    /// Wrap a writer, without recording a source map.
+    pub fn new(inner: &'w mut W) -> Self {
+        SourceWriter {
+            inner,
+            line: 1,
+            at_line_start: true,
+            map: None,
+        }
+    }
+
+    /// Wrap a writer, recording a source map.
+    pub fn with_source_map(inner: &'w mut W) -> Self {
+        SourceWriter {
+            map: Some(SourceMap::new()),
+            ..Self::new(inner)
+        }
+    }
+
+    /// The line that will be written to next, counting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Record the lines written by the given function as having come from the given origin.
+    pub fn track<F>(&mut self, origin: Option<&Origin>, func: F) -> std::io::Result<()>
+    where
+        F: FnOnce(&mut Self) -> std::io::Result<()>,
+    {
+        let start_line = self.line;
+        // Add our entry before running `func`, so that outer entries come first.
+        let index = match (origin, &mut self.map) {
+            (Some(origin), Some(map)) => {
+                map.entries.push(SourceMapEntry {
+                    start_line,
+                    end_line: start_line,
+                    origin: origin.clone(),
+                });
+                map.entries.len() - 1
+            }
+            _ => return func(self),
+        };
+        func(self)?;
+        let end_line = if self.at_line_start {
+            self.line - 1
+        } else {
+            self.line
+        };
+        let map = self.map.as_mut().expect("checked above");
+        if end_line < start_line {
+            // Nothing was written, so there's nothing to map.
+            map.entries.remove(index);
+        } else {
+            map.entries[index].end_line = end_line;
+        }
+        Ok(())
+    }
+
+    /// Finish writing, and get the recorded source map (which is empty if not recording).
+    pub fn into_source_map(self) -> SourceMap {
+        self.map.unwrap_or_default()
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for SourceWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        let written = &buf[..n];
+        self.line += written.iter().filter(|b| **b == b'\n').count();
+        if let Some(last) = written.last() {
+            self.at_line_start = *last == b'\n';
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
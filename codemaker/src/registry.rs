@@ -0,0 +1,171 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Choosing between sets of rules at runtime.
+//!
+//! Rules are ordinarily resolved statically through trait impls, which is great for
+//! catching mistakes but means that choosing a different output style requires a
+//! recompile. A [`RuleRegistry`] maps names to boxed `dyn` rules, so that a maker can
+//! pick between them based on configuration. Each named rule set is typically its own
+//! little maker type, and the registry holds them as trait objects:
+//!
+//! ```ignore
+//! struct GlobalsStyle;
+//! struct EnumStyle { class_name: String }
+//! define_codemaker_rules!{ GlobalsStyle as self { ... } }
+//! define_codemaker_rules!{ EnumStyle as self { ... } }
+//!
+//! type ConstantsStyles = RuleRegistry<dyn for<'a> CodeMakerRule<&'a Constants, py::Module>>;
+//!
+//! struct MyCodeMaker {
+//!     constants: ConstantsStyles,
+//!     constants_style: String,
+//! }
+//!
+//! let maker = MyCodeMaker {
+//!     // Rust can't infer the trait object type from the first rule set added, so it
+//!     // needs to be given explicitly, here via a type alias.
+//!     constants: ConstantsStyles::new()
+//!         .with_rule_set("globals", Box::new(GlobalsStyle))
+//!         .with_rule_set("enum", Box::new(EnumStyle { class_name: "Status".into() })),
+//!     constants_style: config.constants_style,
+//! };
+//!
+//! define_codemaker_rules!{
+//!     MyCodeMaker as self {
+//!         &Constants as input => py::Module {
+//!             self.constants.get(&self.constants_style).unwrap().make_from(input)
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! The registry is generic over the type of trait object it holds, so if each rule set needs
+//! to provide rules for several different input and output types, you can define a trait with
+//! each of the required [`CodeMakerRule`](crate::CodeMakerRule)s as supertraits and store
+//! trait objects of that instead.
+
+use std::collections::BTreeMap;
+
+/// A registry of named rule sets, for choosing between them at runtime.
+///
+/// The type parameter `R` is the type of trait object to store, usually something like
+/// `dyn CodeMakerRule<Input, Output>`. For borrowed inputs you'll probably want a
+/// higher-ranked trait object like `dyn for<'a> CodeMakerRule<&'a Input, Output>`,
+/// so that the registry isn't tied to the lifetime of any particular input.
+pub struct RuleRegistry<R: ?Sized> {
+    rule_sets: BTreeMap<String, Box<R>>,
+}
+
+impl<R: ?Sized> RuleRegistry<R> {
+    pub fn new() -> Self {
+        RuleRegistry {
+            rule_sets: BTreeMap::new(),
+        }
+    }
+
+    /// Register a rule set under the given name, replacing any existing set with that name.
+    pub fn insert<N: Into<String>>(&mut self, name: N, rules: Box<R>) {
+        self.rule_sets.insert(name.into(), rules);
+    }
+
+    /// Fluently register a rule set under the given name.
+    pub fn with_rule_set<N: Into<String>>(mut self, name: N, rules: Box<R>) -> Self {
+        self.insert(name, rules);
+        self
+    }
+
+    /// Get the rule set with the given name.
+    ///
+    /// The name will often come from a config file, so the error lists the available
+    /// rule sets in order to help the user fix it.
+    pub fn get(&self, name: &str) -> Result<&R, UnknownRuleSet> {
+        self.rule_sets
+            .get(name)
+            .map(|rules| &**rules)
+            .ok_or_else(|| UnknownRuleSet {
+                name: name.to_string(),
+                available: self.names().into_iter().map(String::from).collect(),
+            })
+    }
+
+    /// Check whether a rule set with the given name has been registered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.rule_sets.contains_key(name)
+    }
+
+    /// The names of all registered rule sets, in sorted order.
+    pub fn names(&self) -> Vec<&str> {
+        self.rule_sets.keys().map(String::as_str).collect()
+    }
+}
+
+impl<R: ?Sized> Default for RuleRegistry<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An error from looking up a rule set that hasn't been registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownRuleSet {
+    name: String,
+    available: Vec<String>,
+}
+
+impl UnknownRuleSet {
+    /// The name that was looked up.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The names of the rule sets that are available.
+    pub fn available(&self) -> &[String] {
+        &self.available
+    }
+}
+
+impl std::fmt::Display for UnknownRuleSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown rule set {:?}, expected one of: {}",
+            self.name,
+            self.available.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownRuleSet {}
+
+/// A [`CodeMakerRule`](crate::CodeMakerRule) implemented by a closure.
+///
+/// This is handy for registering small one-off rules in a [`RuleRegistry`] without
+/// having to define a new type for each of them:
+///
+/// ```ignore
+/// registry.insert("upper", Box::new(FnRule(|name: &str| name.to_uppercase())));
+/// ```
+pub struct FnRule<F>(pub F);
+
+impl<Input, Output, F> crate::CodeMakerRule<Input, Output> for FnRule<F>
+where
+    F: Fn(Input) -> Output,
+{
+    fn make_from(&self, input: Input) -> Output {
+        (self.0)(input)
+    }
+}
@@ -0,0 +1,323 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Rendering fragments of output as text.
+//!
+//! An [`OutputFile`](crate::OutputFile) knows how to write itself to disk, but rules usually
+//! produce smaller fragments of output like individual statements or expressions. Target crates
+//! implement [`Render`] for those fragments so that they can be turned into text on their own,
+//! which is mostly useful for testing rules with [`assert_makes!`](crate::assert_makes).
+
+use std::io::Write;
+
+/// A fragment of output that can be rendered as text in the target format.
+pub trait Render {
+    /// Write the rendered text of this fragment into the given writer.
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()>;
+
+    /// Write the rendered text of this fragment, honouring the indent style and width from the given options.
+    ///
+    /// The default implementation ignores the options and calls [`Render::render_into`];
+    /// target crates should override it for fragments whose layout depends on the options.
+    fn render_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        let _ = options;
+        self.render_into(writer)
+    }
+
+    /// Render this fragment to a string.
+    fn render(&self) -> String {
+        let mut buf = vec![];
+        self.render_into(&mut buf)
+            .expect("writing into a Vec should not fail");
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+impl Render for str {
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(self.as_bytes())
+    }
+}
+
+impl Render for String {
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.as_str().render_into(writer)
+    }
+}
+
+/// Rendering a sequence of fragments renders each of them in turn.
+impl<T: Render> Render for [T] {
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for item in self {
+            item.render_into(writer)?;
+        }
+        Ok(())
+    }
+
+    fn render_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        for item in self {
+            item.render_into_with_options(writer, options)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Render> Render for Vec<T> {
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.as_slice().render_into(writer)
+    }
+
+    fn render_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        self.as_slice().render_into_with_options(writer, options)
+    }
+}
+
+/// Rendering a missing fragment renders nothing at all.
+impl<T: Render> Render for Option<T> {
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            Some(item) => item.render_into(writer),
+            None => Ok(()),
+        }
+    }
+
+    fn render_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        match self {
+            Some(item) => item.render_into_with_options(writer, options),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Render + ?Sized> Render for &T {
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        (**self).render_into(writer)
+    }
+
+    fn render_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        (**self).render_into_with_options(writer, options)
+    }
+}
+
+impl<T: Render + ?Sized> Render for Box<T> {
+    fn render_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        (**self).render_into(writer)
+    }
+
+    fn render_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        (**self).render_into_with_options(writer, options)
+    }
+}
+
+/// How to indent each level of nesting in the rendered output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndentStyle {
+    /// Indent with the given number of spaces per level.
+    Spaces(usize),
+    /// Indent with a single tab character per level.
+    Tabs,
+}
+
+/// Which characters to use for ending each line of the rendered output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Formatting preferences for rendering output files.
+///
+/// Target crates are responsible for honouring the indent style and maximum line width, since
+/// only they know how their output is structured. Line endings, final newlines and trailing
+/// whitespace are applied to the rendered text by a [`RenderWriter`], which the default
+/// implementation of [`OutputFile::write_into_with_options`](crate::OutputFile::write_into_with_options)
+/// takes care of.
+///
+/// The default options leave the output exactly as the target crate writes it, apart from
+/// indenting with four spaces per level.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RenderOptions {
+    indent: IndentStyle,
+    line_ending: Option<LineEnding>,
+    final_newline: bool,
+    trim_trailing_whitespace: bool,
+    max_width: usize,
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        RenderOptions {
+            indent: IndentStyle::Spaces(4),
+            line_ending: None,
+            final_newline: false,
+            trim_trailing_whitespace: false,
+            max_width: 100,
+        }
+    }
+
+    pub fn indent(mut self, indent: IndentStyle) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// End every line with the given line ending, whichever one it was written with.
+    ///
+    /// By default, lines keep the ending that the target crate wrote them with.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = Some(line_ending);
+        self
+    }
+
+    /// Whether to make sure that non-empty files end with a line ending.
+    pub fn final_newline(mut self, final_newline: bool) -> Self {
+        self.final_newline = final_newline;
+        self
+    }
+
+    /// Whether to remove spaces and tabs from the end of each line.
+    pub fn trim_trailing_whitespace(mut self, trim: bool) -> Self {
+        self.trim_trailing_whitespace = trim;
+        self
+    }
+
+    /// The width at which target crates should try to break long lines.
+    pub fn max_width(mut self, max_width: usize) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    pub fn indent_style(&self) -> IndentStyle {
+        self.indent
+    }
+
+    /// The line ending to use, which is `\n` unless a different one has been set.
+    pub fn line_ending_style(&self) -> LineEnding {
+        self.line_ending.unwrap_or(LineEnding::Lf)
+    }
+
+    pub fn width(&self) -> usize {
+        self.max_width
+    }
+
+    /// The text to indent a line by the given number of levels.
+    pub fn indentation(&self, level: usize) -> String {
+        match self.indent {
+            IndentStyle::Spaces(n) => " ".repeat(n * level),
+            IndentStyle::Tabs => "\t".repeat(level),
+        }
+    }
+
+    /// The width of the indentation for the given number of levels, counting a tab as four columns.
+    pub fn indentation_width(&self, level: usize) -> usize {
+        match self.indent {
+            IndentStyle::Spaces(n) => n * level,
+            IndentStyle::Tabs => 4 * level,
+        }
+    }
+
+    /// Wrap a writer to apply the line ending, final newline and trailing whitespace options.
+    pub fn writer<W: Write>(&self, inner: W) -> RenderWriter<W> {
+        RenderWriter {
+            inner,
+            line_ending: self.line_ending,
+            final_newline: self.final_newline,
+            trim_trailing_whitespace: self.trim_trailing_whitespace,
+            line: vec![],
+            last_ending: LineEnding::Lf,
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A writer that applies [`RenderOptions`] to text written with `\n` or `\r\n` line endings.
+///
+/// Text is buffered a line at a time, so [`RenderWriter::finish`] must be called once all of
+/// the output has been written in order to write out the last line.
+pub struct RenderWriter<W: Write> {
+    inner: W,
+    line_ending: Option<LineEnding>,
+    final_newline: bool,
+    trim_trailing_whitespace: bool,
+    line: Vec<u8>,
+    last_ending: LineEnding,
+}
+
+impl<W: Write> RenderWriter<W> {
+    /// Write out the buffered line, followed by the line ending it was written with if any.
+    fn write_line(&mut self, ending: Option<LineEnding>) -> std::io::Result<()> {
+        if self.trim_trailing_whitespace {
+            while let Some(b' ') | Some(b'\t') = self.line.last() {
+                self.line.pop();
+            }
+        }
+        self.inner.write_all(&self.line)?;
+        if let Some(ending) = ending {
+            let ending = self.line_ending.unwrap_or(ending);
+            self.inner.write_all(ending.as_str().as_bytes())?;
+        }
+        self.line.clear();
+        Ok(())
+    }
+
+    /// Write out any unfinished last line, and return the inner writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if !self.line.is_empty() {
+            // Without a line ending to use, end the file like the line before it.
+            let ending = if self.final_newline { Some(self.last_ending) } else { None };
+            self.write_line(ending)?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for RenderWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while let Some(i) = rest.iter().position(|b| *b == b'\n') {
+            self.line.extend_from_slice(&rest[..i]);
+            let ending = if self.line.last() == Some(&b'\r') {
+                self.line.pop();
+                LineEnding::CrLf
+            } else {
+                LineEnding::Lf
+            };
+            self.last_ending = ending;
+            self.write_line(Some(ending))?;
+            rest = &rest[i + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
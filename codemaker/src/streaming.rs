@@ -0,0 +1,93 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Output files whose contents are produced lazily, for outputs too large to hold in memory.
+//!
+//! Ordinarily a rule builds the whole output structure in memory before it's written to
+//! disk, which is simplest but can use a lot of memory for huge generated files like data
+//! tables. A [`StreamingFile`] instead holds a function that produces the fragments of the
+//! file one at a time, typically by mapping a rule over the input, and each fragment is
+//! written out as soon as it has been made:
+//!
+//! ```ignore
+//! let table = StreamingFile::new("big_table.py", move || maker.make_from_iter(input.rows.iter()));
+//! table.write_into_dir("generated")?;
+//! ```
+//!
+//! The function is called again each time the file is written, so the fragments must be
+//! made the same way every time.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{OutputFile, Render, RenderOptions};
+
+type Fragments<'a, T> = dyn Fn() -> Box<dyn Iterator<Item = T> + 'a> + 'a;
+
+/// An output file whose contents are the fragments from a lazy iterator, rendered one after another.
+pub struct StreamingFile<'a, T: Render> {
+    path: PathBuf,
+    fragments: Box<Fragments<'a, T>>,
+}
+
+impl<'a, T: Render> StreamingFile<'a, T> {
+    /// A file at the given path whose contents come from the iterator returned by `fragments`.
+    pub fn new<P, F, I>(path: P, fragments: F) -> Self
+    where
+        P: Into<PathBuf>,
+        F: Fn() -> I + 'a,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: 'a,
+    {
+        StreamingFile {
+            path: path.into(),
+            fragments: Box::new(move || Box::new(fragments().into_iter())),
+        }
+    }
+
+    /// Iterate over the fragments of the file, making them afresh.
+    pub fn fragments(&self) -> Box<dyn Iterator<Item = T> + 'a> {
+        (self.fragments)()
+    }
+}
+
+impl<T: Render> OutputFile for StreamingFile<'_, T> {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for fragment in self.fragments() {
+            fragment.render_into(writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        let mut writer = options.writer(writer);
+        for fragment in self.fragments() {
+            fragment.render_into_with_options(&mut writer, options)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+impl<T: Render> std::fmt::Debug for StreamingFile<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamingFile").field("path", &self.path).finish()
+    }
+}
@@ -0,0 +1,390 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Making output for several target languages in a single run.
+//!
+//! A [`CodeMaker`](crate::CodeMaker) has a single output type, but it's often useful to make
+//! bindings for several languages from the same input, so that they can share validation of
+//! the input and agree on things like naming. [`Targets`] is an [`OutputFileSet`] that holds
+//! the output of any number of targets, each written under its own subdirectory:
+//!
+//! ```ignore
+//! struct StatusCodesMaker {
+//!     targets: Targets,
+//! }
+//!
+//! impl<'a> CodeMaker<'a> for StatusCodesMaker {
+//!     type Input = &'a StatusCodes;
+//!     type Output = Targets;
+//! }
+//!
+//! define_codemaker_rules! {
+//!     StatusCodesMaker as self {
+//!         &StatusCodes as input => Targets {
+//!             let names = self.validate_and_name(input);
+//!             self.targets
+//!                 .clone()
+//!                 .with_target("python", PythonMaker.make_from(&names))
+//!                 .with_target("typescript", TypeScriptMaker.make_from(&names))
+//!         }
+//!     }
+//! }
+//!
+//! let maker = StatusCodesMaker {
+//!     targets: Targets::new()
+//!         .with_options("python", TargetOptions::new().subdirectory("py/status_codes"))
+//!         .with_options("typescript", TargetOptions::new().enabled(config.typescript)),
+//! };
+//! maker.make(&codes).write_into_dir("generated")?;
+//! ```
+//!
+//! Options for each target, including the [`RenderOptions`] for its files, are registered
+//! before its output is added, typically by keeping a pre-configured [`Targets`] in the maker
+//! and cloning it for each run as above.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::{OutputFile, OutputFileSet, PostProcess, PostProcessError, RenderOptions, SourceMap};
+
+/// Options controlling how the output of an individual target is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetOptions {
+    subdirectory: Option<PathBuf>,
+    enabled: bool,
+    render: Option<RenderOptions>,
+}
+
+impl TargetOptions {
+    pub fn new() -> Self {
+        TargetOptions {
+            subdirectory: None,
+            enabled: true,
+            render: None,
+        }
+    }
+
+    /// Write the target's files under the given subdirectory, rather than one named after the target.
+    ///
+    /// The subdirectory must be a relative path inside the base output directory, and may be
+    /// empty to write the files directly into the base output directory. Writing the output
+    /// into a directory will fail with an error of kind `InvalidInput` if it isn't.
+    pub fn subdirectory<P: Into<PathBuf>>(mut self, subdirectory: P) -> Self {
+        self.subdirectory = Some(subdirectory.into());
+        self
+    }
+
+    /// Whether the target should be made at all; output added for a disabled target is discarded.
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Render the target's files with the given options, rather than those used for the other targets.
+    pub fn render_options(mut self, options: RenderOptions) -> Self {
+        self.render = Some(options);
+        self
+    }
+
+    /// Check whether the target is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+impl Default for TargetOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The output of several targets, each written under its own subdirectory.
+///
+/// The files of each target share ownership of that target's output through an `Rc`, so
+/// that each [`TargetFile`] can render itself and cloning a `Targets` doesn't copy any
+/// output. This means that `Targets` isn't `Send`, and that the output added to it must be
+/// `'static` rather than borrowing from the input; the latter keeps `Targets` free of a
+/// lifetime parameter, so that it can be used as a maker's output type as it stands.
+#[derive(Clone, Default)]
+pub struct Targets {
+    options: BTreeMap<String, TargetOptions>,
+    names: Vec<String>,
+    files: Vec<TargetFile>,
+}
+
+impl Targets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the options for the named target, which will apply to any output added for it afterwards.
+    pub fn set_options<N: Into<String>>(&mut self, name: N, options: TargetOptions) {
+        self.options.insert(name.into(), options);
+    }
+
+    /// Fluently set the options for the named target.
+    pub fn with_options<N: Into<String>>(mut self, name: N, options: TargetOptions) -> Self {
+        self.set_options(name, options);
+        self
+    }
+
+    /// The options for the named target, which are the defaults if none have been set.
+    pub fn options(&self, name: &str) -> TargetOptions {
+        self.options.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Add the output of the named target, unless that target is disabled.
+    ///
+    /// Adding output for the same target more than once will write the files from each
+    /// into the same subdirectory.
+    pub fn insert<N: Into<String>, S: OutputFileSet + 'static>(&mut self, name: N, output: S) {
+        let name = name.into();
+        let options = self.options(&name);
+        if !options.enabled {
+            return;
+        }
+        let subdirectory = options.subdirectory.unwrap_or_else(|| PathBuf::from(&name));
+        let paths = output.files().into_iter().map(|file| subdirectory.join(file.path())).collect::<Vec<_>>();
+        let output: Rc<dyn DynOutputFileSet> = Rc::new(output);
+        for (index, path) in paths.into_iter().enumerate() {
+            self.files.push(TargetFile {
+                path,
+                target: name.clone(),
+                render: options.render.clone(),
+                index,
+                output: Rc::clone(&output),
+            });
+        }
+        if !self.names.contains(&name) {
+            self.names.push(name);
+        }
+    }
+
+    /// Fluently add the output of the named target.
+    pub fn with_target<N: Into<String>, S: OutputFileSet + 'static>(mut self, name: N, output: S) -> Self {
+        self.insert(name, output);
+        self
+    }
+
+    /// The names of the targets that have output, in the order they were added.
+    pub fn names(&self) -> Vec<&str> {
+        self.names.iter().map(String::as_str).collect()
+    }
+
+    /// The files made for the named target.
+    pub fn files_for(&self, name: &str) -> Vec<&TargetFile> {
+        self.files.iter().filter(|f| f.target == name).collect()
+    }
+}
+
+impl Targets {
+    /// Look up the underlying file for each of the files, calling `files()` only once for the
+    /// output of each target rather than once per file.
+    fn materialize(&self) -> Materialized<'_> {
+        let mut files = Vec::with_capacity(self.files.len());
+        let mut current_output: Option<&Rc<dyn DynOutputFileSet>> = None;
+        let mut output_files = vec![];
+        for target_file in &self.files {
+            // The files added for each target are contiguous, so only a change of output needs a lookup.
+            if !current_output.is_some_and(|output| Rc::ptr_eq(output, &target_file.output)) {
+                current_output = Some(&target_file.output);
+                output_files = target_file.output.dyn_files();
+            }
+            files.push(MaterializedFile {
+                target_file,
+                file: output_files[target_file.index],
+            });
+        }
+        Materialized(files)
+    }
+}
+
+impl OutputFileSet for Targets {
+    type OutputFile = TargetFile;
+    fn files(&self) -> Vec<&TargetFile> {
+        self.files.iter().collect()
+    }
+
+    fn write_into_dir_with_options<P: AsRef<Path>>(&self, base_directory: P, options: &RenderOptions) -> std::io::Result<()> {
+        self.materialize().write_into_dir_with_options(base_directory, options)
+    }
+
+    fn write_into_dir_with_post_process<P: AsRef<Path>>(
+        &self,
+        base_directory: P,
+        options: &RenderOptions,
+        post_process: &PostProcess,
+    ) -> std::io::Result<Vec<PostProcessError>> {
+        self.materialize()
+            .write_into_dir_with_post_process(base_directory, options, post_process)
+    }
+
+    fn write_into_dir_with_source_maps<P: AsRef<Path>>(&self, base_directory: P, options: &RenderOptions) -> std::io::Result<()> {
+        self.materialize().write_into_dir_with_source_maps(base_directory, options)
+    }
+}
+
+/// A single file made for one of a set of [`Targets`].
+///
+/// Writing a file on its own has to look it up among the files of its target's output, which
+/// takes time proportional to the number of those files. The `write_into_dir` methods of
+/// [`Targets`] look up all the files at once, so prefer them when writing the whole output.
+#[derive(Clone)]
+pub struct TargetFile {
+    path: PathBuf,
+    target: String,
+    render: Option<RenderOptions>,
+    index: usize,
+    output: Rc<dyn DynOutputFileSet>,
+}
+
+impl TargetFile {
+    /// The name of the target that this file was made for.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+}
+
+impl OutputFile for TargetFile {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write_target_file(self, self.output.dyn_files()[self.index], writer)
+    }
+
+    fn write_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        write_target_file_with_options(self, self.output.dyn_files()[self.index], writer, options)
+    }
+
+    fn write_into_with_source_map<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<SourceMap> {
+        write_target_file_with_source_map(self, self.output.dyn_files()[self.index], writer, options)
+    }
+}
+
+// These apply the target's own render options, if it has any, in preference to those given.
+
+fn write_target_file<W: Write>(target_file: &TargetFile, file: &dyn DynOutputFile, mut writer: &mut W) -> std::io::Result<()> {
+    match &target_file.render {
+        Some(options) => file.write_with_options(&mut writer, options),
+        None => file.write(&mut writer),
+    }
+}
+
+fn write_target_file_with_options<W: Write>(
+    target_file: &TargetFile,
+    file: &dyn DynOutputFile,
+    mut writer: &mut W,
+    options: &RenderOptions,
+) -> std::io::Result<()> {
+    file.write_with_options(&mut writer, target_file.render.as_ref().unwrap_or(options))
+}
+
+fn write_target_file_with_source_map<W: Write>(
+    target_file: &TargetFile,
+    file: &dyn DynOutputFile,
+    mut writer: &mut W,
+    options: &RenderOptions,
+) -> std::io::Result<SourceMap> {
+    file.write_with_source_map(&mut writer, target_file.render.as_ref().unwrap_or(options))
+}
+
+/// The files of a set of [`Targets`] along with the underlying file that each of them writes,
+/// so that writing all of them doesn't have to look up each one separately.
+struct Materialized<'t>(Vec<MaterializedFile<'t>>);
+
+struct MaterializedFile<'t> {
+    target_file: &'t TargetFile,
+    file: &'t dyn DynOutputFile,
+}
+
+impl<'t> OutputFileSet for Materialized<'t> {
+    type OutputFile = MaterializedFile<'t>;
+    fn files(&self) -> Vec<&MaterializedFile<'t>> {
+        self.0.iter().collect()
+    }
+}
+
+impl OutputFile for MaterializedFile<'_> {
+    fn path(&self) -> &Path {
+        &self.target_file.path
+    }
+
+    fn write_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write_target_file(self.target_file, self.file, writer)
+    }
+
+    fn write_into_with_options<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        write_target_file_with_options(self.target_file, self.file, writer, options)
+    }
+
+    fn write_into_with_source_map<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<SourceMap> {
+        write_target_file_with_source_map(self.target_file, self.file, writer, options)
+    }
+}
+
+impl std::fmt::Debug for TargetFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TargetFile")
+            .field("path", &self.path)
+            .field("target", &self.target)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for Targets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Targets")
+            .field("options", &self.options)
+            .field("files", &self.files)
+            .finish()
+    }
+}
+
+/// An object-safe version of [`OutputFileSet`], so that targets of different types can be stored together.
+trait DynOutputFileSet {
+    fn dyn_files(&self) -> Vec<&dyn DynOutputFile>;
+}
+
+impl<S: OutputFileSet> DynOutputFileSet for S {
+    fn dyn_files(&self) -> Vec<&dyn DynOutputFile> {
+        self.files().into_iter().map(|file| file as &dyn DynOutputFile).collect()
+    }
+}
+
+/// An object-safe version of [`OutputFile`], for the files of a [`DynOutputFileSet`].
+trait DynOutputFile {
+    fn write(&self, writer: &mut dyn Write) -> std::io::Result<()>;
+    fn write_with_options(&self, writer: &mut dyn Write, options: &RenderOptions) -> std::io::Result<()>;
+    fn write_with_source_map(&self, writer: &mut dyn Write, options: &RenderOptions) -> std::io::Result<SourceMap>;
+}
+
+impl<F: OutputFile> DynOutputFile for F {
+    fn write(&self, mut writer: &mut dyn Write) -> std::io::Result<()> {
+        self.write_into(&mut writer)
+    }
+
+    fn write_with_options(&self, mut writer: &mut dyn Write, options: &RenderOptions) -> std::io::Result<()> {
+        self.write_into_with_options(&mut writer, options)
+    }
+
+    fn write_with_source_map(&self, mut writer: &mut dyn Write, options: &RenderOptions) -> std::io::Result<SourceMap> {
+        self.write_into_with_source_map(&mut writer, options)
+    }
+}
@@ -0,0 +1,180 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Assertions for unit-testing individual rules.
+//!
+//! Rules are easiest to test one at a time, by giving them a small input and checking
+//! the fragment of output that they make. The [`assert_makes!`](crate::assert_makes)
+//! macro renders the output using the target crate's [`Render`] impl and compares
+//! it to the expected text, while [`assert_makes_eq!`](crate::assert_makes_eq) compares
+//! the output structurally. Both show a line-by-line diff when the output doesn't match:
+//!
+//! ```ignore
+//! assert_makes!(maker, &(200, "OK".into()) => "OK = 200\n");
+//! assert_makes_eq!(maker, &(200, "OK".into()) => py::Assignment::new("OK", "200"));
+//! ```
+
+use crate::Render;
+
+/// Check that a rule made the expected text, panicking with a readable diff if not.
+#[doc(hidden)]
+pub fn assert_rendered<T: Render + ?Sized>(output: &T, expected: &str, input: &str) {
+    let actual = output.render();
+    if actual != expected {
+        panic!(
+            "rule made unexpected output from `{}`:\n{}",
+            input,
+            diff_lines(expected, &actual)
+        );
+    }
+}
+
+/// Check that a rule made the expected value, panicking with a readable diff if not.
+#[doc(hidden)]
+pub fn assert_structurally_eq<T: std::fmt::Debug + PartialEq>(output: &T, expected: &T, input: &str) {
+    if output != expected {
+        panic!(
+            "rule made unexpected output from `{}`:\n{}",
+            input,
+            diff_lines(&format!("{:#?}", expected), &format!("{:#?}", output))
+        );
+    }
+}
+
+/// A unified-style diff of two strings, line by line.
+///
+/// Lines only in `expected` are prefixed with `-` and lines only in `actual` with `+`.
+/// A missing or extra trailing newline shows up as a difference in the final, empty, line.
+pub fn diff_lines(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.split('\n').collect();
+    let new: Vec<&str> = actual.split('\n').collect();
+    // Classic longest-common-subsequence table; test outputs are small enough
+    // that the quadratic cost doesn't matter.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut diff = String::from("--- expected\n+++ actual\n");
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push_str(&format!(" {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+/// Assert that a rule makes output which renders as the expected text.
+///
+/// The rule is called with `make_from`, and its output rendered using the [`Render`] trait.
+/// If the rendered text doesn't match, the assertion panics with a diff of the two:
+///
+/// ```ignore
+/// assert_makes!(maker, &(200, "OK".into()) => "OK = 200\n");
+/// ```
+///
+/// If the maker has several rules for the same input type, name the output type
+/// of the one to test after the maker:
+///
+/// ```ignore
+/// assert_makes!(maker => py::Statement, &(200, "OK".into()) => "OK = 200\n");
+/// ```
+///
+/// For stateless rules, prefix the type of the maker with `stateless`:
+///
+/// ```ignore
+/// assert_makes!(stateless StatusMaker, (200, "OK".into()) => "OK = 200\n");
+/// assert_makes!(stateless StatusMaker => py::Statement, (200, "OK".into()) => "OK = 200\n");
+/// ```
+#[macro_export]
+macro_rules! assert_makes {
+    (stateless $maker:ty => $out:ty, $input:expr => $expected:expr $(,)?) => {
+        $crate::__private::assert_rendered(
+            &<$maker as $crate::StatelessCodeMakerRule<_, $out>>::make_from($input),
+            ::std::convert::AsRef::<str>::as_ref(&$expected),
+            stringify!($input),
+        )
+    };
+    (stateless $maker:ty, $input:expr => $expected:expr $(,)?) => {
+        $crate::__private::assert_rendered(
+            &<$maker as $crate::StatelessCodeMakerRule<_, _>>::make_from($input),
+            ::std::convert::AsRef::<str>::as_ref(&$expected),
+            stringify!($input),
+        )
+    };
+    ($maker:expr => $out:ty, $input:expr => $expected:expr $(,)?) => {{
+        use $crate::CodeMakerRule as _;
+        let output: $out = ($maker).make_from($input);
+        $crate::__private::assert_rendered(
+            &output,
+            ::std::convert::AsRef::<str>::as_ref(&$expected),
+            stringify!($input),
+        )
+    }};
+    ($maker:expr, $input:expr => $expected:expr $(,)?) => {{
+        use $crate::CodeMakerRule as _;
+        $crate::__private::assert_rendered(
+            &($maker).make_from($input),
+            ::std::convert::AsRef::<str>::as_ref(&$expected),
+            stringify!($input),
+        )
+    }};
+}
+
+/// Assert that a rule makes output equal to an expected value.
+///
+/// This is like [`assert_makes!`](crate::assert_makes), but compares the output structurally
+/// using `PartialEq` rather than rendering it, and shows a diff of the `Debug` representation
+/// of the two values if they don't match. The output type is inferred from the expected value:
+///
+/// ```ignore
+/// assert_makes_eq!(maker, &(200, "OK".into()) => py::Assignment::new("OK", "200"));
+/// assert_makes_eq!(stateless StatusMaker, (200, "OK".into()) => py::Assignment::new("OK", "200"));
+/// ```
+#[macro_export]
+macro_rules! assert_makes_eq {
+    (stateless $maker:ty, $input:expr => $expected:expr $(,)?) => {{
+        let expected = $expected;
+        $crate::__private::assert_structurally_eq(
+            &<$maker as $crate::StatelessCodeMakerRule<_, _>>::make_from($input),
+            &expected,
+            stringify!($input),
+        )
+    }};
+    ($maker:expr, $input:expr => $expected:expr $(,)?) => {{
+        use $crate::CodeMakerRule as _;
+        let expected = $expected;
+        $crate::__private::assert_structurally_eq(
+            &($maker).make_from($input),
+            &expected,
+            stringify!($input),
+        )
+    }};
+}
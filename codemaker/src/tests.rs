@@ -69,3 +69,1433 @@ fn test_define_rules_on_target_with_anonymous_lifetime_on_target() {
     assert_eq!(t.make_from(vec![1, 2, 3]), "1-2-3-");
     assert_eq!(t.make_from(&vec![4, 5, 6, 7]), "4-5-6-7-");
 }
+
+#[test]
+fn test_define_inheritable_rules() {
+    struct ParentMaker {
+        sep: String,
+    }
+    struct ChildMaker {
+        parent: ParentMaker,
+    }
+    impl std::ops::Deref for ChildMaker {
+        type Target = ParentMaker;
+        fn deref(&self) -> &ParentMaker {
+            &self.parent
+        }
+    }
+
+    define_inheritable_codemaker_rules! {
+        parent_rules for ParentMaker as self {
+            /// Rules with doc comments can be inherited.
+            Vec<u32> as input => String {
+                self.make_from_iter(input).collect()
+            }
+            u32 as input => String {
+                format!("{}{}", input, self.sep)
+            }
+            (u32, u32) as (a, b) => String {
+                format!("{}{}", a + b, self.sep)
+            }
+        }
+    }
+
+    parent_rules! {
+        ChildMaker as self {
+            u32 as input => String {
+                format!("<{}>{}", input, self.sep)
+            }
+            bool as input => String {
+                format!("{}", input)
+            }
+        }
+    }
+
+    let p = ParentMaker { sep: ",".into() };
+    assert_eq!(p.make_from(vec![1, 2, 3]), "1,2,3,");
+    let c = ChildMaker { parent: ParentMaker { sep: ";".into() } };
+    assert_eq!(c.make_from(vec![1, 2, 3]), "<1>;<2>;<3>;");
+    assert_eq!(c.make_from((1, 2)), "3;");
+    assert_eq!(c.make_from(true), "true");
+}
+
+#[test]
+fn test_define_rules_for_enum_variants() {
+    enum TestType {
+        Int(u32),
+        List(Vec<TestType>),
+        Unit,
+        Nothing,
+    }
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            match &TestType => String {
+                /// Variant rules can have doc comments.
+                TestType::Int(value) => {
+                    self.make_from(*value)
+                }
+                TestType::List(items) => {
+                    format!("[{}]", self.make_from_iter(items).collect::<Vec<String>>().join(","))
+                }
+                TestType::Unit | TestType::Nothing => {
+                    "()".into()
+                }
+            }
+            u32 as input => String {
+                format!("{}", input)
+            }
+        }
+    }
+
+    let t = TestMaker {};
+    let input = TestType::List(vec![
+        TestType::Int(1),
+        TestType::Unit,
+        TestType::List(vec![TestType::Int(2), TestType::Nothing]),
+    ]);
+    assert_eq!(t.make_from(&input), "[1,(),[2,()]]");
+}
+
+#[test]
+fn test_define_rules_for_named_enum_input() {
+    #[derive(Debug)]
+    enum TestType {
+        Int(u32),
+        Name(&'static str),
+    }
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            u32 as input => String {
+                format!("{}", input)
+            }
+            /// Make a thing from a test type.
+            match &TestType as whole => String {
+                #[allow(clippy::clone_on_copy)]
+                TestType::Int(value) => {
+                    self.make_from(value.clone())
+                }
+                #[cfg(any())]
+                TestType::Missing => {
+                    unreachable!()
+                }
+                /// Names are debug-printed.
+                TestType::Name(name) => {
+                    format!("{} is {:?}", name, whole)
+                }
+            }
+        }
+    }
+
+    assert_eq!(TestMaker.make_from(&TestType::Int(1)), "1");
+    assert_eq!(TestMaker.make_from(&TestType::Name("x")), "x is Name(\"x\")");
+    let graph = RuleGraph::for_maker::<TestMaker>();
+    let rule = graph.rules().iter().find(|r| r.input_type() == "&TestType").unwrap();
+    assert_eq!(rule.doc(), "Make a thing from a test type.\nNames are debug-printed.");
+}
+
+#[test]
+fn test_define_lots_of_rules() {
+    enum TestType {
+        Small(u8),
+        Large(u16),
+    }
+    struct TestMaker;
+
+    macro_rules! lots_of_rules {
+        ($($n:literal)*) => {
+            define_codemaker_rules! {
+                TestMaker as self {
+                    $( [u8; $n] as input => usize { input.len() } )*
+                    match TestType => usize {
+                        TestType::Small(value) => {
+                            value.into()
+                        }
+                        TestType::Large(value) => {
+                            value.into()
+                        }
+                    }
+                    $( [u16; $n] as input => usize { input.len() * 2 } )*
+                }
+            }
+        };
+    }
+    lots_of_rules! {
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19
+            20 21 22 23 24 25 26 27 28 29 30 31 32 33 34 35 36 37 38 39
+            40 41 42 43 44 45 46 47 48 49 50 51 52 53 54 55 56 57 58 59
+            60 61 62 63 64 65 66 67 68 69 70 71 72 73 74 75 76 77 78 79
+            80 81 82 83 84 85 86 87 88 89 90 91 92 93 94 95 96 97 98 99
+            100 101 102 103 104 105 106 107 108 109 110 111 112 113 114 115 116 117 118 119
+            120 121 122 123 124 125 126 127 128 129 130 131 132 133 134 135 136 137 138 139
+            140 141 142 143 144 145 146 147 148 149 150 151 152 153 154 155 156 157 158 159
+            160 161 162 163 164 165 166 167 168 169 170 171 172 173 174 175 176 177 178 179
+            180 181 182 183 184 185 186 187 188 189 190 191 192 193 194 195 196 197 198 199
+    }
+
+    assert_eq!(TestMaker.make_from([0u8; 150]), 150);
+    assert_eq!(TestMaker.make_from([0u16; 199]), 398);
+    assert_eq!(TestMaker.make_from(TestType::Small(3)), 3);
+    assert_eq!(TestMaker.make_from(TestType::Large(7)), 7);
+}
+
+#[test]
+fn test_inherit_rules_for_enum_variants() {
+    enum TestType {
+        Int(u32),
+        Pair(u32, u32),
+    }
+    struct ParentMaker;
+    struct ChildMaker;
+
+    define_inheritable_codemaker_rules! {
+        parent_rules for ParentMaker as self {
+            Vec<TestType> as input => String {
+                self.make_from_iter(input).collect()
+            }
+            match TestType => String {
+                TestType::Int(value) => {
+                    format!("{};", value)
+                }
+                TestType::Pair(a, b) => {
+                    format!("{}:{};", a, b)
+                }
+            }
+        }
+    }
+
+    parent_rules! {
+        ChildMaker as self {
+            match TestType as _input => String {
+                TestType::Int(value) => {
+                    format!("<{}>", value)
+                }
+                TestType::Pair(a, b) => {
+                    format!("<{}:{}>", a, b)
+                }
+            }
+        }
+    }
+
+    let input = || vec![TestType::Int(1), TestType::Pair(2, 3)];
+    assert_eq!(ParentMaker.make_from(input()), "1;2:3;");
+    assert_eq!(ChildMaker.make_from(input()), "<1><2:3>");
+}
+
+#[test]
+fn test_memoize_rules_on_shared_nodes() {
+    struct Node {
+        name: String,
+        children: Vec<std::rc::Rc<Node>>,
+    }
+    struct TestMaker {
+        calls: std::cell::Cell<u32>,
+        names: MemoCache<ByAddress, String>,
+        defined: EmitOnce<ByAddress>,
+    }
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            &Node as input => String {
+                self.names.get_or_make(ByAddress::of(input), || {
+                    self.calls.set(self.calls.get() + 1);
+                    input.name.to_uppercase()
+                })
+            }
+            &Node as input => Vec<String> {
+                let mut defs: Vec<String> = input.children.iter().flat_map(|c| {
+                    let defs: Vec<String> = self.make_from(&**c);
+                    defs
+                }).collect();
+                let name: String = self.make_from(input);
+                defs.extend(self.defined.emit_once(ByAddress::of(input), || format!("def {}", name)));
+                defs
+            }
+        }
+    }
+
+    let shared = std::rc::Rc::new(Node { name: "shared".into(), children: vec![] });
+    let left = std::rc::Rc::new(Node { name: "left".into(), children: vec![shared.clone()] });
+    let right = std::rc::Rc::new(Node { name: "right".into(), children: vec![shared.clone()] });
+    let root = Node { name: "root".into(), children: vec![left, right, shared.clone()] };
+
+    let t = TestMaker {
+        calls: std::cell::Cell::new(0),
+        names: MemoCache::new(),
+        defined: EmitOnce::new(),
+    };
+    let defs: Vec<String> = t.make_from(&root);
+    assert_eq!(defs, vec!["def SHARED", "def LEFT", "def RIGHT", "def ROOT"]);
+    assert_eq!(t.calls.get(), 4);
+    assert!(t.defined.was_emitted(&ByAddress::of(&*shared)));
+    assert_eq!(t.names.get(&ByAddress::of(&*shared)), Some("SHARED".into()));
+}
+
+#[test]
+fn test_two_phase_make_with_index() {
+    struct Wrapper(String);
+    impl OutputFile for Wrapper {
+        fn path(&self) -> &std::path::Path {
+            std::path::Path::new("test.txt")
+        }
+        fn write_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+            writer.write_all(self.0.as_bytes())
+        }
+    }
+    #[derive(PartialEq)]
+    struct Exported(String);
+    struct TestMaker {
+        index: IndexCell,
+    }
+
+    impl<'a> CodeMaker<'a> for TestMaker {
+        type Input = &'a Vec<(String, u32)>;
+        type Output = Wrapper;
+
+        fn collect(&self, input: &Self::Input, index: &mut Index) {
+            for (name, _) in input.iter() {
+                index.insert(Exported(name.clone()));
+            }
+            index.entries_mut::<Exported>().sort_by(|a, b| a.0.cmp(&b.0));
+            index.insert(input.len());
+        }
+
+        fn index_cell(&self) -> Option<&IndexCell> {
+            Some(&self.index)
+        }
+    }
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            &Vec<(String, u32)> as input => Wrapper {
+                let index = self.index.get();
+                let names: Vec<&str> = index.all::<Exported>().iter().map(|e| e.0.as_str()).collect();
+                let mut out = format!("__all__ = {:?}  # {} items\n", names, index.all::<usize>()[0]);
+                out.extend(self.make_from_iter(input));
+                Wrapper(out)
+            }
+            &(String, u32) as (name, value) => String {
+                assert!(self.index.get().contains(&Exported(name.clone())));
+                format!("{} = {}\n", name, value)
+            }
+        }
+    }
+
+    let t = TestMaker { index: IndexCell::new() };
+    assert!(!t.index.is_set());
+    let input = vec![("B".to_string(), 2), ("A".to_string(), 1)];
+    assert_eq!(t.make(&input).0, "__all__ = [\"A\", \"B\"]  # 2 items\nB = 2\nA = 1\n");
+    assert!(t.index.get().find::<Exported, _>(|e| e.0 == "A").is_some());
+    assert!(t.index.get().all::<String>().is_empty());
+}
+
+#[test]
+fn test_track_provenance_of_rule_outputs() {
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            Vec<u32> as input => Vec<(String, Option<Origin>)> {
+                self.make_from_iter(input).collect()
+            }
+            u32 as input => (String, Option<Origin>) {
+                (format!("{}", input), Origin::current())
+            }
+        }
+    }
+
+    let outputs = TestMaker.make_from(vec![1, 2]);
+    assert!(outputs.iter().all(|(_, origin)| origin.is_none()));
+
+    let outputs = with_provenance(|| TestMaker.make_from(vec![1, 2]));
+    assert!(!provenance_enabled());
+    let origin = outputs[1].1.as_ref().unwrap();
+    assert_eq!(origin.input_type(), "u32");
+    assert_eq!(origin.output_type(), "(String, Option<Origin>)");
+    assert_eq!(origin.input_path(), "Vec<u32>[1] > u32");
+
+    use std::io::Write;
+    let mut buf = vec![];
+    let mut writer = SourceWriter::with_source_map(&mut buf);
+    writer
+        .track(Some(origin), |w| {
+            writeln!(w, "def f():")?;
+            w.track(outputs[0].1.as_ref(), |w| writeln!(w, "    pass"))?;
+            w.track(Some(origin), |_| Ok(()))
+        })
+        .unwrap();
+    let map = writer.into_source_map();
+    assert_eq!(map.entries().len(), 2);
+    assert_eq!((map.entries()[0].start_line(), map.entries()[0].end_line()), (1, 2));
+    assert_eq!(map.find_line(2).unwrap().origin().input_path(), "Vec<u32>[0] > u32");
+    assert_eq!(
+        map.to_json(std::path::Path::new("f.py")),
+        "{\"file\": \"f.py\", \"mappings\": [\n  \
+         {\"lines\": [1, 2], \"input_type\": \"u32\", \"output_type\": \"(String, Option<Origin>)\", \"input_path\": \"Vec<u32>[1] > u32\"},\n  \
+         {\"lines\": [2, 2], \"input_type\": \"u32\", \"output_type\": \"(String, Option<Origin>)\", \"input_path\": \"Vec<u32>[0] > u32\"}\n]}\n"
+    );
+}
+
+#[test]
+fn test_rule_graph_introspection() {
+    enum TestType {
+        Int(u32),
+        List(Vec<u32>),
+    }
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            /// Make a list of things.
+            Vec<TestType> as input => String {
+                self.make_from_iter(input).collect()
+            }
+            match TestType => String {
+                /// Ints are made directly.
+                TestType::Int(value) => {
+                    self.make_from(value)
+                }
+                TestType::List(items) => {
+                    self.make_from_iter(items).collect()
+                }
+            }
+            u32 as input => String {
+                format!("{};", input)
+            }
+            bool as input => String {
+                format!("{}", input)
+            }
+        }
+    }
+
+    let mut graph = RuleGraph::for_maker::<TestMaker>();
+    let rules: Vec<String> = graph.rules().iter().map(|r| r.to_string()).collect();
+    assert_eq!(
+        rules,
+        vec!["TestType => String", "Vec<TestType> => String", "bool => String", "u32 => String"]
+    );
+    assert_eq!(graph.rules()[0].doc(), "Ints are made directly.");
+    assert_eq!(graph.rules()[1].doc(), "Make a list of things.");
+    assert!(graph.rules()[1].maker_type().ends_with("TestMaker"));
+    assert_eq!(graph.unreachable().len(), 4);
+
+    let output = graph.record(|| TestMaker.make_from(vec![TestType::Int(1), TestType::List(vec![2])]));
+    assert_eq!(output, "1;2;");
+    let edges: Vec<(String, String)> = graph
+        .delegations()
+        .iter()
+        .map(|(caller, callee)| (caller.input_type().to_string(), callee.input_type().to_string()))
+        .collect();
+    assert_eq!(
+        edges,
+        vec![("TestType".into(), "u32".into()), ("Vec<TestType>".into(), "TestType".into())]
+    );
+    assert_eq!(graph.delegates_of(graph.rules()[0]).len(), 1);
+    assert_eq!(graph.entry_points()[0].input_type(), "Vec<TestType>");
+    let unreachable: Vec<String> = graph.unreachable().iter().map(|r| r.to_string()).collect();
+    assert_eq!(unreachable, vec!["bool => String"]);
+
+    assert_eq!(
+        graph.to_dot(),
+        "digraph rules {\n    node [shape=box];\n    \
+         r0 [label=\"TestType => String\", tooltip=\"Ints are made directly.\"];\n    \
+         r1 [label=\"Vec<TestType> => String\", tooltip=\"Make a list of things.\", style=bold];\n    \
+         r2 [label=\"bool => String\", tooltip=\"\", style=dashed];\n    \
+         r3 [label=\"u32 => String\", tooltip=\"\"];\n    \
+         r0 -> r3;\n    r1 -> r0;\n}\n"
+    );
+    let json = graph.to_json();
+    assert!(json.contains(
+        "\"input_type\": \"Vec<TestType>\", \"output_type\": \"String\", \"doc\": \"Make a list of things.\", \
+         \"entry_point\": true, \"reachable\": true, \"delegates_to\": [0]}"
+    ));
+    assert!(json.contains("\"input_type\": \"bool\", \"output_type\": \"String\", \"doc\": \"\", \
+         \"entry_point\": false, \"reachable\": false, \"delegates_to\": []}"));
+}
+
+#[test]
+fn test_rule_graph_dot_escapes_doc_strings() {
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            #[doc = "Say \"hi\" to C:\\path\x07\nand bell."]
+            u32 as input => String {
+                format!("{};", input)
+            }
+        }
+    }
+
+    let graph = RuleGraph::for_maker::<TestMaker>();
+    assert_eq!(
+        graph.to_dot(),
+        "digraph rules {\n    node [shape=box];\n    \
+         r0 [label=\"u32 => String\", tooltip=\"Say \\\"hi\\\" to C:\\\\path\x07\\nand bell.\"];\n}\n"
+    );
+}
+
+#[test]
+fn test_rule_graph_declared_delegations() {
+    struct TestMaker;
+    struct StatelessMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            /// Make a list of things.
+            #[delegates(u32 => String, bool => String)]
+            Vec<u32> as input => String {
+                self.make_from_iter(input).collect()
+            }
+            u32 as input => String {
+                format!("{};", input)
+            }
+            bool as input => String {
+                format!("{}", input)
+            }
+        }
+    }
+    define_stateless_codemaker_rules! {
+        StatelessMaker {
+            #[delegates(u32 => String)]
+            Vec<u32> as input => String {
+                Self::make_from_iter(input).collect()
+            }
+            u32 as input => String {
+                format!("{};", input)
+            }
+        }
+    }
+
+    let mut graph = RuleGraph::for_maker::<TestMaker>();
+    assert_eq!(graph.rules()[0].doc(), "Make a list of things.");
+    assert_eq!(graph.rules()[0].declared_delegates(), &[("u32", "String"), ("bool", "String")]);
+    let delegates: Vec<String> = graph.delegates_of(graph.rules()[0]).iter().map(|r| r.to_string()).collect();
+    assert_eq!(delegates, vec!["bool => String", "u32 => String"]);
+    let graph_stateless = RuleGraph::for_maker::<StatelessMaker>();
+    assert_eq!(graph_stateless.delegations().len(), 1);
+
+    // A panic while recording shouldn't leave the recording running.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        graph.record(|| -> String { panic!("oops") })
+    }));
+    assert!(result.is_err());
+    assert!(!graph::recording());
+    graph.record(|| TestMaker.make_from(vec![1]));
+    assert_eq!(graph.entry_points()[0].input_type(), "Vec<u32>");
+}
+
+#[test]
+fn test_rule_coverage() {
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            Vec<u32> as input => String {
+                self.make_from_iter(input).collect()
+            }
+            u32 as input => String {
+                format!("{};", input)
+            }
+            (u32, u32) as (a, b) => String {
+                format!("{}{}", self.make_from(a) as String, self.make_from(b) as String)
+            }
+            bool as input => String {
+                format!("{}", input)
+            }
+        }
+    }
+
+    let mut coverage = Coverage::for_maker::<TestMaker>();
+    assert_eq!(coverage.never_run().len(), 4);
+    // Nothing is counted outside of `record`.
+    TestMaker.make_from(vec![1, 2, 3]);
+    assert_eq!(coverage.record(|| TestMaker.make_from(vec![1, 2, 3])), "1;2;3;");
+    assert_eq!(coverage.record(|| TestMaker.make_from((4, 5))), "4;5;");
+
+    let counts: Vec<(String, RuleCount)> = coverage
+        .counts()
+        .iter()
+        .map(|(rule, count)| (rule.to_string(), *count))
+        .collect();
+    assert_eq!(
+        counts,
+        vec![
+            ("(u32, u32) => String".into(), RuleCount { calls: 1, from_iter: 0 }),
+            ("Vec<u32> => String".into(), RuleCount { calls: 1, from_iter: 0 }),
+            ("bool => String".into(), RuleCount { calls: 0, from_iter: 0 }),
+            ("u32 => String".into(), RuleCount { calls: 5, from_iter: 3 }),
+        ]
+    );
+    assert_eq!(coverage.count(coverage.never_run()[0]).calls, 0);
+    assert_eq!(
+        coverage.to_string(),
+        concat!(
+            "3 of 4 rules were run:\n",
+            "       1 calls (0 from make_from_iter)  (u32, u32) => String\n",
+            "       1 calls (0 from make_from_iter)  Vec<u32> => String\n",
+            "       5 calls (3 from make_from_iter)  u32 => String\n",
+            "1 rules were never run:\n",
+            "  bool => String\n",
+        )
+    );
+}
+
+#[test]
+fn test_nested_rule_coverage() {
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            u32 as input => String {
+                format!("{};", input)
+            }
+            bool as input => String {
+                format!("{}", input)
+            }
+        }
+    }
+
+    let mut outer = Coverage::for_maker::<TestMaker>();
+    let mut inner = Coverage::for_maker::<TestMaker>();
+    outer.record(|| {
+        TestMaker.make_from(1);
+        inner.record(|| TestMaker.make_from(true));
+        // A panic in a nested recording doesn't stop the outer one.
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            inner.record(|| -> String { panic!("oops") })
+        }));
+        assert!(result.is_err());
+        TestMaker.make_from(2)
+    });
+    assert!(!coverage::recording());
+    let calls = |c: &Coverage| c.counts().iter().map(|(_, count)| count.calls).collect::<Vec<_>>();
+    assert_eq!(calls(&inner), vec![1, 0]);
+    assert_eq!(calls(&outer), vec![1, 2]);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_span_for_each_rule() {
+    use std::sync::{Arc, Mutex};
+    use tracing::span;
+
+    struct SpanNames(Arc<Mutex<Vec<String>>>);
+    impl tracing::Subscriber for SpanNames {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(format!("{}: {}", span.metadata().target(), span.metadata().name()));
+            span::Id::from_u64(names.len() as u64)
+        }
+        fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+        fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+        fn event(&self, _event: &tracing::Event<'_>) {}
+        fn enter(&self, _span: &span::Id) {}
+        fn exit(&self, _span: &span::Id) {}
+    }
+
+    struct TestMaker;
+    define_codemaker_rules! {
+        TestMaker as self {
+            Vec<u32> as input => String {
+                self.make_from_iter(input).collect()
+            }
+            u32 as input => String {
+                format!("{};", input)
+            }
+        }
+    }
+    define_stateless_codemaker! {
+        StatelessMaker {
+            bool as input => String {
+                format!("{}", input)
+            }
+        }
+    }
+
+    let names = Arc::new(Mutex::new(vec![]));
+    tracing::subscriber::with_default(SpanNames(names.clone()), || {
+        assert_eq!(TestMaker.make_from(vec![1, 2]), "1;2;");
+        assert_eq!(<StatelessMaker as StatelessCodeMakerRule<bool, String>>::make_from(true), "true");
+    });
+    assert_eq!(
+        *names.lock().unwrap(),
+        vec![
+            "codemaker::rules: Vec<u32> => String",
+            "codemaker::rules: u32 => String",
+            "codemaker::rules: u32 => String",
+            "codemaker::rules: bool => String",
+        ]
+    );
+}
+
+#[test]
+fn test_choose_rules_at_runtime() {
+    type Constants = Vec<(String, u32)>;
+    type Styles = RuleRegistry<dyn for<'a> CodeMakerRule<&'a Constants, String>>;
+    struct GlobalsStyle;
+    struct EnumStyle {
+        class_name: String,
+    }
+    struct TestMaker {
+        styles: Styles,
+        style: String,
+    }
+
+    define_codemaker_rules! {
+        GlobalsStyle as self {
+            &Constants as input => String {
+                self.make_from_iter(input).collect()
+            }
+            &(String, u32) as (name, value) => String {
+                format!("{} = {}\n", name, value)
+            }
+        }
+    }
+    define_codemaker_rules! {
+        EnumStyle as self {
+            &Constants as input => String {
+                let mut out = format!("class {}(enum.Enum):\n", self.class_name);
+                out.extend(self.make_from_iter(input));
+                out
+            }
+            &(String, u32) as (name, value) => String {
+                format!("    {} = {}\n", name, value)
+            }
+        }
+    }
+    define_codemaker_rules! {
+        TestMaker as self {
+            &Constants as input => Result<String, UnknownRuleSet> {
+                Ok(self.styles.get(&self.style)?.make_from(input))
+            }
+        }
+    }
+
+    let mut maker = TestMaker {
+        styles: Styles::new()
+            .with_rule_set("globals", Box::new(GlobalsStyle))
+            .with_rule_set("enum", Box::new(EnumStyle { class_name: "Status".into() })),
+        style: "globals".into(),
+    };
+    maker.styles.insert("upper", Box::new(FnRule(|c: &Constants| c[0].0.to_uppercase())));
+    let input = vec![("OK".to_string(), 200), ("CREATED".to_string(), 201)];
+
+    assert_eq!(maker.make_from(&input).unwrap(), "OK = 200\nCREATED = 201\n");
+    maker.style = "enum".into();
+    assert_eq!(
+        maker.make_from(&input).unwrap(),
+        "class Status(enum.Enum):\n    OK = 200\n    CREATED = 201\n"
+    );
+    maker.style = "upper".into();
+    assert_eq!(maker.make_from(&input).unwrap(), "OK");
+    maker.style = "constants".into();
+    let err = maker.make_from(&input).unwrap_err();
+    assert_eq!(err.name(), "constants");
+    assert_eq!(
+        err.to_string(),
+        "unknown rule set \"constants\", expected one of: enum, globals, upper"
+    );
+    assert!(maker.styles.contains("enum"));
+}
+
+#[test]
+fn test_compose_rules_with_combinators() {
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            &str as input => Vec<String> {
+                input.split(',').map(|s| s.trim().to_string()).collect()
+            }
+        }
+    }
+
+    // Small rules that can each be tested on their own.
+    let number = FnRule(|s: String| s.parse::<u32>().ok().map(|n| format!("{:#x}", n)));
+    let quoted = FnRule(|s: String| format!("{:?}", s));
+    let boolean = FnRule(|s: String| match s.as_str() {
+        "yes" => Some("True".to_string()),
+        "no" => Some("False".to_string()),
+        _ => None,
+    });
+    assert_eq!(number.make_from("12".to_string()), Some("0xc".to_string()));
+    assert_eq!(number.make_from("twelve".to_string()), None);
+    assert_eq!(quoted.make_from("twelve".to_string()), "\"twelve\"");
+
+    // Fall back to later rules when earlier ones decline.
+    let value = number.or(boolean).or_else(quoted);
+    assert_eq!(value.make_from("yes".to_string()), "True");
+    assert_eq!(value.make_from("maybe".to_string()), "\"maybe\"");
+
+    // Skip empty inputs, and apply to each of the values split out by the maker.
+    let values = value.filter(|s: &String| !s.is_empty()).flat_map();
+    let rule = CodeMakerRuleExt::<&str, Vec<String>>::by_ref(&TestMaker).then(values);
+    assert_eq!(
+        rule.make_from("1, , no, hello, 255"),
+        vec!["0x1", "False", "\"hello\"", "0xff"]
+    );
+}
+
+#[test]
+fn test_assert_makes_for_individual_rules() {
+    struct TestMaker;
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            &(u32, String) as (code, name) => String {
+                format!("{} = {}\n", name, code)
+            }
+            &(u32, String) as (code, _) => u32 {
+                *code
+            }
+            &str as name => Vec<String> {
+                vec![name.to_uppercase(), name.to_lowercase()]
+            }
+        }
+    }
+    define_stateless_codemaker! {
+        StatelessMaker {
+            u32 as input => String {
+                format!("{:#x}", input)
+            }
+            u32 as input => Option<String> {
+                if input > 0 { Some(input.to_string()) } else { None }
+            }
+        }
+    }
+
+    let maker = TestMaker;
+    assert_makes!(maker => String, &(200, "OK".into()) => "OK = 200\n");
+    assert_makes!(&maker, "Ok" => "OKok");
+    assert_makes!(stateless StatelessMaker => String, 255 => "0xff");
+    assert_makes!(stateless StatelessMaker => Option<String>, 0 => "");
+    assert_makes_eq!(maker, &(201, "CREATED".into()) => 201);
+    assert_makes_eq!(maker, "Ok" => vec!["OK".to_string(), "ok".to_string()]);
+    assert_makes_eq!(stateless StatelessMaker, 12 => Some("12".to_string()));
+
+    let err = std::panic::catch_unwind(|| {
+        assert_makes!(maker => String, &(200, "OK".into()) => "OKAY = 200\n");
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<String>().unwrap(),
+        "rule made unexpected output from `&(200, \"OK\".into())`:\n\
+         --- expected\n+++ actual\n-OKAY = 200\n+OK = 200\n \n"
+    );
+    assert_eq!(
+        diff_lines("a\nb\nc", "a\nc\nd"),
+        "--- expected\n+++ actual\n a\n-b\n c\n+d\n"
+    );
+}
+
+#[test]
+fn test_incremental_generation_reuses_unchanged_output() {
+    use std::cell::Cell;
+
+    struct TextFile(std::path::PathBuf, String);
+    impl OutputFile for TextFile {
+        fn path(&self) -> &std::path::Path {
+            &self.0
+        }
+        fn write_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+            writer.write_all(self.1.as_bytes())
+        }
+    }
+    struct TextFiles(Vec<TextFile>);
+    impl OutputFileSet for TextFiles {
+        type OutputFile = TextFile;
+        fn files(&self) -> Vec<&TextFile> {
+            self.0.iter().collect()
+        }
+    }
+
+    struct TestMaker {
+        runs: Cell<usize>,
+    }
+
+    define_codemaker_rules! {
+        TestMaker as self {
+            &(String, Vec<u32>) as (name, values) => TextFiles {
+                self.runs.set(self.runs.get() + 1);
+                let body: String = values.iter().map(|v| format!("{}\n", v)).collect();
+                TextFiles(vec![TextFile(format!("{}.txt", name).into(), body)])
+            }
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("codemaker-incremental-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let maker = TestMaker { runs: Cell::new(0) };
+    let run_with_options = |inputs: &[(String, Vec<u32>)], version: &str, options: &RenderOptions| {
+        let mut cache = IncrementalCache::open(&dir, version).unwrap();
+        let mut output = CachedFiles::default();
+        for input in inputs {
+            output.extend(cache.make(&input.0, input, options, |i| maker.make_from(i)).unwrap());
+        }
+        cache.save().unwrap();
+        let rendered: Vec<(String, String)> = output
+            .files()
+            .into_iter()
+            .map(|f| {
+                (
+                    f.path().display().to_string(),
+                    String::from_utf8(f.contents().to_vec()).unwrap(),
+                )
+            })
+            .collect();
+        (rendered, cache.reused(), cache.regenerated())
+    };
+    let run = |inputs: &[(String, Vec<u32>)], version: &str| {
+        run_with_options(inputs, version, &RenderOptions::new())
+    };
+
+    let mut inputs = vec![("a".to_string(), vec![1, 2]), ("b".to_string(), vec![3])];
+    let (files, reused, regenerated) = run(&inputs, "v1");
+    assert_eq!(files, vec![("a.txt".into(), "1\n2\n".into()), ("b.txt".into(), "3\n".into())]);
+    assert_eq!((reused, regenerated, maker.runs.get()), (0, 2, 2));
+
+    // Only the changed subtree is made again, and the rest comes from the cache.
+    inputs[1].1.push(4);
+    let (files, reused, regenerated) = run(&inputs, "v1");
+    assert_eq!(files, vec![("a.txt".into(), "1\n2\n".into()), ("b.txt".into(), "3\n4\n".into())]);
+    assert_eq!((reused, regenerated, maker.runs.get()), (1, 1, 3));
+
+    // A new version of the generator invalidates everything.
+    let (_, reused, regenerated) = run(&inputs, "v2");
+    assert_eq!((reused, regenerated, maker.runs.get()), (0, 2, 5));
+
+    // So does rendering with different options, and the files are rendered with them.
+    let crlf = RenderOptions::new().line_ending(LineEnding::CrLf);
+    let (files, reused, regenerated) = run_with_options(&inputs, "v2", &crlf);
+    assert_eq!(
+        files,
+        vec![("a.txt".into(), "1\r\n2\r\n".into()), ("b.txt".into(), "3\r\n4\r\n".into())]
+    );
+    assert_eq!((reused, regenerated, maker.runs.get()), (0, 2, 7));
+    let (_, reused, regenerated) = run(&inputs, "v2");
+    assert_eq!((reused, regenerated, maker.runs.get()), (0, 2, 9));
+
+    // Removed subtrees are forgotten, along with the contents of their files.
+    let (_, reused, _) = run(&inputs[..1], "v2");
+    assert_eq!(reused, 1);
+    assert_eq!(std::fs::read_dir(dir.join("blobs")).unwrap().count(), 1);
+
+    // Keys must be unique within a run.
+    let mut cache = IncrementalCache::open(&dir, "v2").unwrap();
+    cache.make("a", &inputs[0], &RenderOptions::new(), |i| maker.make_from(i)).unwrap();
+    let err = cache.make("a", &inputs[0], &RenderOptions::new(), |i| maker.make_from(i)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    // Cached contents that don't match their hash are made again rather than trusted.
+    for blob in std::fs::read_dir(dir.join("blobs")).unwrap() {
+        std::fs::write(blob.unwrap().path(), "1\n").unwrap();
+    }
+    let (files, reused, regenerated) = run(&inputs[..1], "v2");
+    assert_eq!(files, vec![("a.txt".into(), "1\n2\n".into())]);
+    assert_eq!((reused, regenerated), (0, 1));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// A minimal output format for tests that need to write files.
+struct TestFile(std::path::PathBuf, String);
+
+impl OutputFile for TestFile {
+    fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+    fn write_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(self.1.as_bytes())
+    }
+}
+
+struct TestFiles(Vec<TestFile>);
+
+impl OutputFileSet for TestFiles {
+    type OutputFile = TestFile;
+    fn files(&self) -> Vec<&TestFile> {
+        self.0.iter().collect()
+    }
+}
+
+#[test]
+fn test_build_script_helpers() {
+    let out_dir = std::env::temp_dir().join(format!("codemaker-build-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&out_dir);
+    let input = out_dir.join("api.txt");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    std::fs::write(&input, "hello").unwrap();
+
+    let output = TestFiles(vec![
+        TestFile("types.rs".into(), "pub struct Thing;\n".into()),
+        TestFile("client/mod.rs".into(), "pub fn get() {}\n".into()),
+        TestFile("client/v1-beta.rs".into(), "pub fn put() {}\n".into()),
+        TestFile("README.md".into(), "# Generated\n".into()),
+    ]);
+    let mut build = build::Build::with_out_dir(&out_dir, vec![]);
+    assert_eq!(build.read_input(&input).unwrap(), "hello");
+    build.warning("2 problems:\nfirst\nsecond");
+    let paths = build.write_output(&output).unwrap();
+    assert_eq!(paths.len(), 4);
+    assert_eq!(std::fs::read_to_string(out_dir.join("client/mod.rs")).unwrap(), "pub fn get() {}\n");
+    let stub_path = build.write_include_stub("generated.rs", &output).unwrap();
+    let stub = std::fs::read_to_string(stub_path).unwrap();
+    let out = |p: &str| format!("{:?}", out_dir.join(p).to_string_lossy());
+    assert_eq!(
+        stub,
+        format!(
+            "// @generated by codemaker; do not edit.\n\
+             pub mod client {{\n    \
+             include!({});\n    \
+             pub mod v1_beta {{ include!({}); }}\n\
+             }}\n\
+             pub mod types {{ include!({}); }}\n",
+            out("client/mod.rs"),
+            out("client/v1-beta.rs"),
+            out("types.rs"),
+        )
+    );
+    assert_eq!(
+        String::from_utf8(build.into_directives()).unwrap(),
+        format!(
+            "cargo:rerun-if-changed={}\n\
+             cargo:warning=2 problems:\n\
+             cargo:warning=first\n\
+             cargo:warning=second\n",
+            input.display()
+        )
+    );
+    std::fs::remove_dir_all(&out_dir).unwrap();
+}
+
+#[test]
+fn test_build_script_output_paths() {
+    let out_dir = std::env::temp_dir().join(format!("codemaker-build-paths-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&out_dir);
+    let crlf = RenderOptions::new().line_ending(LineEnding::CrLf);
+    let mut build = build::Build::with_out_dir(&out_dir, vec![]).render_options(crlf);
+
+    let output = TestFiles(vec![TestFile("lib.rs".into(), "pub struct Thing;\n".into())]);
+    build.write_output(&output).unwrap();
+    assert_eq!(std::fs::read_to_string(out_dir.join("lib.rs")).unwrap(), "pub struct Thing;\r\n");
+
+    // Paths that would escape the output directory are errors, and nothing gets written.
+    for bad_path in &["/tmp/evil.rs", "../evil.rs"] {
+        let output = TestFiles(vec![
+            TestFile("good.rs".into(), "\n".into()),
+            TestFile((*bad_path).into(), "\n".into()),
+        ]);
+        let err = build.write_output(&output).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(build.include_stub(&output).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(!out_dir.join("good.rs").exists());
+    }
+    let err = build.write_include_stub("../stub.rs", &output).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    std::fs::remove_dir_all(&out_dir).unwrap();
+}
+
+#[test]
+fn test_include_stub_module_names() {
+    let file = |path: &str| TestFile(path.into(), String::new());
+    let output = TestFiles(vec![
+        file("mod.rs"),
+        file("type.rs"),
+        file("crate/self.rs"),
+        file("super/Self.rs"),
+        file("_.rs"),
+        file("gen/try.rs"),
+        file("2fa.rs"),
+    ]);
+    let build = build::Build::with_out_dir("/out", vec![]);
+    let include = |p: &str| format!("include!({:?});", std::path::Path::new("/out").join(p).to_string_lossy());
+    assert_eq!(
+        build.include_stub(&output).unwrap(),
+        format!(
+            "// @generated by codemaker; do not edit.\n\
+             {}\n\
+             pub mod _2fa {{ {} }}\n\
+             pub mod __ {{ {} }}\n\
+             pub mod crate_ {{\n    \
+             pub mod self_ {{ {} }}\n\
+             }}\n\
+             pub mod r#gen {{\n    \
+             pub mod r#try {{ {} }}\n\
+             }}\n\
+             pub mod r#type {{ {} }}\n\
+             pub mod super_ {{\n    \
+             pub mod Self_ {{ {} }}\n\
+             }}\n",
+            include("mod.rs"),
+            include("2fa.rs"),
+            include("_.rs"),
+            include("crate/self.rs"),
+            include("gen/try.rs"),
+            include("type.rs"),
+            include("super/Self.rs"),
+        )
+    );
+}
+
+#[test]
+fn test_make_output_for_several_targets() {
+    struct MultiMaker {
+        targets: Targets,
+    }
+
+    define_codemaker_rules! {
+        MultiMaker as self {
+            &[(&str, u32)] as codes => Targets {
+                // Both targets share the same naming of the input.
+                let names: Vec<String> = codes.iter().map(|(name, _)| name.to_uppercase()).collect();
+                let python = codes.iter().zip(&names).map(|((_, code), name)| format!("{} = {}\n", name, code));
+                let typescript = codes.iter().zip(&names).map(|((_, code), name)| format!("export const {} = {};\n", name, code));
+                let c = names.iter().map(|name| format!("#define {}\n", name));
+                self.targets
+                    .clone()
+                    .with_target("python", TestFiles(vec![TestFile("codes.py".into(), python.collect())]))
+                    .with_target("typescript", TestFiles(vec![TestFile("codes.ts".into(), typescript.collect())]))
+                    .with_target("c", TestFile("codes.h".into(), c.collect()))
+            }
+        }
+    }
+
+    let maker = MultiMaker {
+        targets: Targets::new()
+            .with_options("python", TargetOptions::new().subdirectory("py/status"))
+            .with_options("c", TargetOptions::new().enabled(false)),
+    };
+    let output = maker.make_from(&[("ok", 200), ("not_found", 404)][..]);
+    assert_eq!(output.names(), vec!["python", "typescript"]);
+    let paths: Vec<_> = output.files().into_iter().map(|f| (f.target(), f.path())).collect();
+    assert_eq!(
+        paths,
+        vec![
+            ("python", std::path::Path::new("py/status/codes.py")),
+            ("typescript", std::path::Path::new("typescript/codes.ts")),
+        ]
+    );
+    assert_eq!(output.files_for("c").len(), 0);
+
+    let dir = std::env::temp_dir().join(format!("codemaker-targets-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    output.write_into_dir(&dir).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.join("py/status/codes.py")).unwrap(),
+        "OK = 200\nNOT_FOUND = 404\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("typescript/codes.ts")).unwrap(),
+        "export const OK = 200;\nexport const NOT_FOUND = 404;\n"
+    );
+
+    // Subdirectories that would escape the output directory fail when written, before
+    // anything has been written.
+    for subdirectory in &["../escaped", "/tmp/escaped"] {
+        let output = Targets::new()
+            .with_options("python", TargetOptions::new().subdirectory(*subdirectory))
+            .with_target("typescript", TestFile("early.ts".into(), String::new()))
+            .with_target("python", TestFile("codes.py".into(), String::new()));
+        let err = output.write_into_dir(dir.join("escape")).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(!dir.join("escape").exists());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_write_targets_looks_up_files_once() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct CountingFiles(Vec<TestFile>, Rc<Cell<usize>>);
+
+    impl OutputFileSet for CountingFiles {
+        type OutputFile = TestFile;
+        fn files(&self) -> Vec<&TestFile> {
+            self.1.set(self.1.get() + 1);
+            self.0.iter().collect()
+        }
+    }
+
+    let calls = Rc::new(Cell::new(0));
+    let files = |ext: &str| {
+        (0..10)
+            .map(|i| TestFile(format!("{}.{}", i, ext).into(), format!("{}\n", i)))
+            .collect::<Vec<_>>()
+    };
+    let output = Targets::new()
+        .with_options("dos", TargetOptions::new().render_options(RenderOptions::new().line_ending(LineEnding::CrLf)))
+        .with_target("dos", CountingFiles(files("bat"), Rc::clone(&calls)))
+        .with_target("unix", CountingFiles(files("sh"), Rc::clone(&calls)));
+    calls.set(0);
+
+    let dir = std::env::temp_dir().join(format!("codemaker-targets-once-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    output.write_into_dir(&dir).unwrap();
+    assert_eq!(calls.get(), 2);
+    assert_eq!(std::fs::read_to_string(dir.join("dos/9.bat")).unwrap(), "9\r\n");
+    assert_eq!(std::fs::read_to_string(dir.join("unix/9.sh")).unwrap(), "9\n");
+
+    calls.set(0);
+    output.write_into_dir_with_source_maps(&dir, &RenderOptions::new()).unwrap();
+    assert_eq!(calls.get(), 2);
+    assert!(dir.join("unix/0.sh.map.json").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_render_options() {
+    use std::io::Write;
+
+    let write = |options: &RenderOptions, text: &str| {
+        let mut writer = options.writer(vec![]);
+        // Write in awkward pieces, to check that lines are put back together.
+        for chunk in text.as_bytes().chunks(3) {
+            writer.write_all(chunk).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    };
+    let text = "a = 1  \nb = 2\t\r\n\nc = 3";
+    // By default, lines keep whichever line ending they were written with.
+    assert_eq!(write(&RenderOptions::default(), text), text);
+    let options = RenderOptions::new().trim_trailing_whitespace(true).final_newline(true);
+    assert_eq!(write(&options, "a  \r\nb\t\r\nc"), "a\r\nb\r\nc\r\n");
+    let options = RenderOptions::new().line_ending(LineEnding::Lf);
+    assert_eq!(write(&options, text), "a = 1  \nb = 2\t\n\nc = 3");
+    let options = RenderOptions::new()
+        .line_ending(LineEnding::CrLf)
+        .trim_trailing_whitespace(true)
+        .final_newline(true);
+    assert_eq!(write(&options, text), "a = 1\r\nb = 2\r\n\r\nc = 3\r\n");
+    assert_eq!(write(&options, ""), "");
+    assert_eq!(RenderOptions::new().indentation(2), "        ");
+    assert_eq!(RenderOptions::new().indent(IndentStyle::Spaces(2)).indentation(2), "    ");
+    assert_eq!(RenderOptions::new().indent(IndentStyle::Tabs).indentation(2), "\t\t");
+
+    // Options given for a particular target take precedence over those for the whole output.
+    let output = Targets::new()
+        .with_options("dos", TargetOptions::new().render_options(RenderOptions::new().line_ending(LineEnding::CrLf)))
+        .with_target("dos", TestFile("a.txt".into(), "one\ntwo".into()))
+        .with_target("unix", TestFile("b.txt".into(), "one\ntwo".into()));
+    let rendered: Vec<String> = output
+        .files()
+        .into_iter()
+        .map(|f| {
+            let mut buf = vec![];
+            f.write_into_with_options(&mut buf, &RenderOptions::new().final_newline(true)).unwrap();
+            String::from_utf8(buf).unwrap()
+        })
+        .collect();
+    assert_eq!(rendered, vec!["one\r\ntwo", "one\ntwo\n"]);
+}
+
+#[test]
+fn test_lay_out_docs_within_width() {
+    let call = |name: &str, args: Vec<Doc>| {
+        Doc::text(&format!("{}(", name))
+            .append(
+                Doc::softline()
+                    .append(Doc::join(args, Doc::text(",").append(Doc::line())))
+                    .append(Doc::if_break(Doc::text(","), Doc::nil()))
+                    .nest(),
+            )
+            .append(Doc::softline())
+            .append(Doc::text(")"))
+            .group()
+    };
+    let doc = call(
+        "outer",
+        vec![
+            Doc::text("first"),
+            call("inner", vec![Doc::text("a"), Doc::text("b")]),
+            Doc::text("last"),
+        ],
+    );
+    let render = |width: usize, indent: IndentStyle| doc.pretty(&RenderOptions::new().max_width(width).indent(indent));
+    assert_eq!(render(40, IndentStyle::Spaces(4)), "outer(first, inner(a, b), last)");
+    // Outer groups are broken first, leaving inner ones on a single line where they fit.
+    assert_eq!(
+        render(20, IndentStyle::Spaces(4)),
+        "outer(\n    first,\n    inner(a, b),\n    last,\n)"
+    );
+    assert_eq!(
+        render(10, IndentStyle::Spaces(2)),
+        "outer(\n  first,\n  inner(\n    a,\n    b,\n  ),\n  last,\n)"
+    );
+    assert_eq!(render(20, IndentStyle::Tabs), "outer(\n\tfirst,\n\tinner(a, b),\n\tlast,\n)");
+
+    // Hard line breaks are always taken, and blank lines aren't indented.
+    let doc = Doc::text("a")
+        .append(Doc::concat(vec![Doc::hardline(), Doc::hardline(), Doc::text("b")]).nest())
+        .group();
+    assert_eq!(doc.render(), "a\n\n    b");
+}
+
+#[test]
+fn test_post_process_written_files() {
+    let output = TestFiles(vec![
+        TestFile("codes.py".into(), "ok = 200\n".into()),
+        TestFile("codes.txt".into(), "ok\n".into()),
+        TestFile("bad.py".into(), "BAD\n".into()),
+    ]);
+    let post = PostProcess::new()
+        .transform("py", |_, text| {
+            if text.starts_with("BAD") {
+                Err("refusing to format".into())
+            } else {
+                Ok(text.to_uppercase())
+            }
+        })
+        .transform("*", |path, text| Ok(format!("# {}\n{}", path.display(), text)));
+    let dir = std::env::temp_dir().join(format!("codemaker-post-process-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let failures = output
+        .write_into_dir_with_post_process(&dir, &RenderOptions::default(), &post)
+        .unwrap();
+    assert_eq!(
+        failures.iter().map(|f| f.to_string()).collect::<Vec<_>>(),
+        vec!["bad.py: transform failed: refusing to format"]
+    );
+    assert_eq!(std::fs::read_to_string(dir.join("codes.py")).unwrap(), "# codes.py\nOK = 200\n");
+    assert_eq!(std::fs::read_to_string(dir.join("codes.txt")).unwrap(), "# codes.txt\nok\n");
+    assert!(!dir.join("bad.py").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // External commands get the file on stdin, and any errors they print are reported.
+    if cfg!(unix) {
+        let post = PostProcess::new().command("txt", "tr", &["a-z", "A-Z"]);
+        let processed = post.process("codes.txt".as_ref(), b"ok\n".to_vec()).unwrap();
+        assert_eq!(processed, b"OK\n");
+        let post = PostProcess::new().command("txt", "sh", &["-c", "echo \"cannot format {path}\" >&2; exit 3"]);
+        let err = post.process("codes.txt".as_ref(), b"ok\n".to_vec()).unwrap_err();
+        assert_eq!(err.step(), "sh");
+        assert_eq!(err.message(), "exited with exit status: 3: cannot format codes.txt");
+        let post = PostProcess::new().command("txt", "codemaker-no-such-formatter", &[]);
+        let err = post.process("codes.txt".as_ref(), b"ok\n".to_vec()).unwrap_err();
+        assert!(err.message().starts_with("could not run codemaker-no-such-formatter: "));
+    }
+}
+
+#[test]
+fn test_stream_fragments_into_output_file() {
+    use std::cell::Cell;
+    use std::io::Write;
+
+    /// A writer that records how many fragments had been made at the time of each write.
+    struct Recorder<'a> {
+        made: &'a Cell<usize>,
+        writes: Vec<(usize, String)>,
+    }
+
+    impl Write for Recorder<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes.push((self.made.get(), String::from_utf8_lossy(buf).into_owned()));
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct RowMaker;
+
+    define_codemaker_rules! {
+        RowMaker as self {
+            &u32 as row => String {
+                format!("row {}\n", row)
+            }
+        }
+    }
+
+    let made = Cell::new(0);
+    let rows = [1, 2, 3];
+    let file = StreamingFile::new("table.txt", || {
+        RowMaker.make_from_iter(rows.iter()).inspect(|_| made.set(made.get() + 1))
+    });
+    let mut recorder = Recorder { made: &made, writes: vec![] };
+    file.write_into(&mut recorder).unwrap();
+    // Each row is written as soon as it's made, before making the next one.
+    assert_eq!(
+        recorder.writes,
+        vec![(1, "row 1\n".into()), (2, "row 2\n".into()), (3, "row 3\n".into())]
+    );
+
+    // The fragments are made afresh each time the file is written.
+    let mut buf = vec![];
+    file.write_into_with_options(&mut buf, &RenderOptions::new().line_ending(LineEnding::CrLf))
+        .unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), "row 1\r\nrow 2\r\nrow 3\r\n");
+    assert_eq!(made.get(), 6);
+}
+
+#[test]
+fn test_check_determinism() {
+    use std::cell::Cell;
+    use std::collections::HashMap;
+
+    struct TableMaker {
+        sorted: bool,
+    }
+
+    impl<'a> CodeMaker<'a> for TableMaker {
+        type Input = &'a [(String, u32)];
+        type Output = TestFiles;
+    }
+
+    define_codemaker_rules! {
+        TableMaker as self {
+            &[(String, u32)] as codes => TestFiles {
+                let codes: HashMap<&str, u32> = codes.iter().map(|(name, code)| (name.as_str(), *code)).collect();
+                let line = |(name, code): (&&str, &u32)| format!("{} = {}\n", name, code);
+                let body: String = if self.sorted {
+                    codes.iter_sorted().map(line).collect()
+                } else {
+                    codes.iter().map(line).collect()
+                };
+                TestFiles(vec![
+                    TestFile("codes.txt".into(), format!("# codes\n{}", body)),
+                    TestFile("about.txt".into(), "generated\n".into()),
+                ])
+            }
+        }
+    }
+
+    let codes: Vec<(String, u32)> = (0..26).map(|i| (format!("{}", (b'a' + i as u8) as char), i)).collect();
+    check_determinism(&TableMaker { sorted: true }, &codes[..]).unwrap();
+    let err = check_determinism(&TableMaker { sorted: false }, &codes[..]).unwrap_err();
+    assert_eq!(err.path(), std::path::Path::new("codes.txt"));
+    let line = err.line().unwrap();
+    assert!(line >= 2);
+    assert_ne!(err.first(), err.second());
+    assert!(err
+        .to_string()
+        .starts_with(&format!("codes.txt:{} differs between runs\n  first:  \"", line)));
+
+    struct CountingMaker {
+        runs: Cell<usize>,
+    }
+
+    impl<'a> CodeMaker<'a> for CountingMaker {
+        type Input = ();
+        type Output = TestFile;
+    }
+
+    define_codemaker_rules! {
+        CountingMaker as self {
+            () as _input => TestFile {
+                self.runs.set(self.runs.get() + 1);
+                TestFile(format!("run{}.txt", self.runs.get()).into(), String::new())
+            }
+        }
+    }
+
+    let err = check_determinism(&CountingMaker { runs: Cell::new(0) }, ()).unwrap_err();
+    assert_eq!(err.to_string(), "run1.txt was only made by the first run");
+    assert_eq!(err.line(), None);
+}
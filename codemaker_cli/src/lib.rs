@@ -0,0 +1,827 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! # A standard command line for `codemaker` generators.
+//!
+//! Every generator binary needs to find its input, decide where to put its output,
+//! and report what it did, and it's nice if they all do so in the same way. This crate
+//! wraps the generation step in a standard command line:
+//!
+//! ```ignore
+//! fn main() {
+//!     codemaker_cli::Cli::new("status-codes")
+//!         .about("Generate a Python module of HTTP status codes")
+//!         .default_input("status_codes.yaml")
+//!         .run(|opts| {
+//!             let codes: StatusCodes = opts.load_input()?;
+//!             Ok(StatusModuleMaker::default().make(&codes))
+//!         })
+//! }
+//! ```
+//!
+//! Which accepts the following options:
+//!
+//! ```text
+//!   -i, --input <PATH>     The input file to generate from
+//!   -o, --out-dir <DIR>    The directory to write output files into
+//!       --check            Check that the output files are up-to-date, without writing them
+//!       --dry-run          Report which output files would change, without writing them
+//!       --list-files       List the output files, without writing them
+//!       --stdout           Print the output to stdout, instead of writing files
+//!       --watch            Write the output files, then rewrite them whenever the input changes
+//!       --check-determinism
+//!                          Check that generating twice gives the same output, without writing it
+//!   -v, --verbose          Report more detail, may be repeated
+//!   -q, --quiet            Only report errors
+//!   -h, --help             Print this help message
+//! ```
+//!
+//! The process exits with one of the codes from [`Status`], so that CI scripts can tell
+//! the difference between stale output and a broken generator.
+//!
+//! When writing files, each one is streamed to disk as it's rendered, so output that's too
+//! large to hold in memory can be written from a [`codemaker::StreamingFile`]. Listing the
+//! files doesn't render them at all. The other modes, and writing with a
+//! [post-processing](Cli::post_process) pipeline, hold all of the rendered output in memory
+//! at once.
+//!
+//! In `--watch` mode, the generator is re-run whenever any of the files it read changes.
+//! To know which files those are, the generation step should load its input using
+//! [`Options::load_input`] or [`Options::load`], or report files that it reads by other
+//! means using [`Options::track_input`].
+//!
+//! With `--check-determinism`, the generator is run twice with different hash seeds, and any
+//! difference between the two outputs is reported; see [`codemaker::check_determinism`] for
+//! the kinds of nondeterminism that this can find. Changing the seeds is best-effort, so a
+//! pass only means that the two runs agreed, and is not proof that the output is deterministic.
+
+use codemaker::{OutputFile, OutputFileSet, PostProcess, RenderOptions};
+use codemaker_input::{InputError, InputLoader};
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(test)]
+mod tests;
+
+/// The error type for generation failures; anything that can be converted into this
+/// can be returned from the generation step using `?`.
+pub type Error = Box<dyn std::error::Error>;
+
+/// The outcome of running the command line, and the corresponding process exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Everything went fine, or `--check` found that the output is up-to-date.
+    Success = 0,
+    /// `--check` found that some output files are missing or out-of-date.
+    OutOfDate = 1,
+    /// The command-line arguments were invalid.
+    Usage = 2,
+    /// The generation step failed, e.g. because the input was invalid.
+    GenerationFailed = 3,
+    /// The output could not be read or written.
+    Io = 4,
+    /// `--check-determinism` found that the output differs from run to run.
+    ///
+    /// Since the check is best-effort, a [`Status::Success`] from it doesn't mean the opposite.
+    Nondeterministic = 5,
+}
+
+impl Status {
+    /// The exit code for the process.
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+/// What to do with the generated output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Write the output files into the output directory.
+    Write,
+    /// Check that the output files in the output directory are up-to-date.
+    Check,
+    /// Report which output files would change, without writing them.
+    DryRun,
+    /// List the paths of the output files.
+    ListFiles,
+    /// Print the contents of the output files to stdout.
+    Stdout,
+    /// Write the output files, then rewrite them whenever the input changes.
+    Watch,
+    /// Generate the output twice, and check that both runs give the same output.
+    ///
+    /// This is a best-effort check: passing doesn't prove that the output is deterministic.
+    CheckDeterminism,
+}
+
+/// The options given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// The input file to generate from.
+    pub input: PathBuf,
+    /// The directory to write output files into.
+    pub out_dir: PathBuf,
+    /// What to do with the generated output.
+    pub mode: Mode,
+    /// How much to report: negative for quiet, zero by default, positive for verbose.
+    pub verbosity: i32,
+    /// How to format the output files.
+    pub render: RenderOptions,
+    loader: RefCell<InputLoader>,
+}
+
+impl Options {
+    pub fn new<P: Into<PathBuf>, D: Into<PathBuf>>(input: P, out_dir: D, mode: Mode) -> Self {
+        Options {
+            input: input.into(),
+            out_dir: out_dir.into(),
+            mode,
+            verbosity: 0,
+            render: RenderOptions::default(),
+            loader: RefCell::new(InputLoader::new()),
+        }
+    }
+
+    /// Load the input file, using its extension to determine the format.
+    pub fn load_input<T: DeserializeOwned>(&self) -> Result<T, InputError> {
+        self.load(&self.input)
+    }
+
+    /// Load the main input file along with any files that it includes, merged into one value.
+    ///
+    /// See [`InputLoader::load_set`](codemaker_input::InputLoader::load_set) for how included
+    /// files are merged. Every included file is watched for changes in `--watch` mode.
+    pub fn load_input_set<T: DeserializeOwned>(&self) -> Result<T, InputError> {
+        self.loader.borrow_mut().load_set(&self.input)
+    }
+
+    /// Load some other input file, such as one included by the main input.
+    pub fn load<T: DeserializeOwned, P: AsRef<Path>>(&self, path: P) -> Result<T, InputError> {
+        self.loader.borrow_mut().load(path)
+    }
+
+    /// Record that an input file was read by some other means, so that it will be watched for changes.
+    pub fn track_input<P: AsRef<Path>>(&self, path: P) {
+        self.loader.borrow_mut().track(path)
+    }
+
+    /// The input files that were read by the generation step.
+    ///
+    /// This always includes the main input file, even if it wasn't loaded using [`Options::load_input`].
+    pub fn files_read(&self) -> Vec<PathBuf> {
+        let mut files = vec![self.input.clone()];
+        for path in self.loader.borrow().files_read() {
+            if !files.contains(path) {
+                files.push(path.clone());
+            }
+        }
+        files
+    }
+}
+
+/// A standard command line for a generator.
+pub struct Cli {
+    name: String,
+    about: Option<String>,
+    default_input: Option<PathBuf>,
+    default_out_dir: PathBuf,
+    poll_interval: Duration,
+    render: RenderOptions,
+    post_process: PostProcess,
+}
+
+impl Cli {
+    /// A command line for the generator with the given program name.
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Cli {
+            name: name.into(),
+            about: None,
+            default_input: None,
+            default_out_dir: PathBuf::from("."),
+            poll_interval: Duration::from_millis(250),
+            render: RenderOptions::default(),
+            post_process: PostProcess::new(),
+        }
+    }
+
+    /// A short description of the generator, for the help message.
+    pub fn about<T: Into<String>>(mut self, about: T) -> Self {
+        self.about = Some(about.into());
+        self
+    }
+
+    /// The input file to use if `--input` isn't given; otherwise `--input` is required.
+    pub fn default_input<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.default_input = Some(path.into());
+        self
+    }
+
+    /// The output directory to use if `--out-dir` isn't given; the default is the current directory.
+    pub fn default_out_dir<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.default_out_dir = path.into();
+        self
+    }
+
+    /// How often to check the input files for changes in `--watch` mode.
+    ///
+    /// Changes are also debounced by this interval, so that the generator isn't re-run
+    /// until the input files have stopped changing.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// How to format the output files, for generators with a house style.
+    pub fn render_options(mut self, options: RenderOptions) -> Self {
+        self.render = options;
+        self
+    }
+
+    /// Run each output file through a post-processing pipeline, such as an external formatter.
+    ///
+    /// The pipeline is run before comparing the output with what's on disk, so `--check`
+    /// expects the files to have been post-processed too. Files that fail to be processed
+    /// are reported individually, and if any of them fail then none of the output is written.
+    /// Since the pipeline works on the full
+    /// contents of each file, all of the output is rendered into memory before any of it
+    /// is written.
+    pub fn post_process(mut self, post_process: PostProcess) -> Self {
+        self.post_process = post_process;
+        self
+    }
+
+    /// Run the command line using the process arguments, then exit the process.
+    ///
+    /// The `generate` function is given the parsed [`Options`] and should make the output,
+    /// which is then handled according to the mode requested on the command line.
+    pub fn run<S, F>(&self, generate: F) -> !
+    where
+        S: OutputFileSet,
+        F: FnMut(&Options) -> Result<S, Error>,
+    {
+        let status = self.run_with_args(
+            std::env::args().skip(1),
+            &mut std::io::stdout(),
+            &mut std::io::stderr(),
+            generate,
+        );
+        std::process::exit(status.code())
+    }
+
+    /// Run the command line with the given arguments and output streams, returning the status.
+    ///
+    /// The arguments should not include the program name.
+    pub fn run_with_args<A, S, F>(
+        &self,
+        args: A,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+        mut generate: F,
+    ) -> Status
+    where
+        A: IntoIterator,
+        A::Item: Into<String>,
+        S: OutputFileSet,
+        F: FnMut(&Options) -> Result<S, Error>,
+    {
+        let opts = match self.parse_args(args) {
+            Ok(Some(opts)) => opts,
+            Ok(None) => {
+                let _ = stdout.write_all(self.help().as_bytes());
+                return Status::Success;
+            }
+            Err(msg) => {
+                let _ = writeln!(stderr, "{}: {}", self.name, msg);
+                let _ = writeln!(stderr, "Try '{} --help' for more information.", self.name);
+                return Status::Usage;
+            }
+        };
+        if opts.mode == Mode::Watch {
+            return self.watch(&opts, stdout, stderr, generate, None, None);
+        }
+        if opts.mode == Mode::CheckDeterminism {
+            return self.check_determinism(&opts, stderr, generate);
+        }
+        let output = match generate(&opts) {
+            Ok(output) => output,
+            Err(e) => {
+                let _ = writeln!(stderr, "{}: error: {}", self.name, e);
+                return Status::GenerationFailed;
+            }
+        };
+        let mut reporter = Reporter {
+            name: &self.name,
+            verbosity: opts.verbosity,
+            stderr,
+        };
+        match handle_output(&opts, &output, &self.post_process, stdout, &mut reporter) {
+            Ok(status) => status,
+            Err(e) => {
+                reporter.error(&e);
+                Status::Io
+            }
+        }
+    }
+
+    /// Generate the output, then regenerate it whenever the input files change.
+    ///
+    /// Errors are reported without stopping the watcher, so that they can be fixed by
+    /// editing the input. This only returns if the input files can't be checked for
+    /// changes, after `max_runs` runs of the generator if given, or if no change is seen
+    /// before the `deadline` if given.
+    pub(crate) fn watch<S, F>(
+        &self,
+        opts: &Options,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+        mut generate: F,
+        max_runs: Option<usize>,
+        deadline: Option<Instant>,
+    ) -> Status
+    where
+        S: OutputFileSet,
+        F: FnMut(&Options) -> Result<S, Error>,
+    {
+        let mut runs = 0;
+        loop {
+            // Start afresh, since the set of input files might change from run to run.
+            opts.loader.replace(InputLoader::new());
+            let result = generate(opts);
+            let mut reporter = Reporter {
+                name: &self.name,
+                verbosity: opts.verbosity,
+                stderr: &mut *stderr,
+            };
+            match result {
+                Ok(output) => {
+                    if let Err(e) = handle_output(opts, &output, &self.post_process, stdout, &mut reporter) {
+                        reporter.error(&e);
+                    }
+                }
+                Err(e) => reporter.error(&*e),
+            }
+            runs += 1;
+            if max_runs == Some(runs) {
+                return Status::Success;
+            }
+            let files = opts.files_read();
+            reporter.detail(&format!("watching {} input files for changes", files.len()));
+            let changed = wait_for_change(&files, self.poll_interval, deadline);
+            match changed {
+                Ok(Some(changed)) => {
+                    for path in changed {
+                        reporter.info(&format!("changed {}", path.display()));
+                    }
+                }
+                Ok(None) => {
+                    reporter.info("stopped watching, no changes before the deadline");
+                    return Status::Success;
+                }
+                Err(e) => {
+                    reporter.error(&e);
+                    return Status::Io;
+                }
+            }
+        }
+    }
+
+    /// Generate the output twice, with different hash seeds, and report any difference.
+    fn check_determinism<S, F>(&self, opts: &Options, stderr: &mut dyn Write, mut generate: F) -> Status
+    where
+        S: OutputFileSet,
+        F: FnMut(&Options) -> Result<S, Error>,
+    {
+        let mut reporter = Reporter {
+            name: &self.name,
+            verbosity: opts.verbosity,
+            stderr,
+        };
+        let first = match generate(opts) {
+            Ok(output) => output,
+            Err(e) => {
+                reporter.error(&*e);
+                return Status::GenerationFailed;
+            }
+        };
+        codemaker::perturb_hash_seeds();
+        let second = match generate(opts) {
+            Ok(output) => output,
+            Err(e) => {
+                reporter.error(&*e);
+                return Status::GenerationFailed;
+            }
+        };
+        match codemaker::compare_outputs(&first, &second) {
+            Ok(()) => {
+                reporter.info(&format!("all {} files are the same in both runs", first.files().len()));
+                Status::Success
+            }
+            Err(e) => {
+                reporter.error(&e);
+                Status::Nondeterministic
+            }
+        }
+    }
+
+    /// Parse the command-line arguments, returning `None` if help was requested.
+    pub fn parse_args<A>(&self, args: A) -> Result<Option<Options>, String>
+    where
+        A: IntoIterator,
+        A::Item: Into<String>,
+    {
+        let mut input = None;
+        let mut out_dir = None;
+        let mut modes = vec![];
+        let mut verbosity = 0;
+        let mut args = args.into_iter().map(Into::into);
+        while let Some(arg) = args.next() {
+            // Support both `--flag value` and `--flag=value`.
+            let (flag, inline_value) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+                _ => (arg.clone(), None),
+            };
+            if inline_value.is_some() && !matches!(flag.as_str(), "--input" | "--out-dir") {
+                return Err(format!("{} does not take a value", flag));
+            }
+            let mut value = |name: &str| {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} requires a value", name))
+            };
+            match flag.as_str() {
+                "-i" | "--input" => input = Some(PathBuf::from(value("--input")?)),
+                "-o" | "--out-dir" => out_dir = Some(PathBuf::from(value("--out-dir")?)),
+                "--check" => modes.push((Mode::Check, flag)),
+                "--dry-run" => modes.push((Mode::DryRun, flag)),
+                "--list-files" => modes.push((Mode::ListFiles, flag)),
+                "--stdout" => modes.push((Mode::Stdout, flag)),
+                "--watch" => modes.push((Mode::Watch, flag)),
+                "--check-determinism" => modes.push((Mode::CheckDeterminism, flag)),
+                "-v" | "--verbose" => verbosity += 1,
+                "-q" | "--quiet" => verbosity -= 1,
+                "-h" | "--help" => return Ok(None),
+                // Allow stacking of short verbosity flags like `-vv`.
+                _ if flag.len() > 2 && flag.starts_with('-') && flag[1..].chars().all(|c| c == 'v') => {
+                    verbosity += flag.len() as i32 - 1
+                }
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+        let mode = match modes.as_slice() {
+            [] => Mode::Write,
+            [(mode, _)] => *mode,
+            [(_, first), (_, second), ..] => {
+                return Err(format!("{} cannot be used with {}", first, second))
+            }
+        };
+        let input = input
+            .or_else(|| self.default_input.clone())
+            .ok_or_else(|| "no input file given, use --input to specify one".to_string())?;
+        let mut opts = Options::new(input, out_dir.unwrap_or_else(|| self.default_out_dir.clone()), mode);
+        opts.verbosity = verbosity;
+        opts.render = self.render.clone();
+        Ok(Some(opts))
+    }
+
+    /// The help message, listing the available options.
+    pub fn help(&self) -> String {
+        let mut help = String::new();
+        if let Some(about) = &self.about {
+            help.push_str(&format!("{}\n\n", about));
+        }
+        help.push_str(&format!("Usage: {} [OPTIONS]\n\nOptions:\n", self.name));
+        let input_default = match &self.default_input {
+            Some(path) => format!(" [default: {}]", path.display()),
+            None => String::new(),
+        };
+        help.push_str(&format!(
+            "  -i, --input <PATH>     The input file to generate from{}\n\
+            \x20 -o, --out-dir <DIR>    The directory to write output files into [default: {}]\n\
+            \x20     --check            Check that the output files are up-to-date, without writing them\n\
+            \x20     --dry-run          Report which output files would change, without writing them\n\
+            \x20     --list-files       List the output files, without writing them\n\
+            \x20     --stdout           Print the output to stdout, instead of writing files\n\
+            \x20     --watch            Write the output files, then rewrite them whenever the input changes\n\
+            \x20     --check-determinism\n\
+            \x20                        Check that generating twice gives the same output, without writing it\n\
+            \x20                        (best-effort: passing does not prove the output is deterministic)\n\
+            \x20 -v, --verbose          Report more detail, may be repeated\n\
+            \x20 -q, --quiet            Only report errors\n\
+            \x20 -h, --help             Print this help message\n",
+            input_default,
+            self.default_out_dir.display(),
+        ));
+        help
+    }
+}
+
+/// Reports progress to stderr, according to the requested verbosity.
+struct Reporter<'a> {
+    name: &'a str,
+    verbosity: i32,
+    stderr: &'a mut dyn Write,
+}
+
+impl Reporter<'_> {
+    fn info(&mut self, msg: &str) {
+        if self.verbosity >= 0 {
+            let _ = writeln!(self.stderr, "{}", msg);
+        }
+    }
+
+    fn detail(&mut self, msg: &str) {
+        if self.verbosity > 0 {
+            let _ = writeln!(self.stderr, "{}", msg);
+        }
+    }
+
+    fn error(&mut self, err: &dyn std::fmt::Display) {
+        let _ = writeln!(self.stderr, "{}: error: {}", self.name, err);
+    }
+}
+
+/// The state of an output file compared to what's already on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileState {
+    Unchanged,
+    Changed,
+    Missing,
+}
+
+impl FileState {
+    fn of(path: &Path, contents: &[u8]) -> std::io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(existing) if existing == contents => Ok(FileState::Unchanged),
+            Ok(_) => Ok(FileState::Changed),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FileState::Missing),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Handle the output according to the mode.
+///
+/// Writing files streams each one to disk as it's rendered, so that outputs too large to
+/// hold in memory (such as a [`codemaker::StreamingFile`]) can be written, and listing files
+/// only needs their paths. The other modes, and writing with a post-processing pipeline,
+/// render all of the files into memory first:
+/// post-processing works on a file's full contents, `--check` and `--dry-run` compare them
+/// with what's on disk, and nothing should be written if any file fails to be processed.
+fn handle_output<S: OutputFileSet>(
+    opts: &Options,
+    output: &S,
+    post_process: &PostProcess,
+    stdout: &mut dyn Write,
+    reporter: &mut Reporter<'_>,
+) -> std::io::Result<Status> {
+    let output_files = output.files();
+    for file in &output_files {
+        let path = file.path();
+        if !path.is_relative() || path.components().any(|c| c == std::path::Component::ParentDir) {
+            // A bug in the generator rather than a problem with the output directory, so
+            // report it as such, before anything has been written.
+            reporter.error(&format!("output file path {:?} is not inside the output directory", path));
+            return Ok(Status::GenerationFailed);
+        }
+    }
+    if opts.mode == Mode::ListFiles {
+        for file in &output_files {
+            writeln!(stdout, "{}", opts.out_dir.join(file.path()).display())?;
+        }
+        return Ok(Status::Success);
+    }
+    if matches!(opts.mode, Mode::Write | Mode::Watch) && post_process.is_empty() {
+        write_files(opts, reporter, &output_files, |file| file.path(), |file, writer| {
+            file.write_into_with_options(writer, &opts.render)
+        })?;
+        return Ok(Status::Success);
+    }
+    let mut files = vec![];
+    let mut failed = 0;
+    for file in output_files {
+        let path = file.path();
+        let mut contents = vec![];
+        file.write_into_with_options(&mut contents, &opts.render)?;
+        match post_process.process(path, contents) {
+            Ok(contents) => files.push((path, contents)),
+            Err(e) => {
+                reporter.error(&e);
+                failed += 1;
+            }
+        }
+    }
+    let report_failures = |reporter: &mut Reporter<'_>| {
+        reporter.info(&format!("post-processing failed for {} of {} files", failed, failed + files.len()));
+        Ok(Status::GenerationFailed)
+    };
+    match opts.mode {
+        Mode::CheckDeterminism | Mode::ListFiles => unreachable!("these modes don't render the output"),
+        Mode::Stdout => {
+            for (path, contents) in &files {
+                // Like `head`, only label the files if there's more than one.
+                if files.len() > 1 {
+                    writeln!(stdout, "==> {} <==", opts.out_dir.join(path).display())?;
+                }
+                stdout.write_all(contents)?;
+            }
+        }
+        Mode::Write | Mode::Watch => {
+            // Writing some of the files could leave the output inconsistent, so write none of them.
+            if failed > 0 {
+                return report_failures(reporter);
+            }
+            write_files(opts, reporter, &files, |(path, _)| path, |(_, contents), writer| {
+                writer.write_all(contents)
+            })?;
+        }
+        Mode::Check | Mode::DryRun => {
+            let mut stale = 0;
+            for (path, contents) in &files {
+                let path = &opts.out_dir.join(path);
+                let state = FileState::of(path, contents)?;
+                let label = match (opts.mode, state) {
+                    (_, FileState::Unchanged) => {
+                        reporter.detail(&format!("up-to-date {}", path.display()));
+                        continue;
+                    }
+                    (Mode::Check, FileState::Changed) => "out-of-date",
+                    (Mode::Check, FileState::Missing) => "missing",
+                    (_, FileState::Changed) => "would update",
+                    (_, FileState::Missing) => "would create",
+                };
+                reporter.info(&format!("{} {}", label, path.display()));
+                stale += 1;
+            }
+            if opts.mode == Mode::Check {
+                if stale > 0 {
+                    reporter.info(&format!(
+                        "{} of {} files are out-of-date; re-run the generator to update them",
+                        stale,
+                        files.len()
+                    ));
+                    if failed == 0 {
+                        return Ok(Status::OutOfDate);
+                    }
+                } else if failed == 0 {
+                    reporter.info(&format!("all {} files are up-to-date", files.len()));
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        return report_failures(reporter);
+    }
+    Ok(Status::Success)
+}
+
+/// Write each of the files into the output directory, reporting which ones changed.
+fn write_files<T, P, F>(opts: &Options, reporter: &mut Reporter<'_>, files: &[T], path: P, write: F) -> std::io::Result<()>
+where
+    P: Fn(&T) -> &Path,
+    F: Fn(&T, &mut std::io::BufWriter<std::fs::File>) -> std::io::Result<()>,
+{
+    let mut written = 0;
+    for file in files {
+        let path = opts.out_dir.join(path(file));
+        if write_if_changed(&path, |writer| write(file, writer))? {
+            reporter.detail(&format!("wrote {}", path.display()));
+            written += 1;
+        } else {
+            reporter.detail(&format!("unchanged {}", path.display()));
+        }
+    }
+    reporter.info(&format!(
+        "wrote {} of {} files into {}",
+        written,
+        files.len(),
+        opts.out_dir.display()
+    ));
+    Ok(())
+}
+
+/// Write a file by streaming it into a temporary file, which replaces the file at `path` only
+/// if their contents differ. Returns whether the file was replaced.
+///
+/// Leaving unchanged files untouched avoids needlessly triggering anything that watches them.
+fn write_if_changed<F>(path: &Path, write: F) -> std::io::Result<bool>
+where
+    F: FnOnce(&mut std::io::BufWriter<std::fs::File>) -> std::io::Result<()>,
+{
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".codemaker-tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let result = (|| {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
+        write(&mut writer)?;
+        writer.flush()?;
+        if same_contents(&temp_path, path)? {
+            return Ok(false);
+        }
+        std::fs::rename(&temp_path, path)?;
+        Ok(true)
+    })();
+    // The temporary file has either been moved into place or is no longer needed.
+    let _ = std::fs::remove_file(&temp_path);
+    result
+}
+
+/// Check whether two files have the same contents, without reading either one into memory.
+fn same_contents(path: &Path, other: &Path) -> std::io::Result<bool> {
+    use std::io::{BufRead, Read};
+    let other = match std::fs::File::open(other) {
+        Ok(other) => other,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let file = std::fs::File::open(path)?;
+    if file.metadata()?.len() != other.metadata()?.len() {
+        return Ok(false);
+    }
+    const CHUNK: usize = 8192;
+    let mut file = std::io::BufReader::with_capacity(CHUNK, file);
+    let mut other = std::io::BufReader::new(other);
+    let mut buf = [0; CHUNK];
+    loop {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() {
+            return Ok(true);
+        }
+        let len = chunk.len();
+        other.read_exact(&mut buf[..len])?;
+        if buf[..len] != *chunk {
+            return Ok(false);
+        }
+        file.consume(len);
+    }
+}
+
+/// The modification time and size of a file, or `None` if it doesn't exist.
+type FileStamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> std::io::Result<FileStamp> {
+    match std::fs::metadata(path) {
+        Ok(meta) => Ok(Some((meta.modified()?, meta.len()))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Wait until any of the given files changes, returning the ones that changed, or `None`
+/// if the deadline passes first.
+///
+/// Editors often write files in several steps, so once a change is seen this waits
+/// until the files have stopped changing for a full poll interval before returning.
+fn wait_for_change(
+    files: &[PathBuf],
+    poll_interval: Duration,
+    deadline: Option<Instant>,
+) -> std::io::Result<Option<Vec<PathBuf>>> {
+    let snapshot = |files: &[PathBuf]| -> std::io::Result<Vec<FileStamp>> {
+        files.iter().map(|path| stamp(path)).collect()
+    };
+    let original = snapshot(files)?;
+    let mut current = original.clone();
+    while current == original {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(None);
+        }
+        std::thread::sleep(poll_interval);
+        current = snapshot(files)?;
+    }
+    loop {
+        std::thread::sleep(poll_interval);
+        let next = snapshot(files)?;
+        if next == current {
+            break;
+        }
+        current = next;
+    }
+    Ok(Some(
+        files
+            .iter()
+            .zip(original.iter().zip(current.iter()))
+            .filter(|(_, (before, after))| before != after)
+            .map(|(path, _)| path.clone())
+            .collect(),
+    ))
+}
@@ -0,0 +1,356 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+use super::*;
+
+struct TextFile(PathBuf, String);
+
+impl OutputFile for TextFile {
+    fn path(&self) -> &Path {
+        &self.0
+    }
+    fn write_into<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(self.1.as_bytes())
+    }
+}
+
+struct TextFiles(Vec<TextFile>);
+
+impl OutputFileSet for TextFiles {
+    type OutputFile = TextFile;
+    fn files(&self) -> Vec<&TextFile> {
+        self.0.iter().collect()
+    }
+}
+
+fn cli() -> Cli {
+    Cli::new("gen").default_input("input.txt")
+}
+
+/// Run the CLI over an input of comma-separated file names, returning the status and output.
+fn run(args: &[&str]) -> (Status, String, String) {
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+    let status = cli().run_with_args(args.iter().copied(), &mut stdout, &mut stderr, |opts| {
+        let input = std::fs::read_to_string(&opts.input)?;
+        Ok(TextFiles(
+            input
+                .trim()
+                .split(',')
+                .map(|name| TextFile(name.into(), format!("{}\n", name)))
+                .collect(),
+        ))
+    });
+    (
+        status,
+        String::from_utf8(stdout).unwrap(),
+        String::from_utf8(stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_parse_args() {
+    let opts = cli().parse_args(Vec::<String>::new()).unwrap().unwrap();
+    assert_eq!(opts, Options::new("input.txt", ".", Mode::Write));
+    let opts = cli()
+        .parse_args(vec!["-i", "a.yaml", "--out-dir=out", "--check", "-vv", "-q"])
+        .unwrap()
+        .unwrap();
+    assert_eq!(opts.input, PathBuf::from("a.yaml"));
+    assert_eq!(opts.out_dir, PathBuf::from("out"));
+    assert_eq!(opts.mode, Mode::Check);
+    assert_eq!(opts.verbosity, 1);
+    assert_eq!(cli().parse_args(vec!["--help"]).unwrap(), None);
+    assert_eq!(cli().parse_args(vec!["--watch"]).unwrap().unwrap().mode, Mode::Watch);
+    assert_eq!(
+        cli().parse_args(vec!["--check", "--stdout"]).unwrap_err(),
+        "--check cannot be used with --stdout"
+    );
+    assert_eq!(cli().parse_args(vec!["--input"]).unwrap_err(), "--input requires a value");
+    assert_eq!(cli().parse_args(vec!["--check=yes"]).unwrap_err(), "--check does not take a value");
+    assert_eq!(
+        Cli::new("gen").parse_args(Vec::<String>::new()).unwrap_err(),
+        "no input file given, use --input to specify one"
+    );
+}
+
+#[test]
+fn test_modes_and_exit_codes() {
+    let dir = std::env::temp_dir().join(format!("codemaker-cli-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.txt");
+    std::fs::write(&input, "a.py,sub/b.py").unwrap();
+    let input = input.to_str().unwrap();
+    let out = dir.join("out");
+    let out_dir = out.to_str().unwrap();
+    let path = |p: &str| out.join(p).display().to_string();
+
+    let (status, stdout, _) = run(&["-i", input, "-o", out_dir, "--list-files"]);
+    assert_eq!(status, Status::Success);
+    assert_eq!(stdout, format!("{}\n{}\n", path("a.py"), path("sub/b.py")));
+
+    // Listing the files doesn't render them.
+    struct Unrenderable;
+    impl OutputFile for Unrenderable {
+        fn path(&self) -> &Path {
+            Path::new("never.py")
+        }
+        fn write_into<W: Write>(&self, _: &mut W) -> std::io::Result<()> {
+            panic!("rendered a file that was only being listed")
+        }
+    }
+    let mut stdout = vec![];
+    let args = vec!["-o", out_dir, "--list-files"];
+    let status = cli().run_with_args(args, &mut stdout, &mut vec![], |_| Ok(Unrenderable));
+    assert_eq!(status, Status::Success);
+    assert_eq!(String::from_utf8(stdout).unwrap(), format!("{}\n", path("never.py")));
+
+    let (status, stdout, _) = run(&["-i", input, "--stdout"]);
+    assert_eq!(status, Status::Success);
+    assert_eq!(stdout, "==> ./a.py <==\na.py\n==> ./sub/b.py <==\nsub/b.py\n");
+
+    let (status, _, stderr) = run(&["-i", input, "-o", out_dir, "--check"]);
+    assert_eq!(status, Status::OutOfDate);
+    assert!(stderr.contains(&format!("missing {}\n", path("a.py"))));
+
+    let (status, _, stderr) = run(&["-i", input, "-o", out_dir, "--dry-run"]);
+    assert_eq!(status, Status::Success);
+    assert!(stderr.contains(&format!("would create {}\n", path("sub/b.py"))));
+    assert!(!out.exists());
+
+    let (status, _, stderr) = run(&["-i", input, "-o", out_dir, "-v"]);
+    assert_eq!(status, Status::Success);
+    assert_eq!(
+        stderr,
+        format!("wrote {}\nwrote {}\nwrote 2 of 2 files into {}\n", path("a.py"), path("sub/b.py"), out_dir)
+    );
+    assert_eq!(std::fs::read_to_string(out.join("sub/b.py")).unwrap(), "sub/b.py\n");
+    let (status, _, stderr) = run(&["-i", input, "-o", out_dir, "-v"]);
+    assert_eq!(status, Status::Success);
+    assert_eq!(
+        stderr,
+        format!(
+            "unchanged {}\nunchanged {}\nwrote 0 of 2 files into {}\n",
+            path("a.py"),
+            path("sub/b.py"),
+            out_dir
+        )
+    );
+    // Files are written by way of a temporary file, which doesn't stay behind.
+    assert_eq!(std::fs::read_dir(&out).unwrap().count(), 2);
+
+    let (status, _, stderr) = run(&["-i", input, "-o", out_dir, "--check", "-q"]);
+    assert_eq!((status, stderr.as_str()), (Status::Success, ""));
+    std::fs::write(out.join("a.py"), "edited\n").unwrap();
+    let (status, _, stderr) = run(&["-i", input, "-o", out_dir, "--check"]);
+    assert_eq!(status, Status::OutOfDate);
+    assert_eq!(
+        stderr,
+        format!(
+            "out-of-date {}\n1 of 2 files are out-of-date; re-run the generator to update them\n",
+            path("a.py")
+        )
+    );
+
+    // Post-processing is applied before checking, and failures are reported for each file.
+    let post_process = PostProcess::new().transform("py", |path, text| {
+        if path.starts_with("sub") {
+            Err("no formatting allowed here".into())
+        } else {
+            Ok(text.replace("a.py", "edited"))
+        }
+    });
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+    let status = cli().post_process(post_process).run_with_args(
+        vec!["-i", input, "-o", out_dir, "--check"],
+        &mut stdout,
+        &mut stderr,
+        |_| Ok(TextFiles(vec![TextFile("a.py".into(), "a.py\n".into()), TextFile("sub/b.py".into(), "".into())])),
+    );
+    assert_eq!(status, Status::GenerationFailed);
+    assert_eq!(
+        String::from_utf8(stderr).unwrap(),
+        "gen: error: sub/b.py: transform failed: no formatting allowed here\n\
+         post-processing failed for 1 of 2 files\n"
+    );
+    // Without --check, the processed files are written.
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+    let status = cli()
+        .post_process(PostProcess::new().transform("py", |_, text| Ok(text.to_uppercase())))
+        .run_with_args(vec!["-i", input, "-o", out_dir, "-q"], &mut stdout, &mut stderr, |_| {
+            Ok(TextFiles(vec![TextFile("a.py".into(), "a.py\n".into())]))
+        });
+    assert_eq!((status, stderr.len()), (Status::Success, 0));
+    assert_eq!(std::fs::read_to_string(out.join("a.py")).unwrap(), "A.PY\n");
+    // But if any file fails to be processed, none of them are written.
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+    let status = cli()
+        .post_process(PostProcess::new().transform("py", |path, text| {
+            if path.starts_with("sub") {
+                Err("no formatting allowed here".into())
+            } else {
+                Ok(text.to_lowercase())
+            }
+        }))
+        .run_with_args(vec!["-i", input, "-o", out_dir, "-q"], &mut stdout, &mut stderr, |_| {
+            Ok(TextFiles(vec![TextFile("a.py".into(), "a.py\n".into()), TextFile("sub/c.py".into(), "".into())]))
+        });
+    assert_eq!(status, Status::GenerationFailed);
+    assert_eq!(
+        String::from_utf8(stderr).unwrap(),
+        "gen: error: sub/c.py: transform failed: no formatting allowed here\n"
+    );
+    assert_eq!(std::fs::read_to_string(out.join("a.py")).unwrap(), "A.PY\n");
+    assert!(!out.join("sub/c.py").exists());
+
+    for escaping in &["/abs.py", "../up.py"] {
+        std::fs::write(dir.join("escaping.txt"), format!("c.py,{}", escaping)).unwrap();
+        let (status, _, stderr) = run(&["-i", dir.join("escaping.txt").to_str().unwrap(), "-o", out_dir]);
+        assert_eq!(status, Status::GenerationFailed);
+        assert_eq!(
+            stderr,
+            format!("gen: error: output file path {:?} is not inside the output directory\n", escaping)
+        );
+        assert!(!out.join("c.py").exists());
+    }
+
+    let (status, _, stderr) = run(&["-i", "missing.txt"]);
+    assert_eq!(status, Status::GenerationFailed);
+    assert!(stderr.starts_with("gen: error: "));
+    let (status, _, stderr) = run(&["--bogus"]);
+    assert_eq!(status, Status::Usage);
+    assert_eq!(stderr, "gen: unexpected argument '--bogus'\nTry 'gen --help' for more information.\n");
+    assert_eq!(Status::OutOfDate.code(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_watch_regenerates_when_inputs_change() {
+    let dir = std::env::temp_dir().join(format!("codemaker-cli-watch-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.json");
+    let extra = dir.join("extra.txt");
+    std::fs::write(&input, "[\"a.py\", \"b.py\"").unwrap();
+    std::fs::write(&extra, "1").unwrap();
+    let opts = Options::new(&input, dir.join("out"), Mode::Watch);
+
+    // Edit the input files in the background, while the watcher is waiting. Each edit changes
+    // the file's length, so that it's seen even where modification times are coarse.
+    let (input_, extra_) = (input.clone(), extra.clone());
+    let editor = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        std::fs::write(&input_, "[\"a.py\", \"b.py\"]").unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        std::fs::write(&extra_, "22").unwrap();
+    });
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+    let mut runs = vec![];
+    let status = cli().poll_interval(Duration::from_millis(20)).watch(
+        &opts,
+        &mut stdout,
+        &mut stderr,
+        |opts| {
+            let names: Vec<String> = opts.load_input()?;
+            let contents = std::fs::read_to_string(&extra)?;
+            opts.track_input(&extra);
+            runs.push(opts.files_read().len());
+            Ok(TextFiles(
+                names
+                    .iter()
+                    .map(|name| TextFile(name.into(), format!("{} {}\n", name, contents)))
+                    .collect(),
+            ))
+        },
+        Some(3),
+        // Don't hang the test run if the edits are missed.
+        Some(Instant::now() + Duration::from_secs(10)),
+    );
+    editor.join().unwrap();
+    assert_eq!(status, Status::Success);
+    // The first run fails on the invalid input, without stopping the watcher.
+    assert_eq!(runs, vec![2, 2]);
+    let out = dir.join("out");
+    assert_eq!(
+        String::from_utf8(stderr).unwrap(),
+        format!(
+            "gen: error: {}:1:15: EOF while parsing a list\n\
+             changed {}\n\
+             wrote 2 of 2 files into {}\n\
+             changed {}\n\
+             wrote 2 of 2 files into {}\n",
+            input.display(),
+            input.display(),
+            out.display(),
+            extra.display(),
+            out.display(),
+        )
+    );
+    assert_eq!(std::fs::read_to_string(out.join("b.py")).unwrap(), "b.py 22\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_watch_stops_at_deadline() {
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+    let opts = Options::new("missing.json", "out", Mode::Watch);
+    let status = cli().poll_interval(Duration::from_millis(10)).watch(
+        &opts,
+        &mut stdout,
+        &mut stderr,
+        |opts| opts.load_input::<Vec<String>>().map(|_| TextFiles(vec![])).map_err(Into::into),
+        None,
+        Some(Instant::now() + Duration::from_millis(50)),
+    );
+    assert_eq!(status, Status::Success);
+    assert!(String::from_utf8(stderr)
+        .unwrap()
+        .ends_with("stopped watching, no changes before the deadline\n"));
+}
+
+#[test]
+fn test_check_determinism() {
+    use std::collections::HashSet;
+
+    let dir = std::env::temp_dir().join(format!("codemaker-cli-determinism-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.txt");
+    std::fs::write(&input, "a.py,b.py").unwrap();
+    let input = input.to_str().unwrap();
+    assert_eq!(
+        cli().parse_args(vec!["--check-determinism"]).unwrap().unwrap().mode,
+        Mode::CheckDeterminism
+    );
+
+    let (status, _, stderr) = run(&["-i", input, "--check-determinism"]);
+    assert_eq!(status, Status::Success);
+    assert_eq!(stderr, "all 2 files are the same in both runs\n");
+    assert!(!Path::new("a.py").exists());
+
+    // A file listing names in the order of a `HashSet` changes between runs.
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+    let status = cli().run_with_args(vec!["-i", input, "--check-determinism"], &mut stdout, &mut stderr, |_| {
+        let names: HashSet<String> = (0..26).map(|i| ((b'a' + i) as char).to_string()).collect();
+        let listing: String = names.into_iter().collect();
+        Ok(TextFile("names.txt".into(), listing))
+    });
+    assert_eq!(status, Status::Nondeterministic);
+    assert!(String::from_utf8(stderr)
+        .unwrap()
+        .starts_with("gen: error: names.txt:1 differs between runs\n"));
+    assert_eq!(Status::Nondeterministic.code(), 5);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,351 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! # Derive macros for writing `codemaker` target crates.
+//!
+//! A `codemaker` target crate needs a lot of small, repetitive builder methods
+//! on each of the types that it provides. This crate provides a derive macro
+//! to generate the common ones, so that new target crates are cheaper to write.
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+
+#[cfg(test)]
+mod tests;
+
+/// Derive a fluent-style builder API for a struct.
+///
+/// This always implements `codemaker::FluentAPI` for the struct, and will generate
+/// additional builder methods based on `#[fluent(...)]` attributes, like so:
+///
+/// ```ignore
+/// #[derive(FluentBuilder)]
+/// #[fluent(into(Statement::FuncDef))]
+/// pub struct FunctionDefinition {
+///     #[fluent(new)]
+///     name: String,
+///     #[fluent(add = "add_arg", add_all = "add_args")]
+///     args: Vec<String>,
+///     #[fluent(extend)]
+///     body: Vec<Statement>,
+/// }
+/// ```
+///
+/// The supported attributes on the struct itself are:
+///
+///  * `#[fluent(new)]`: generate a `new()` constructor even if no fields are marked
+///    with `#[fluent(new)]`.
+///  * `#[fluent(into(Enum::Variant))]`: implement `From<Self> for Enum` by wrapping
+///    the struct in the given tuple variant. This can be given multiple times.
+///
+/// The supported attributes on individual fields are:
+///
+///  * `#[fluent(new)]`: take this field as an argument to the generated `new()`
+///    constructor, accepting anything that is `Into` the field type. Fields without
+///    this attribute are initialized with `Default::default()`.
+///  * `#[fluent(origin)]`: for an `Option<codemaker::Origin>` field, initialize it in the
+///    generated `new()` constructor with `codemaker::Origin::current()`, so that the struct
+///    remembers which rule made it when provenance tracking is enabled.
+///  * `#[fluent(set = "name")]`: generate a method `name(value)` that sets the field,
+///    accepting anything that is `Into` the field type.
+///  * `#[fluent(with = "name")]`: generate a method `name(func)` that replaces the
+///    field with the result of calling `func` on its current value. The current value
+///    is moved out with `std::mem::take`, so the field type must implement `Default`.
+///  * `#[fluent(add = "name")]`: for a `Vec<Item>` field, generate a method `name(item)`
+///    that pushes a single item, accepting anything that is `Into<Item>`.
+///  * `#[fluent(add_all = "name")]`: for a `Vec<Item>` field, generate a method
+///    `name(items)` that pushes each item from an iterator.
+///  * `#[fluent(extend)]`: for a `Vec<Item>` field, implement `std::iter::Extend<Item>`
+///    for the struct by extending that field, which in turn provides the fluent
+///    `codemaker::Extend` trait. Each `Item` type can only be used on one field.
+///
+/// The generated code refers to the `codemaker` crate by the absolute path `::codemaker`,
+/// so it will fail to compile in a crate that renames its `codemaker` dependency
+/// (for example with `package = "codemaker"` in `Cargo.toml`) or re-exports it
+/// under another name.
+#[proc_macro_derive(FluentBuilder, attributes(fluent))]
+pub fn derive_fluent_builder(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+    match FluentBuilder::from_derive_input(&input) {
+        Ok(builder) => builder.to_tokens(&input).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[derive(Debug, Default)]
+struct FluentBuilder {
+    new: bool,
+    into: Vec<syn::Path>,
+    fields: Vec<FluentField>,
+}
+
+#[derive(Debug)]
+struct FluentField {
+    name: syn::Ident,
+    ty: syn::Type,
+    new: bool,
+    origin: bool,
+    extend: bool,
+    set: Option<syn::Ident>,
+    with: Option<syn::Ident>,
+    add: Option<syn::Ident>,
+    add_all: Option<syn::Ident>,
+}
+
+impl FluentBuilder {
+    fn from_derive_input(input: &syn::DeriveInput) -> syn::Result<Self> {
+        let mut builder = FluentBuilder::default();
+        for meta in fluent_attrs(&input.attrs)? {
+            match &meta {
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("new") => builder.new = true,
+                syn::NestedMeta::Meta(syn::Meta::List(list)) if list.path.is_ident("into") => {
+                    for nested in &list.nested {
+                        match nested {
+                            syn::NestedMeta::Meta(syn::Meta::Path(p)) => builder.into.push(p.clone()),
+                            _ => return Err(syn::Error::new_spanned(nested, "expected an enum variant path")),
+                        }
+                    }
+                }
+                _ => return Err(syn::Error::new_spanned(meta, "unknown fluent attribute")),
+            }
+        }
+        let fields = match &input.data {
+            syn::Data::Struct(syn::DataStruct { fields: syn::Fields::Named(fields), .. }) => fields,
+            syn::Data::Struct(syn::DataStruct { fields: syn::Fields::Unit, .. }) => return Ok(builder),
+            _ => return Err(syn::Error::new_spanned(&input.ident, "FluentBuilder only supports structs with named fields")),
+        };
+        for field in &fields.named {
+            builder.fields.push(FluentField::from_field(field)?);
+        }
+        Ok(builder)
+    }
+
+    fn to_tokens(&self, input: &syn::DeriveInput) -> TokenStream {
+        let name = &input.ident;
+        let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+        let mut methods = TokenStream::new();
+        let mut impls = TokenStream::new();
+        if self.new || self.fields.iter().any(|f| f.new) {
+            methods.extend(self.new_method());
+        }
+        for field in &self.fields {
+            methods.extend(field.methods());
+            if field.extend {
+                let field_name = &field.name;
+                let item = match vec_item_type(&field.ty) {
+                    Ok(item) => item,
+                    Err(err) => return err.to_compile_error(),
+                };
+                impls.extend(quote_spanned! {field.name.span()=>
+                    impl #impl_generics ::std::iter::Extend<#item> for #name #ty_generics #where_clause {
+                        fn extend<I__: ::std::iter::IntoIterator<Item = #item>>(&mut self, iter: I__) {
+                            ::std::iter::Extend::extend(&mut self.#field_name, iter)
+                        }
+                    }
+                });
+            }
+        }
+        for variant in &self.into {
+            let target = match enum_path(variant) {
+                Ok(target) => target,
+                Err(err) => return err.to_compile_error(),
+            };
+            impls.extend(quote_spanned! {variant.span()=>
+                impl #impl_generics ::std::convert::From<#name #ty_generics> for #target #where_clause {
+                    fn from(value: #name #ty_generics) -> Self {
+                        #variant(value)
+                    }
+                }
+            });
+        }
+        quote! {
+            impl #impl_generics ::codemaker::FluentAPI for #name #ty_generics #where_clause {}
+
+            impl #impl_generics #name #ty_generics #where_clause {
+                #methods
+            }
+
+            #impls
+        }
+    }
+
+    fn new_method(&self) -> TokenStream {
+        let args = self.fields.iter().filter(|f| f.new).map(|f| {
+            let name = &f.name;
+            let ty = &f.ty;
+            quote! { #name: impl ::std::convert::Into<#ty> }
+        });
+        let inits = self.fields.iter().map(|f| {
+            let name = &f.name;
+            if f.new {
+                quote! { #name: #name.into() }
+            } else if f.origin {
+                quote! { #name: ::codemaker::Origin::current() }
+            } else {
+                quote! { #name: ::std::default::Default::default() }
+            }
+        });
+        quote! {
+            #[doc = "Create a new instance, with default values for any unspecified fields."]
+            pub fn new(#(#args),*) -> Self {
+                Self {
+                    #(#inits),*
+                }
+            }
+        }
+    }
+}
+
+impl FluentField {
+    fn from_field(field: &syn::Field) -> syn::Result<Self> {
+        let mut fluent = FluentField {
+            name: field.ident.clone().expect("named fields have names"),
+            ty: field.ty.clone(),
+            new: false,
+            origin: false,
+            extend: false,
+            set: None,
+            with: None,
+            add: None,
+            add_all: None,
+        };
+        for meta in fluent_attrs(&field.attrs)? {
+            match &meta {
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("new") => fluent.new = true,
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("origin") => fluent.origin = true,
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("extend") => fluent.extend = true,
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) => {
+                    let method = match &nv.lit {
+                        syn::Lit::Str(s) => s.parse::<syn::Ident>()?,
+                        _ => return Err(syn::Error::new_spanned(&nv.lit, "expected a method name string")),
+                    };
+                    let slot = if nv.path.is_ident("set") {
+                        &mut fluent.set
+                    } else if nv.path.is_ident("with") {
+                        &mut fluent.with
+                    } else if nv.path.is_ident("add") {
+                        &mut fluent.add
+                    } else if nv.path.is_ident("add_all") {
+                        &mut fluent.add_all
+                    } else {
+                        return Err(syn::Error::new_spanned(meta, "unknown fluent attribute"));
+                    };
+                    *slot = Some(method);
+                }
+                _ => return Err(syn::Error::new_spanned(meta, "unknown fluent attribute")),
+            }
+        }
+        Ok(fluent)
+    }
+
+    fn methods(&self) -> TokenStream {
+        let name = &self.name;
+        let ty = &self.ty;
+        let mut methods = TokenStream::new();
+        if let Some(method) = &self.set {
+            let doc = format!("Set the `{}` field to the given value.", name);
+            methods.extend(quote_spanned! {method.span()=>
+                #[doc = #doc]
+                pub fn #method(mut self, value: impl ::std::convert::Into<#ty>) -> Self {
+                    self.#name = value.into();
+                    self
+                }
+            });
+        }
+        if let Some(method) = &self.with {
+            let doc = format!("Replace the `{}` field with the result of calling a function on it.", name);
+            methods.extend(quote_spanned! {method.span()=>
+                #[doc = #doc]
+                pub fn #method<F__: ::std::ops::FnOnce(#ty) -> #ty>(mut self, func: F__) -> Self {
+                    self.#name = func(::std::mem::take(&mut self.#name));
+                    self
+                }
+            });
+        }
+        if self.add.is_some() || self.add_all.is_some() {
+            let item = match vec_item_type(&self.ty) {
+                Ok(item) => item,
+                Err(err) => return err.to_compile_error(),
+            };
+            if let Some(method) = &self.add {
+                let doc = format!("Add a single item to the `{}` field.", name);
+                methods.extend(quote_spanned! {method.span()=>
+                    #[doc = #doc]
+                    pub fn #method(mut self, item: impl ::std::convert::Into<#item>) -> Self {
+                        self.#name.push(item.into());
+                        self
+                    }
+                });
+            }
+            if let Some(method) = &self.add_all {
+                let doc = format!("Add each item from an iterator to the `{}` field.", name);
+                methods.extend(quote_spanned! {method.span()=>
+                    #[doc = #doc]
+                    pub fn #method<T__, I__>(mut self, items: I__) -> Self
+                    where
+                        T__: ::std::convert::Into<#item>,
+                        I__: ::std::iter::IntoIterator<Item = T__>,
+                    {
+                        self.#name.extend(items.into_iter().map(::std::convert::Into::into));
+                        self
+                    }
+                });
+            }
+        }
+        methods
+    }
+}
+
+/// Find the contents of any `#[fluent(...)]` attributes.
+fn fluent_attrs(attrs: &[syn::Attribute]) -> syn::Result<Vec<syn::NestedMeta>> {
+    let mut metas = vec![];
+    for attr in attrs.iter().filter(|a| a.path.is_ident("fluent")) {
+        match attr.parse_meta()? {
+            syn::Meta::List(list) => metas.extend(list.nested),
+            meta => return Err(syn::Error::new_spanned(meta, "expected #[fluent(...)]")),
+        }
+    }
+    Ok(metas)
+}
+
+/// Find the item type of a `Vec<Item>` field.
+fn vec_item_type(ty: &syn::Type) -> syn::Result<&syn::Type> {
+    if let syn::Type::Path(syn::TypePath { qself: None, path }) = ty {
+        if let Some(last) = path.segments.last() {
+            if last.ident == "Vec" {
+                if let syn::PathArguments::AngleBracketed(args) = &last.arguments {
+                    if let Some(syn::GenericArgument::Type(item)) = args.args.first() {
+                        return Ok(item);
+                    }
+                }
+            }
+        }
+    }
+    Err(syn::Error::new_spanned(ty, "expected a field of type `Vec<Item>`"))
+}
+
+/// Find the enum type from an `Enum::Variant` path.
+fn enum_path(variant: &syn::Path) -> syn::Result<syn::Path> {
+    let mut target = variant.clone();
+    if target.segments.pop().is_none() || target.segments.is_empty() {
+        return Err(syn::Error::new_spanned(variant, "expected a path like `Enum::Variant`"));
+    }
+    // Popping leaves a trailing `::` punctuation behind, which we don't want.
+    let last = target.segments.pop().expect("checked non-empty above").into_value();
+    target.segments.push(last);
+    Ok(target)
+}
@@ -0,0 +1,171 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+use super::*;
+
+/// Run the derive on the given struct, returning the generated code as a string.
+fn derive(input: syn::DeriveInput) -> String {
+    match FluentBuilder::from_derive_input(&input) {
+        Ok(builder) => builder.to_tokens(&input).to_string(),
+        Err(err) => err.to_compile_error().to_string(),
+    }
+}
+
+/// Run the derive on the given struct, expecting it to fail with the given message.
+fn assert_derive_error(input: syn::DeriveInput, message: &str) {
+    let output = derive(input);
+    let expected = quote! { compile_error! { #message } }.to_string();
+    assert!(output.contains(&expected), "expected error {:?} in {}", message, output);
+}
+
+#[test]
+fn test_derive_setters() {
+    let output = derive(syn::parse_quote! {
+        struct Test {
+            #[fluent(new, set = "with_name")]
+            name: String,
+            #[fluent(with = "with_items", add = "add_item", add_all = "add_items")]
+            items: Vec<u32>,
+        }
+    });
+    let expected = quote! {
+        pub fn new(name: impl ::std::convert::Into<String>) -> Self {
+            Self {
+                name: name.into(),
+                items: ::std::default::Default::default()
+            }
+        }
+    };
+    assert!(output.contains(&expected.to_string()), "{}", output);
+    let expected = quote! {
+        pub fn with_name(mut self, value: impl ::std::convert::Into<String>) -> Self {
+            self.name = value.into();
+            self
+        }
+    };
+    assert!(output.contains(&expected.to_string()), "{}", output);
+    let expected = quote! {
+        pub fn add_item(mut self, item: impl ::std::convert::Into<u32>) -> Self {
+            self.items.push(item.into());
+            self
+        }
+    };
+    assert!(output.contains(&expected.to_string()), "{}", output);
+    assert!(output.contains("pub fn with_items"), "{}", output);
+    assert!(output.contains("pub fn add_items"), "{}", output);
+}
+
+#[test]
+fn test_derive_into_and_extend() {
+    let output = derive(syn::parse_quote! {
+        #[fluent(into(Statement::Test, other::Node::Test))]
+        struct Test {
+            #[fluent(extend)]
+            items: Vec<u32>,
+        }
+    });
+    let expected = quote! {
+        impl ::std::convert::From<Test> for Statement {
+            fn from(value: Test) -> Self {
+                Statement::Test(value)
+            }
+        }
+    };
+    assert!(output.contains(&expected.to_string()), "{}", output);
+    assert!(output.contains(&quote! { for other::Node }.to_string()), "{}", output);
+    assert!(output.contains(&quote! { ::std::iter::Extend<u32> for Test }.to_string()), "{}", output);
+}
+
+#[test]
+fn test_derive_errors() {
+    assert_derive_error(
+        syn::parse_quote! {
+            struct Test {
+                #[fluent(add = "add_item")]
+                items: String,
+            }
+        },
+        "expected a field of type `Vec<Item>`",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            struct Test {
+                #[fluent(extend)]
+                items: Option<Vec<u32>>,
+            }
+        },
+        "expected a field of type `Vec<Item>`",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            struct Test {
+                #[fluent(frobnicate)]
+                items: Vec<u32>,
+            }
+        },
+        "unknown fluent attribute",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            struct Test {
+                #[fluent(frobnicate = "items")]
+                items: Vec<u32>,
+            }
+        },
+        "unknown fluent attribute",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            struct Test {
+                #[fluent(set = 42)]
+                items: Vec<u32>,
+            }
+        },
+        "expected a method name string",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            #[fluent(origin)]
+            struct Test {}
+        },
+        "unknown fluent attribute",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            #[fluent(into(Test))]
+            struct Test {}
+        },
+        "expected a path like `Enum::Variant`",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            #[fluent]
+            struct Test {}
+        },
+        "expected #[fluent(...)]",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            struct Test(u32);
+        },
+        "FluentBuilder only supports structs with named fields",
+    );
+    assert_derive_error(
+        syn::parse_quote! {
+            enum Test {}
+        },
+        "FluentBuilder only supports structs with named fields",
+    );
+}
@@ -0,0 +1,383 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Input sets spanning several files that include one another.
+//!
+//! A file can pull in other files by listing them under an `include` key at its top
+//! level, with paths relative to the including file:
+//!
+//! ```yaml
+//! include:
+//!   - codes/informational.yaml
+//!   - codes/success.yaml
+//! codes:
+//!   - [418, I'm a teapot]
+//! ```
+//!
+//! The included files are loaded first, in order, and the including file is merged on top
+//! of them: maps are merged key by key, lists are concatenated, and other values from the
+//! including file replace those from the included ones. The result is deserialized as a
+//! single value, and any errors in it are traced back to the file and line they came from.
+//!
+//! To merge files in different formats, each file is loaded as a [`serde_json::Value`] before
+//! merging, so only data that JSON can represent survives the trip. In particular, TOML
+//! datetimes arrive as a map that only `toml`'s own `Datetime` type can deserialize, NaN
+//! and infinite floats become null, and RON files that use enum variants, chars or
+//! non-string map keys fail to load. Inputs that depend on such things should be loaded
+//! as a single file using [`InputLoader::load`].
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::{json_location, ErrorKind, Format, InputError, InputLoader};
+
+/// A file that has been loaded as part of an input set.
+struct File {
+    path: PathBuf,
+    format: Format,
+    text: String,
+}
+
+/// A step along the path from the top of a file to one of its values.
+#[derive(Clone)]
+enum Segment {
+    Index(usize),
+    Key(String),
+}
+
+/// Format a path to a value in the same way as `serde_path_to_error`.
+fn display_path(path: &[Segment]) -> String {
+    let mut display = String::new();
+    for segment in path {
+        match segment {
+            Segment::Index(index) => display.push_str(&format!("[{}]", index)),
+            Segment::Key(key) if display.is_empty() => display.push_str(key),
+            Segment::Key(key) => display.push_str(&format!(".{}", key)),
+        }
+    }
+    display
+}
+
+/// A value from an input file, annotated with where it came from.
+///
+/// The position of a value within its file is kept as the path to it from the top of the
+/// file, which is turned into a line and column only if there's an error to report.
+struct Node {
+    kind: NodeKind,
+    file: usize,
+    path: Vec<Segment>,
+}
+
+enum NodeKind {
+    Scalar(Value),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+impl Node {
+    fn from_value(value: Value, file: usize, path: Vec<Segment>) -> Self {
+        let child_path = |segment: Segment| -> Vec<Segment> {
+            let mut child = path.clone();
+            child.push(segment);
+            child
+        };
+        let kind = match value {
+            Value::Array(items) => NodeKind::Seq(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, item)| Node::from_value(item, file, child_path(Segment::Index(i))))
+                    .collect(),
+            ),
+            Value::Object(entries) => NodeKind::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, item)| {
+                        let item = Node::from_value(item, file, child_path(Segment::Key(key.clone())));
+                        (key, item)
+                    })
+                    .collect(),
+            ),
+            scalar => NodeKind::Scalar(scalar),
+        };
+        Node { kind, file, path }
+    }
+
+    /// Merge another node on top of this one.
+    fn merge(&mut self, other: Node) {
+        match (&mut self.kind, other.kind) {
+            (NodeKind::Map(entries), NodeKind::Map(others)) => {
+                for (key, other) in others {
+                    match entries.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, existing)) => existing.merge(other),
+                        None => entries.push((key, other)),
+                    }
+                }
+            }
+            (NodeKind::Seq(items), NodeKind::Seq(others)) => items.extend(others),
+            (_, kind) => {
+                *self = Node {
+                    kind,
+                    file: other.file,
+                    path: other.path,
+                }
+            }
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match &self.kind {
+            NodeKind::Scalar(value) => value.clone(),
+            NodeKind::Seq(items) => Value::Array(items.iter().map(Node::to_value).collect()),
+            NodeKind::Map(entries) => Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, item)| (key.clone(), item.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Find the deepest node along a path into the merged value.
+    fn locate(&self, path: &serde_path_to_error::Path) -> &Node {
+        let mut node = self;
+        for segment in path {
+            let child = match (&node.kind, segment) {
+                (NodeKind::Seq(items), serde_path_to_error::Segment::Seq { index }) => items.get(*index),
+                (NodeKind::Map(entries), serde_path_to_error::Segment::Map { key }) => {
+                    entries.iter().find(|(k, _)| k == key).map(|(_, item)| item)
+                }
+                _ => None,
+            };
+            match child {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        node
+    }
+}
+
+impl InputLoader {
+    /// Use a different top-level key for listing included files; the default is `include`.
+    pub fn with_include_key<T: Into<String>>(mut self, key: T) -> Self {
+        self.include_key = key.into();
+        self
+    }
+
+    /// Load a root input file along with all the files it includes, merged into a single value.
+    ///
+    /// Each file may be in any of the supported formats, determined by its extension. A file
+    /// that is included more than once is only merged in the first time, and a file that
+    /// (directly or indirectly) includes itself is reported as an error.
+    pub fn load_set<T: DeserializeOwned, P: AsRef<Path>>(&mut self, root: P) -> Result<T, InputError> {
+        let mut files = vec![];
+        let merged = self.load_node(root.as_ref(), &mut vec![], &mut files)?;
+        let merged = match merged {
+            Some(merged) => merged,
+            None => unreachable!("the root file can't have been loaded already"),
+        };
+        serde_path_to_error::deserialize(merged.to_value()).map_err(|e| {
+            let node = merged.locate(e.path());
+            let file = &files[node.file];
+            let message = e.into_inner().to_string();
+            InputError {
+                path: file.path.clone(),
+                location: file.format.locate(&file.text, &node.path),
+                kind: ErrorKind::Invalid,
+                message: if node.path.is_empty() {
+                    message
+                } else {
+                    format!("{}: {}", display_path(&node.path), message)
+                },
+            }
+        })
+    }
+
+    /// Load a file and everything it includes, returning `None` if it was already loaded.
+    fn load_node(
+        &mut self,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+        files: &mut Vec<File>,
+    ) -> Result<Option<Node>, InputError> {
+        let include_error = |message: String| InputError {
+            path: path.to_path_buf(),
+            location: None,
+            kind: ErrorKind::Include,
+            message,
+        };
+        // Compare canonical paths, so that different ways of naming a file are treated the same.
+        let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if let Some(start) = stack.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = files_in_cycle(&stack[start..], files)
+                .chain(std::iter::once(path.display().to_string()))
+                .collect();
+            return Err(include_error(format!("include cycle: {}", cycle.join(" -> "))));
+        }
+        if files.iter().any(|f| std::fs::canonicalize(&f.path).ok().as_ref() == Some(&canonical)) {
+            return Ok(None);
+        }
+        let format = Format::from_path(path).ok_or_else(|| self.unknown_format(path))?;
+        let text = self.read(path)?;
+        let value: Value = format.parse(&text, path)?;
+        let file = files.len();
+        files.push(File {
+            path: path.to_path_buf(),
+            format,
+            text,
+        });
+        let mut node = Node::from_value(value, file, vec![]);
+        let includes = match &mut node.kind {
+            NodeKind::Map(entries) => entries
+                .iter()
+                .position(|(k, _)| *k == self.include_key)
+                .map(|i| entries.remove(i).1),
+            _ => None,
+        };
+        let includes: Vec<String> = match includes.map(|n| n.to_value()) {
+            None => vec![],
+            Some(Value::String(include)) => vec![include],
+            Some(Value::Array(items)) => items
+                .into_iter()
+                .map(|item| match item {
+                    Value::String(include) => Ok(include),
+                    _ => Err(include_error(format!("{} must list file paths", self.include_key))),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(include_error(format!("{} must list file paths", self.include_key))),
+        };
+        stack.push(canonical);
+        let mut merged: Option<Node> = None;
+        for include in includes {
+            let include = path.parent().unwrap_or_else(|| Path::new("")).join(include);
+            if let Some(included) = self.load_node(&include, stack, files)? {
+                match merged.as_mut() {
+                    Some(merged) => merged.merge(included),
+                    None => merged = Some(included),
+                }
+            }
+        }
+        stack.pop();
+        Ok(Some(match merged {
+            Some(mut merged) => {
+                merged.merge(node);
+                merged
+            }
+            None => node,
+        }))
+    }
+}
+
+/// The display names of the files on the include stack, for reporting a cycle.
+fn files_in_cycle<'a>(stack: &'a [PathBuf], files: &'a [File]) -> impl Iterator<Item = String> + 'a {
+    stack.iter().map(move |canonical| {
+        files
+            .iter()
+            .map(|f| &f.path)
+            .find(|f| std::fs::canonicalize(f).ok().as_ref() == Some(canonical))
+            .unwrap_or(canonical)
+            .display()
+            .to_string()
+    })
+}
+
+impl Format {
+    /// Find the line and column of the value at the given path in a file's text.
+    ///
+    /// This re-parses the text up to the value and then fails there, so that the format's
+    /// own error reporting says where the value is.
+    fn locate(self, text: &str, path: &[Segment]) -> Option<(usize, usize)> {
+        let probe = Probe(path);
+        match self {
+            Format::Json => {
+                let e = probe.deserialize(&mut serde_json::Deserializer::from_str(text)).err()?;
+                json_location(&e)
+            }
+            Format::Ron => {
+                let mut de = ron::Deserializer::from_str(text).ok()?;
+                let e = probe.deserialize(&mut de).err()?;
+                let e = de.span_error(e);
+                Some((e.position.line, e.position.col))
+            }
+            Format::Toml => {
+                let e = probe.deserialize(&mut toml::Deserializer::new(text)).err()?;
+                // TOML reports 0-based positions.
+                e.line_col().map(|(line, col)| (line + 1, col + 1))
+            }
+            Format::Yaml => {
+                let e = probe.deserialize(serde_yaml::Deserializer::from_str(text)).err()?;
+                e.location().map(|l| (l.line(), l.column()))
+            }
+        }
+    }
+}
+
+/// Deserializes the value at a path, failing as soon as it gets there.
+///
+/// A scalar found part-way along the path fails too, which locates the deepest value on
+/// the path that exists. If the path leads nowhere, deserialization succeeds.
+struct Probe<'a>(&'a [Segment]);
+
+impl<'de> DeserializeSeed<'de> for Probe<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Probe<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the value being located")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        match self.0.split_first() {
+            None => return Err(de::Error::custom("found the value being located")),
+            Some((Segment::Index(index), rest)) => {
+                for _ in 0..*index {
+                    if seq.next_element::<IgnoredAny>()?.is_none() {
+                        return Ok(());
+                    }
+                }
+                seq.next_element_seed(Probe(rest))?;
+            }
+            Some((Segment::Key(_), _)) => (),
+        }
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let (key, rest) = match self.0.split_first() {
+            None => return Err(de::Error::custom("found the value being located")),
+            Some((Segment::Key(key), rest)) => (Some(key), rest),
+            Some((Segment::Index(_), rest)) => (None, rest),
+        };
+        while let Some(k) = map.next_key::<String>()? {
+            if Some(&k) == key {
+                map.next_value_seed(Probe(rest))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! # Load input data for `codemaker` generators.
+//!
+//! Generators typically start by deserializing some data file into an in-memory structure.
+//! This crate takes care of the fiddly parts of doing so: picking a format based on the file
+//! extension, reporting problems with the file in a way that points at the offending line,
+//! and keeping track of which files were read so that the generator can be re-run when
+//! they change:
+//!
+//! ```ignore
+//! let mut loader = codemaker_input::InputLoader::new();
+//! let codes: StatusCodes = loader.load("status_codes.yaml")?;
+//! for path in loader.files_read() {
+//!     build.track_input(path);
+//! }
+//! ```
+//!
+//! Larger inputs can be split across several files, with a root file that includes the
+//! others; see [`InputLoader::load_set`].
+//!
+//! An error in the input file will be reported like this:
+//!
+//! ```text
+//! status_codes.yaml:3:5: codes[1]: invalid type: string "two hundred", expected u16
+//! ```
+
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+mod tests;
+
+mod include;
+
+/// The data formats that inputs can be loaded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Ron,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Detect the format of a file from its extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Format> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(Format::Json),
+            "ron" => Some(Format::Ron),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Deserialize a string in this format, reporting errors as if it came from the given path.
+    pub fn parse<T: DeserializeOwned>(self, text: &str, path: &Path) -> Result<T, InputError> {
+        let invalid = |field: String, message: String, location: Option<(usize, usize)>| InputError {
+            path: path.to_path_buf(),
+            location,
+            kind: ErrorKind::Invalid,
+            // Some formats already include the path to the field in their message.
+            message: if field.is_empty() || field == "." || message.starts_with(&format!("{}: ", field)) {
+                message
+            } else {
+                format!("{}: {}", field, message)
+            },
+        };
+        match self {
+            Format::Json => {
+                let mut de = serde_json::Deserializer::from_str(text);
+                let value = serde_path_to_error::deserialize(&mut de).map_err(|e| {
+                    let field = e.path().to_string();
+                    let e = e.into_inner();
+                    invalid(field, strip_location(&e.to_string()), json_location(&e))
+                })?;
+                de.end()
+                    .map_err(|e| invalid(String::new(), strip_location(&e.to_string()), json_location(&e)))?;
+                Ok(value)
+            }
+            Format::Ron => {
+                let mut de = ron::Deserializer::from_str(text).map_err(|e| {
+                    invalid(String::new(), e.code.to_string(), Some((e.position.line, e.position.col)))
+                })?;
+                let value = serde_path_to_error::deserialize(&mut de).map_err(|e| {
+                    let field = e.path().to_string();
+                    let e = de.span_error(e.into_inner());
+                    invalid(field, e.code.to_string(), Some((e.position.line, e.position.col)))
+                })?;
+                de.end().map_err(|e| {
+                    let e = de.span_error(e);
+                    invalid(String::new(), e.code.to_string(), Some((e.position.line, e.position.col)))
+                })?;
+                Ok(value)
+            }
+            Format::Toml => {
+                let mut de = toml::Deserializer::new(text);
+                serde_path_to_error::deserialize(&mut de).map_err(|e| {
+                    let field = e.path().to_string();
+                    let e = e.into_inner();
+                    // TOML reports 0-based positions.
+                    let location = e.line_col().map(|(line, col)| (line + 1, col + 1));
+                    let mut message = strip_location(&e.to_string());
+                    // This duplicates the path to the field, which we report separately.
+                    if let Some(i) = message.find(" for key `") {
+                        message.truncate(i);
+                    }
+                    invalid(field, message, location)
+                })
+            }
+            Format::Yaml => {
+                let de = serde_yaml::Deserializer::from_str(text);
+                serde_path_to_error::deserialize(de).map_err(|e| {
+                    let field = e.path().to_string();
+                    let e = e.into_inner();
+                    let location = e.location().map(|l| (l.line(), l.column()));
+                    invalid(field, strip_location(&e.to_string()), location)
+                })
+            }
+        }
+    }
+}
+
+fn json_location(e: &serde_json::Error) -> Option<(usize, usize)> {
+    if e.line() == 0 {
+        None
+    } else {
+        Some((e.line(), e.column()))
+    }
+}
+
+/// Remove the " at line X column Y" suffix that some formats add to their error messages,
+/// since we report the location separately.
+fn strip_location(message: &str) -> String {
+    if let Some(i) = message.rfind(" at line ") {
+        let tail = &message[i + " at line ".len()..];
+        let mut parts = tail.splitn(3, ' ');
+        let is_location = matches!(
+            (parts.next(), parts.next(), parts.next()),
+            (Some(line), Some("column"), Some(col))
+                if line.chars().all(|c| c.is_ascii_digit()) && col.chars().all(|c| c.is_ascii_digit())
+        );
+        if is_location {
+            return message[..i].to_string();
+        }
+    }
+    message.to_string()
+}
+
+/// The kinds of error that can occur when loading input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The file could not be read.
+    Io,
+    /// The format of the file could not be determined from its extension.
+    UnknownFormat,
+    /// The file could not be parsed, or its contents didn't match the expected structure.
+    Invalid,
+    /// The file's list of included files was invalid, or files include each other in a cycle.
+    Include,
+}
+
+/// An error from loading input, pointing at the location of the problem where possible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputError {
+    path: PathBuf,
+    location: Option<(usize, usize)>,
+    kind: ErrorKind,
+    message: String,
+}
+
+impl InputError {
+    /// The path of the file containing the error.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The 1-based line number of the error, if known.
+    pub fn line(&self) -> Option<usize> {
+        self.location.map(|(line, _)| line)
+    }
+
+    /// The 1-based column number of the error, if known.
+    pub fn column(&self) -> Option<usize> {
+        self.location.map(|(_, col)| col)
+    }
+
+    /// What kind of error this is.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// A description of the error, without its location.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Errors are displayed as `file:line:col: message`, like a compiler would.
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some((line, col)) = self.location {
+            write!(f, ":{}:{}", line, col)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for InputError {}
+
+/// Loads input files, keeping track of the files that have been read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputLoader {
+    files_read: Vec<PathBuf>,
+    include_key: String,
+}
+
+impl Default for InputLoader {
+    fn default() -> Self {
+        InputLoader {
+            files_read: vec![],
+            include_key: "include".into(),
+        }
+    }
+}
+
+impl InputLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load and deserialize a file, using its extension to determine the format.
+    pub fn load<T: DeserializeOwned, P: AsRef<Path>>(&mut self, path: P) -> Result<T, InputError> {
+        let path = path.as_ref();
+        let format = Format::from_path(path).ok_or_else(|| self.unknown_format(path))?;
+        self.load_as(path, format)
+    }
+
+    fn unknown_format(&self, path: &Path) -> InputError {
+        InputError {
+            path: path.to_path_buf(),
+            location: None,
+            kind: ErrorKind::UnknownFormat,
+            message: "unknown input format, expected one of .json, .ron, .toml, .yaml or .yml".into(),
+        }
+    }
+
+    /// Load and deserialize a file in the given format, regardless of its extension.
+    pub fn load_as<T: DeserializeOwned, P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        format: Format,
+    ) -> Result<T, InputError> {
+        let path = path.as_ref();
+        let text = self.read(path)?;
+        format.parse(&text, path)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<String, InputError> {
+        // Record the file even if reading it fails, so that the generator
+        // is re-run once the problem is fixed.
+        self.track(path);
+        std::fs::read_to_string(path).map_err(|e| InputError {
+            path: path.to_path_buf(),
+            location: None,
+            kind: ErrorKind::Io,
+            message: e.to_string(),
+        })
+    }
+
+    /// Record that a file was read by some other means, for dependency tracking.
+    pub fn track<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        if !self.files_read.iter().any(|p| p == path) {
+            self.files_read.push(path.to_path_buf());
+        }
+    }
+
+    /// The files that have been read so far, in the order they were first read.
+    pub fn files_read(&self) -> &[PathBuf] {
+        &self.files_read
+    }
+}
+
+/// Load and deserialize a single file, using its extension to determine the format.
+pub fn load<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<T, InputError> {
+    InputLoader::new().load(path)
+}
@@ -0,0 +1,168 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+use super::*;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct StatusCodes {
+    codes: Vec<(u16, String)>,
+}
+
+fn expected() -> StatusCodes {
+    StatusCodes {
+        codes: vec![(200, "OK".into()), (404, "Not Found".into())],
+    }
+}
+
+fn parse(format: Format, text: &str) -> Result<StatusCodes, String> {
+    format.parse(text, Path::new("input")).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_detect_format_from_extension() {
+    assert_eq!(Format::from_path("a/b.yml"), Some(Format::Yaml));
+    assert_eq!(Format::from_path("codes.YAML"), Some(Format::Yaml));
+    assert_eq!(Format::from_path("codes.json"), Some(Format::Json));
+    assert_eq!(Format::from_path("Cargo.toml"), Some(Format::Toml));
+    assert_eq!(Format::from_path("codes.ron"), Some(Format::Ron));
+    assert_eq!(Format::from_path("codes.txt"), None);
+    assert_eq!(Format::from_path("codes"), None);
+}
+
+#[test]
+fn test_parse_each_format() {
+    let yaml = "codes:\n  - [200, OK]\n  - [404, Not Found]\n";
+    assert_eq!(parse(Format::Yaml, yaml), Ok(expected()));
+    let json = r#"{"codes": [[200, "OK"], [404, "Not Found"]]}"#;
+    assert_eq!(parse(Format::Json, json), Ok(expected()));
+    let toml = "codes = [[200, \"OK\"], [404, \"Not Found\"]]\n";
+    assert_eq!(parse(Format::Toml, toml), Ok(expected()));
+    let ron = "(codes: [(200, \"OK\"), (404, \"Not Found\")])";
+    assert_eq!(parse(Format::Ron, ron), Ok(expected()));
+}
+
+#[test]
+fn test_report_errors_with_location() {
+    let yaml = "codes:\n  - [200, OK]\n  - [four, Not Found]\n";
+    assert_eq!(
+        parse(Format::Yaml, yaml).unwrap_err(),
+        "input:3:6: codes[1][0]: invalid type: string \"four\", expected u16"
+    );
+    let json = "{\"codes\": [\n  [200, \"OK\"],\n  [404]\n]}";
+    assert_eq!(
+        parse(Format::Json, json).unwrap_err(),
+        "input:3:7: codes[1]: invalid length 1, expected a tuple of size 2"
+    );
+    let json = "{\"codes\": []} trailing";
+    assert_eq!(parse(Format::Json, json).unwrap_err(), "input:1:15: trailing characters");
+    let toml = "codes = [[200, \"OK\"],\n  [404, 42]]\n";
+    assert_eq!(
+        parse(Format::Toml, toml).unwrap_err(),
+        "input:2:9: codes[1][1]: invalid type: integer `42`, expected a string"
+    );
+    let ron = "(codes: [\n  (200, \"OK\"),\n  (404, \"Not Found\", 1),\n])";
+    assert_eq!(
+        parse(Format::Ron, ron).unwrap_err(),
+        "input:3:22: codes[1]: Expected closing `)`"
+    );
+    let ron = "(codez: [])";
+    assert_eq!(
+        parse(Format::Ron, ron).unwrap_err(),
+        "input:1:11: Unexpected missing field `codes` in `StatusCodes`"
+    );
+}
+
+#[test]
+fn test_record_files_read() {
+    let dir = std::env::temp_dir().join(format!("codemaker-input-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let codes = dir.join("codes.json");
+    std::fs::write(&codes, r#"{"codes": [[200, "OK"], [404, "Not Found"]]}"#).unwrap();
+
+    let mut loader = InputLoader::new();
+    assert_eq!(loader.load::<StatusCodes, _>(&codes).unwrap(), expected());
+    let err = loader.load::<StatusCodes, _>(dir.join("missing.yaml")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Io);
+    assert_eq!(err.line(), None);
+    let err = loader.load::<StatusCodes, _>(dir.join("codes.txt")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFormat);
+    loader.load::<StatusCodes, _>(&codes).unwrap();
+    assert_eq!(loader.files_read(), &[codes, dir.join("missing.yaml")]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_load_set_with_includes() {
+    let dir = std::env::temp_dir().join(format!("codemaker-input-set-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("codes")).unwrap();
+    let write = |name: &str, text: &str| std::fs::write(dir.join(name), text).unwrap();
+    write("root.yaml", "include: [codes/ok.json, codes/missing.toml]\ncodes:\n  - [418, I'm a teapot]\n");
+    write("codes/ok.json", r#"{"include": "common.yaml", "codes": [[200, "OK"]]}"#);
+    write("codes/missing.toml", "include = [\"common.yaml\"]\ncodes = [[404, \"Not Found\"]]\n");
+    write("codes/common.yaml", "codes:\n  - [100, Continue]\n");
+
+    let mut loader = InputLoader::new();
+    let codes: StatusCodes = loader.load_set(dir.join("root.yaml")).unwrap();
+    assert_eq!(
+        codes.codes,
+        vec![
+            (100, "Continue".into()),
+            (200, "OK".into()),
+            (404, "Not Found".into()),
+            (418, "I'm a teapot".into()),
+        ]
+    );
+    assert_eq!(loader.files_read().len(), 4);
+
+    // Errors in the merged value are reported against the file that the value came from.
+    write("codes/missing.toml", "codes = [[404, 42]]\n");
+    let err = loader.load_set::<StatusCodes, _>(dir.join("root.yaml")).unwrap_err();
+    assert_eq!(err.path(), dir.join("codes/missing.toml"));
+    assert_eq!(err.kind(), ErrorKind::Invalid);
+    assert_eq!(err.message(), "codes[0][1]: invalid type: integer `42`, expected a string");
+    assert_eq!((err.line(), err.column()), (Some(1), Some(16)));
+    write("codes/missing.toml", "codes = [[404, \"Not Found\"]]\n");
+    write("codes/ok.json", "{\"include\": \"common.yaml\",\n \"codes\": [\n  [200, \"OK\"],\n  [201]\n]}");
+    let err = loader.load_set::<StatusCodes, _>(dir.join("root.yaml")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "{}:4:3: codes[1]: invalid length 1, expected a tuple of size 2",
+            dir.join("codes/ok.json").display()
+        )
+    );
+    write("codes/ok.json", r#"{"include": "common.yaml", "codes": [[200, "OK"]]}"#);
+    write("root.yaml", "include: [codes/ok.json, codes/missing.toml]\ncodes:\n  - [418, 419]\n");
+    let err = loader.load_set::<StatusCodes, _>(dir.join("root.yaml")).unwrap_err();
+    assert_eq!(err.path(), dir.join("root.yaml"));
+    assert_eq!(err.message(), "codes[0][1]: invalid type: integer `419`, expected a string");
+    assert_eq!((err.line(), err.column()), (Some(3), Some(11)));
+
+    write("codes/common.yaml", "include: ../root.yaml\n");
+    let err = loader.load_set::<StatusCodes, _>(dir.join("root.yaml")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Include);
+    assert_eq!(
+        err.message(),
+        format!(
+            "include cycle: {} -> {} -> {} -> {}",
+            dir.join("root.yaml").display(),
+            dir.join("codes/ok.json").display(),
+            dir.join("codes/common.yaml").display(),
+            dir.join("codes/../root.yaml").display(),
+        )
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
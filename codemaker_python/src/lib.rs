@@ -20,25 +20,74 @@
 //! code from Rust. We'll see how it works out...
 
 use codemaker::traits::*;
+use codemaker::{Doc, Origin, RenderOptions, SourceWriter};
+use std::io::Write;
+use codemaker_derive::FluentBuilder;
 
 pub use codemaker_python_macros::quoted_rule;
 
-const INDENT: &'static str = "    ";
+#[cfg(test)]
+mod tests;
+
+mod link;
+pub use link::LinkError;
+
+/// The indentation level of a block of code, displayed as the indentation for its lines.
+#[derive(Clone, Copy)]
+struct Indent<'o> {
+    options: &'o RenderOptions,
+    level: usize,
+}
+
+impl<'o> Indent<'o> {
+    fn top(options: &'o RenderOptions) -> Self {
+        Indent { options, level: 0 }
+    }
+
+    fn deeper(self) -> Self {
+        Indent {
+            level: self.level + 1,
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for Indent<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.options.indentation(self.level))
+    }
+}
+
+/// Write a line laid out from a [`Doc`], so that it's broken up if it would be too long.
+fn write_doc_line<W: std::io::Write>(writer: &mut W, indent: Indent<'_>, doc: Doc) -> std::io::Result<()> {
+    write!(writer, "{}", indent)?;
+    doc.write_into(writer, indent.options, indent.level)?;
+    writeln!(writer)
+}
+
+/// A comma-separated list that is broken one item per line, with a trailing comma, if it doesn't fit.
+fn comma_list<I: IntoIterator<Item = Doc>>(items: I) -> Doc {
+    Doc::softline()
+        .append(Doc::join(items, Doc::text(",").append(Doc::line())))
+        .append(Doc::if_break(Doc::text(","), Doc::nil()))
+        .nest()
+        .append(Doc::softline())
+}
 
 macro_rules! indented_writeln {
     ($writer:expr, $indent:expr, $fmt:literal $($tail:tt)*) => {
-        writeln!($writer, concat!("{}", $fmt), INDENT.repeat($indent) $($tail)*)
+        writeln!($writer, concat!("{}", $fmt), $indent $($tail)*)
     };
 }
 
 macro_rules! indented_write {
     ($writer:expr, $indent:expr, $fmt:literal $($tail:tt)*) => {
-        write!($writer, concat!("{}", $fmt), INDENT.repeat($indent) $($tail)*)
+        write!($writer, concat!("{}", $fmt), $indent $($tail)*)
     };
 }
 
 /// A Python package, the highest-level output format for Python code.
-
+#[derive(Debug, PartialEq, FluentBuilder)]
 pub struct Package {
     dirpath: std::path::PathBuf,
     root_module: Module,
@@ -66,13 +115,11 @@ impl codemaker::OutputFileSet for Package {
         vec![&self.root_module]
             .into_iter()
             .chain(self.submodules.iter())
-            .chain(self.subpackages.iter().map(|p| p.files()).flatten())
+            .chain(self.subpackages.iter().flat_map(|p| p.files()))
             .collect()
     }
 }
 
-impl FluentAPI for Package {}
-
 /// Adding Statements to a Package, puts them in its root module.
 impl std::iter::Extend<Statement> for Package {
     fn extend<T: IntoIterator<Item = Statement>>(&mut self, iter: T) {
@@ -104,9 +151,10 @@ impl std::iter::Extend<Package> for Package {
 }
 
 /// A Python module, a single file containing Python source code.
-
+#[derive(Debug, PartialEq, FluentBuilder)]
 pub struct Module {
     filepath: std::path::PathBuf,
+    #[fluent(extend)]
     statements: Vec<Statement>,
 }
 
@@ -120,25 +168,62 @@ impl Module {
     }
 }
 
+impl Module {
+    /// A module too large to build in memory, whose statements are made lazily as it's written.
+    ///
+    /// See [`codemaker::StreamingFile`] for details.
+    pub fn streaming<'a, T, F, I>(name: T, statements: F) -> codemaker::StreamingFile<'a, Statement>
+    where
+        T: AsRef<str>,
+        F: Fn() -> I + 'a,
+        I: IntoIterator<Item = Statement>,
+        I::IntoIter: 'a,
+    {
+        codemaker::StreamingFile::new(format!("{}.py", name.as_ref()), statements)
+    }
+}
+
 impl codemaker::OutputFile for Module {
     fn path(&self) -> &std::path::Path {
         self.filepath.as_path()
     }
     fn write_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        for stmt in &self.statements {
-            stmt.write_into(writer, 0)?
-        }
+        self.write_statements(&mut SourceWriter::new(writer), &RenderOptions::default())
+    }
+    fn write_into_with_options<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        let mut writer = options.writer(writer);
+        self.write_statements(&mut SourceWriter::new(&mut writer), options)?;
+        writer.finish()?;
         Ok(())
     }
+    fn write_into_with_source_map<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        options: &RenderOptions,
+    ) -> std::io::Result<codemaker::SourceMap> {
+        let mut writer = options.writer(writer);
+        let mut source_writer = SourceWriter::with_source_map(&mut writer);
+        self.write_statements(&mut source_writer, options)?;
+        let map = source_writer.into_source_map();
+        writer.finish()?;
+        Ok(map)
+    }
 }
 
-impl FluentAPI for Module {}
-
-impl std::iter::Extend<Statement> for Module {
-    fn extend<I: IntoIterator<Item = Statement>>(&mut self, iter: I) {
-        for item in iter {
-            self.statements.push(item);
+impl Module {
+    fn write_statements<W: std::io::Write>(
+        &self,
+        writer: &mut SourceWriter<'_, W>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        for stmt in &self.statements {
+            stmt.write_into(writer, Indent::top(options))?
         }
+        Ok(())
     }
 }
 
@@ -147,10 +232,12 @@ impl std::iter::Extend<Statement> for Module {
 /// This is an Enum to allow different kinds of Statement to be conveniently stored
 /// in a single list. For actually builting the API, you almost certainly want to use
 /// one of the contained types like [`Assignment`] or [`FunctionDefinition`].
+#[derive(Debug, PartialEq)]
 pub enum Statement {
     Assign(Assignment),
     FuncDef(FunctionDefinition),
     IfElse(IfElse),
+    Import(Import),
     Return(Return),
     Raw(String),
 }
@@ -160,148 +247,193 @@ impl Statement {
         Statement::Raw(stmt.into())
     }
 
-    fn write_into<W: std::io::Write>(&self, writer: &mut W, indent: usize) -> std::io::Result<()> {
+    fn write_into<W: std::io::Write>(&self, writer: &mut SourceWriter<'_, W>, indent: Indent<'_>) -> std::io::Result<()> {
+        writer.track(self.origin(), |writer| {
+            match self {
+                Self::Assign(a) => a.write_into(writer, indent)?,
+                Self::FuncDef(f) => f.write_into(writer, indent)?,
+                Self::IfElse(ie) => ie.write_into(writer, indent)?,
+                Self::Import(i) => i.write_into(writer, indent)?,
+                Self::Return(r) => r.write_into(writer, indent)?,
+                Self::Raw(ln) => indented_writeln!(writer, indent, "{}", ln)?,
+            }
+            Ok(())
+        })
+    }
+
+    /// Which rule made this statement, if provenance tracking was enabled at the time.
+    ///
+    /// Raw statements don't record an origin, and are mapped as part of whatever
+    /// statement contains them.
+    pub fn origin(&self) -> Option<&Origin> {
         match self {
-            Self::Assign(a) => a.write_into(writer, indent)?,
-            Self::FuncDef(f) => f.write_into(writer, indent)?,
-            Self::IfElse(ie) => ie.write_into(writer, indent)?,
-            Self::Return(r) => r.write_into(writer, indent)?,
-            Self::Raw(ln) => indented_writeln!(writer, indent, "{}", ln)?,
+            Self::Assign(a) => a.origin.as_ref(),
+            Self::FuncDef(f) => f.origin.as_ref(),
+            Self::IfElse(ie) => ie.origin.as_ref(),
+            Self::Import(i) => i.origin.as_ref(),
+            Self::Return(r) => r.origin.as_ref(),
+            Self::Raw(_) => None,
+        }
+    }
+
+    /// The logical identity and Python name of the symbol defined by this statement, if any.
+    fn defined_symbol(&self) -> Option<(&str, &str)> {
+        match self {
+            Self::Assign(a) => Some((a.symbol.as_deref().unwrap_or(&a.target), &a.target)),
+            Self::FuncDef(f) => Some((f.symbol.as_deref().unwrap_or(&f.name), &f.name)),
+            _ => None,
+        }
+    }
+
+    /// Call a function on each symbol reference contained in this statement.
+    fn visit_symbols_mut(&mut self, func: &mut dyn FnMut(&mut SymbolRef)) {
+        match self {
+            Self::FuncDef(f) => f.body.iter_mut().for_each(|s| s.visit_symbols_mut(func)),
+            Self::IfElse(ie) => {
+                ie.condition.visit_symbols_mut(func);
+                ie.body_if
+                    .body
+                    .iter_mut()
+                    .for_each(|s| s.visit_symbols_mut(func));
+                ie.body_else
+                    .body
+                    .iter_mut()
+                    .for_each(|s| s.visit_symbols_mut(func));
+            }
+            Self::Return(r) => r.value.visit_symbols_mut(func),
+            Self::Assign(_) | Self::Import(_) | Self::Raw(_) => (),
         }
-        Ok(())
     }
 }
 
+#[derive(Debug, FluentBuilder)]
+#[fluent(into(Statement::Assign))]
 pub struct Assignment {
+    #[fluent(new)]
     target: String, // TODO: could also be item assigment etc
+    #[fluent(new)]
     value: String,  // TODO: should be generic "Expression" type.
+    symbol: Option<String>,
+    #[fluent(origin)]
+    origin: Option<Origin>,
 }
 
 impl Assignment {
-    pub fn new<T1: Into<String>, T2: Into<String>>(target: T1, value: T2) -> Self {
-        Assignment {
-            target: target.into(),
-            value: value.into(),
-        }
+    /// Give the assigned variable a logical identity, for use with [`SymbolRef`].
+    ///
+    /// By default the logical identity of a definition is just its name.
+    pub fn with_symbol<T: Into<String>>(mut self, id: T) -> Self {
+        self.symbol = Some(id.into());
+        self
     }
 
-    fn write_into<W: std::io::Write>(&self, writer: &mut W, indent: usize) -> std::io::Result<()> {
+    fn write_into<W: std::io::Write>(&self, writer: &mut SourceWriter<'_, W>, indent: Indent<'_>) -> std::io::Result<()> {
         indented_writeln!(writer, indent, "{} = {}", self.target, self.value)?;
         Ok(())
     }
 }
 
-impl Into<Statement> for Assignment {
-    fn into(self) -> Statement {
-        Statement::Assign(self)
+/// An import statement, either `import module` or `from module import names`.
+#[derive(Debug, FluentBuilder)]
+#[fluent(into(Statement::Import))]
+pub struct Import {
+    #[fluent(new)]
+    module: String,
+    #[fluent(add = "add_name", add_all = "add_names")]
+    names: Vec<String>,
+    #[fluent(origin)]
+    origin: Option<Origin>,
+}
+
+impl Import {
+    fn write_into<W: std::io::Write>(&self, writer: &mut SourceWriter<'_, W>, indent: Indent<'_>) -> std::io::Result<()> {
+        if self.names.is_empty() {
+            indented_writeln!(writer, indent, "import {}", self.module)?;
+        } else {
+            // Long lists of names need to be parenthesized in order to be split over several lines.
+            let doc = Doc::text(&format!("from {} import ", self.module))
+                .append(Doc::if_break(Doc::text("("), Doc::nil()))
+                .append(comma_list(self.names.iter().map(Doc::text)))
+                .append(Doc::if_break(Doc::text(")"), Doc::nil()))
+                .group();
+            write_doc_line(writer, indent, doc)?;
+        }
+        Ok(())
     }
 }
 
+#[derive(Debug, FluentBuilder)]
+#[fluent(into(Statement::Return))]
 pub struct Return {
+    #[fluent(new)]
     value: Expression,
+    #[fluent(origin)]
+    origin: Option<Origin>,
 }
 
 impl Return {
-    pub fn new<T: Into<Expression>>(value: T) -> Self {
-        Return {
-            value: value.into(),
-        }
-    }
-
-    fn write_into<W: std::io::Write>(&self, writer: &mut W, indent: usize) -> std::io::Result<()> {
+    fn write_into<W: std::io::Write>(&self, writer: &mut SourceWriter<'_, W>, indent: Indent<'_>) -> std::io::Result<()> {
         indented_write!(writer, indent, "return ")?;
         self.value.write_into(writer)?;
-        writeln!(writer, "")?;
+        writeln!(writer)?;
         Ok(())
     }
 }
 
-impl Into<Statement> for Return {
-    fn into(self) -> Statement {
-        Statement::Return(self)
-    }
-}
-
-
+#[derive(Debug, FluentBuilder)]
+#[fluent(into(Statement::FuncDef))]
 pub struct FunctionDefinition {
+    #[fluent(new)]
     name: String,
+    #[fluent(add = "add_arg", add_all = "add_args")]
     args: Vec<String>, // TODO: a richer arg type, with defaults etc
+    #[fluent(extend)]
     body: Vec<Statement>,
+    symbol: Option<String>,
+    #[fluent(origin)]
+    origin: Option<Origin>,
 }
 
 impl FunctionDefinition {
-    pub fn new<T: Into<String>>(name: T) -> Self {
-        FunctionDefinition {
-            name: name.into(),
-            args: vec![],
-            body: vec![],
-        }
+    /// Give the function a logical identity, for use with [`SymbolRef`].
+    ///
+    /// By default the logical identity of a definition is just its name.
+    pub fn with_symbol<T: Into<String>>(mut self, id: T) -> Self {
+        self.symbol = Some(id.into());
+        self
     }
 
-    fn write_into<W: std::io::Write>(&self, writer: &mut W, indent: usize) -> std::io::Result<()> {
-        indented_write!(writer, indent, "def {}(", self.name)?;
-        let mut args = self.args.iter();
-        // Writing args, with nice commas.
-        match args.next() {
-            None => (),
-            Some(arg) => {
-                write!(writer, "{}", arg)?;
-                for arg in args {
-                    write!(writer, ", {}", arg)?;
-                }
-            }
-        }
-        writeln!(writer, "):")?;
+    fn write_into<W: std::io::Write>(&self, writer: &mut SourceWriter<'_, W>, indent: Indent<'_>) -> std::io::Result<()> {
+        let args = if self.args.is_empty() {
+            Doc::nil()
+        } else {
+            comma_list(self.args.iter().map(Doc::text))
+        };
+        let doc = Doc::text(&format!("def {}(", self.name))
+            .append(args)
+            .append(Doc::text("):"))
+            .group();
+        write_doc_line(writer, indent, doc)?;
         if self.body.is_empty() {
-            indented_writeln!(writer, indent + 1, "pass")?;
+            indented_writeln!(writer, indent.deeper(), "pass")?;
         } else {
             for stmt in &self.body {
-                stmt.write_into(writer, indent + 1)?;
+                stmt.write_into(writer, indent.deeper())?;
             }
         }
         Ok(())
     }
-
-    pub fn add_arg<T: Into<String>>(mut self, name: T) -> Self {
-        self.args.push(name.into());
-        return self;
-    }
-
-    pub fn add_args<T: Into<String>, I: IntoIterator<Item=T>>(mut self, name: I) -> Self {
-        self.args.extend(name.into_iter().map(Into::into));
-        return self;
-    }
-}
-
-impl Into<Statement> for FunctionDefinition {
-    fn into(self) -> Statement {
-        Statement::FuncDef(self)
-    }
 }
 
-impl FluentAPI for FunctionDefinition {}
-
-impl std::iter::Extend<Statement> for FunctionDefinition {
-    fn extend<I: IntoIterator<Item = Statement>>(&mut self, iter: I) {
-        for item in iter {
-            self.body.push(item);
-        }
-    }
-}
-
-
-
-#[derive(Default)]
+#[derive(Debug, Default, PartialEq, FluentBuilder)]
+#[fluent(new)]
 pub struct Block {
+    #[fluent(extend)]
     body: Vec<Statement>,
 }
 
 impl Block {
-    pub fn new() -> Self {
-        Block { body: vec![] }
-    }
-
-    fn write_into<W: std::io::Write>(&self, writer: &mut W, indent: usize) -> std::io::Result<()> {
+    fn write_into<W: std::io::Write>(&self, writer: &mut SourceWriter<'_, W>, indent: Indent<'_>) -> std::io::Result<()> {
         if self.body.is_empty() {
             indented_writeln!(writer, indent, "pass")?;
         } else {
@@ -319,72 +451,64 @@ impl Block {
     }
 }
 
-impl FluentAPI for Block {}
-
-impl std::iter::Extend<Statement> for Block {
-    fn extend<I: IntoIterator<Item = Statement>>(&mut self, iter: I) {
-        for item in iter {
-            self.body.push(item);
-        }
-    }
-}
-
+#[derive(Debug, FluentBuilder)]
+#[fluent(into(Statement::IfElse))]
 pub struct IfElse {
+    #[fluent(new)]
     condition: Expression,
+    #[fluent(with = "with_body_if")]
     body_if: Block,
+    #[fluent(with = "with_body_else")]
     body_else: Block,
+    #[fluent(origin)]
+    origin: Option<Origin>,
 }
 
 impl IfElse {
-    pub fn new<T: Into<Expression>>(condition: T) -> Self {
-        IfElse {
-            condition: condition.into(),
-            body_if: Block::new(),
-            body_else: Block::new(),
-        }
-    }
-
-    fn write_into<W: std::io::Write>(&self, writer: &mut W, indent: usize) -> std::io::Result<()> {
+    fn write_into<W: std::io::Write>(&self, writer: &mut SourceWriter<'_, W>, indent: Indent<'_>) -> std::io::Result<()> {
         indented_write!(writer, indent, "if ")?;
         self.condition.write_into(writer)?;
         writeln!(writer, ":")?;
-        self.body_if.write_into(writer, indent + 1)?;
+        self.body_if.write_into(writer, indent.deeper())?;
         if ! self.body_else.is_empty() {
             indented_writeln!(writer, indent, "else:")?;
-            self.body_else.write_into(writer, indent + 1)?;
+            self.body_else.write_into(writer, indent.deeper())?;
         }
         Ok(())
     }
-
-    pub fn with_body_if<F>(mut self, func: F) -> Self
-    where
-        F: FnOnce(Block) -> Block
-    {
-        self.body_if = func(std::mem::take(&mut self.body_if));
-        self
-    }
-
-    pub fn with_body_else<F>(mut self, func: F) -> Self
-    where
-        F: FnOnce(Block) -> Block
-    {
-        self.body_else = func(std::mem::take(&mut self.body_else));
-        self
-    }
 }
 
-impl Into<Statement> for IfElse {
-    fn into(self) -> Statement {
-        Statement::IfElse(self)
-    }
+/// Implement `PartialEq` for statements, ignoring the `origin` field.
+///
+/// The origin records which rule made a statement rather than what it says, so
+/// otherwise-identical statements made with and without [`codemaker::with_provenance`]
+/// should still compare equal.
+macro_rules! impl_eq_ignoring_origin {
+    ($($Node:ident { $($field:ident),* })*) => {
+        $(
+            impl PartialEq for $Node {
+                fn eq(&self, other: &Self) -> bool {
+                    let $Node { $($field,)* origin: _ } = self;
+                    true $(&& *$field == other.$field)*
+                }
+            }
+        )*
+    };
 }
 
-impl FluentAPI for IfElse {}
-
+impl_eq_ignoring_origin! {
+    Assignment { target, value, symbol }
+    Import { module, names }
+    Return { value }
+    FunctionDefinition { name, args, body, symbol }
+    IfElse { condition, body_if, body_else }
+}
 
+#[derive(Debug, PartialEq)]
 pub enum Expression {
     Equals(Box<Expression>, Box<Expression>),
     Literal(String),
+    Symbol(SymbolRef),
     Variable(String),
 }
 
@@ -392,6 +516,9 @@ impl Expression {
     pub fn new_equals<T1: Into<Expression>, T2: Into<Expression>>(lhs: T1, rhs: T2) -> Self {
         Expression::Equals(Box::new(lhs.into()), Box::new(rhs.into()))
     }
+    pub fn new_symbol<T: Into<String>>(id: T) -> Self {
+        Expression::Symbol(SymbolRef::new(id))
+    }
     pub fn new_variable<T: Into<String>>(name: T) -> Self {
         Expression::Variable(name.into())
     }
@@ -403,10 +530,64 @@ impl Expression {
                 rhs.write_into(writer)?;
             },
             Self::Literal(lit) => write!(writer, "{}", lit)?,
+            Self::Symbol(sym) => write!(writer, "{}", sym.name())?,
             Self::Variable(name) => write!(writer, "{}", name)?,
         }
         Ok(())
     }
+    fn visit_symbols_mut(&mut self, func: &mut dyn FnMut(&mut SymbolRef)) {
+        match self {
+            Self::Equals(lhs, rhs) => {
+                lhs.visit_symbols_mut(func);
+                rhs.visit_symbols_mut(func);
+            }
+            Self::Symbol(sym) => func(sym),
+            Self::Literal(_) | Self::Variable(_) => (),
+        }
+    }
+}
+
+/// A reference to a definition by its logical identity, rather than its Python name.
+///
+/// This lets a rule refer to a class or function without knowing which module it will
+/// end up being defined in. After the whole [`Package`] has been assembled, calling
+/// [`Package::link`] will find the definition for each reference, resolve it to the
+/// correct name, and add any imports needed to make that name available.
+///
+/// Each [`Assignment`] and [`FunctionDefinition`] at the top level of a module defines
+/// a symbol whose logical identity is its name, or the identity given by `with_symbol`.
+#[derive(Debug, PartialEq)]
+pub struct SymbolRef {
+    id: String,
+    resolved: Option<String>,
+}
+
+impl SymbolRef {
+    pub fn new<T: Into<String>>(id: T) -> Self {
+        SymbolRef {
+            id: id.into(),
+            resolved: None,
+        }
+    }
+
+    /// The logical identity of the referenced definition.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The name to use for the referenced definition in Python code.
+    ///
+    /// This will be the logical identity of the definition until it has been resolved
+    /// by [`Package::link`].
+    pub fn name(&self) -> &str {
+        self.resolved.as_deref().unwrap_or(&self.id)
+    }
+}
+
+impl From<SymbolRef> for Expression {
+    fn from(value: SymbolRef) -> Expression {
+        Expression::Symbol(value)
+    }
 }
 
 impl From<&str> for Expression {
@@ -427,4 +608,52 @@ impl From<&u16> for Expression {
     fn from(value: &u16) -> Expression {
         Expression::Literal(format!("{}", value))
     }
-}
\ No newline at end of file
+}
+
+/// Rendering a Package renders each of its files in turn, with a comment giving the path of each.
+impl codemaker::Render for Package {
+    fn render_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for file in codemaker::OutputFileSet::files(self) {
+            writeln!(writer, "# {}", file.filepath.display())?;
+            codemaker::OutputFile::write_into(file, writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl codemaker::Render for Module {
+    fn render_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.write_statements(&mut SourceWriter::new(writer), &RenderOptions::default())
+    }
+    fn render_into_with_options<W: std::io::Write>(&self, writer: &mut W, options: &RenderOptions) -> std::io::Result<()> {
+        self.write_statements(&mut SourceWriter::new(writer), options)
+    }
+}
+
+impl codemaker::Render for Expression {
+    fn render_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.write_into(writer)
+    }
+}
+
+/// Statements rendered on their own are written at the top level, without any indentation.
+macro_rules! impl_render_for_statements {
+    ($($ty:ty),*) => {
+        $(
+            impl codemaker::Render for $ty {
+                fn render_into<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+                    self.write_into(&mut SourceWriter::new(writer), Indent::top(&RenderOptions::default()))
+                }
+                fn render_into_with_options<W: std::io::Write>(
+                    &self,
+                    writer: &mut W,
+                    options: &RenderOptions,
+                ) -> std::io::Result<()> {
+                    self.write_into(&mut SourceWriter::new(writer), Indent::top(options))
+                }
+            }
+        )*
+    };
+}
+
+impl_render_for_statements!(Statement, Assignment, Import, Return, FunctionDefinition, Block, IfElse);
@@ -0,0 +1,240 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+
+//! Resolving [`SymbolRef`]s across the modules of a [`Package`].
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::{Import, Module, Package, Statement, SymbolRef};
+
+/// An error from linking the symbol references in a [`Package`].
+#[derive(Debug)]
+pub enum LinkError {
+    /// A symbol was referenced, but no module in the package defines it.
+    UndefinedSymbol { id: String, module: String },
+    /// A symbol was referenced, but more than one module in the package defines it.
+    AmbiguousSymbol {
+        id: String,
+        module: String,
+        candidates: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UndefinedSymbol { id, module } => {
+                write!(
+                    f,
+                    "undefined symbol {:?} referenced in module {}",
+                    id, module
+                )
+            }
+            Self::AmbiguousSymbol {
+                id,
+                module,
+                candidates,
+            } => write!(
+                f,
+                "ambiguous symbol {:?} referenced in module {}, defined in: {}",
+                id,
+                module,
+                candidates.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl Package {
+    /// Resolve all the [`SymbolRef`]s in the package, adding imports where necessary.
+    ///
+    /// This is the "link phase" for a package, and should be called once it has been
+    /// fully assembled. For each symbol reference it finds the module that defines the
+    /// symbol and then:
+    ///
+    ///  * If the definition is in the same module, refers to it by its plain name.
+    ///  * Otherwise, adds `from other.module import Name` to the top of the module
+    ///    and refers to it by its plain name.
+    ///  * If that would clash with another name in the module, instead adds
+    ///    `import other.module` and refers to it as `other.module.Name`.
+    ///
+    /// It's safe to call this more than once, since it won't add imports that are
+    /// already present.
+    pub fn link(&mut self) -> Result<(), LinkError> {
+        let mut definitions: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for (module_path, module) in self.modules_with_paths(None) {
+            for (id, name) in module
+                .statements
+                .iter()
+                .filter_map(Statement::defined_symbol)
+            {
+                definitions
+                    .entry(id.to_string())
+                    .or_default()
+                    .push((module_path.clone(), name.to_string()));
+            }
+        }
+        for (module_path, module) in self.modules_with_paths_mut(None) {
+            module.link(&module_path, &definitions)?;
+        }
+        Ok(())
+    }
+
+    /// The name of this package, as it appears in Python module paths.
+    fn name(&self) -> String {
+        self.dirpath
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Iterate over all the modules in this package, along with their dotted Python paths.
+    fn modules_with_paths(&self, parent: Option<&str>) -> Vec<(String, &Module)> {
+        let path = dotted_path(parent, &self.name());
+        let mut modules = vec![(path.clone(), &self.root_module)];
+        for m in &self.submodules {
+            modules.push((dotted_path(Some(&path), &m.name()), m));
+        }
+        for p in &self.subpackages {
+            modules.extend(p.modules_with_paths(Some(&path)));
+        }
+        modules
+    }
+
+    fn modules_with_paths_mut(&mut self, parent: Option<&str>) -> Vec<(String, &mut Module)> {
+        let path = dotted_path(parent, &self.name());
+        let mut modules = vec![(path.clone(), &mut self.root_module)];
+        for m in &mut self.submodules {
+            let name = m.name();
+            modules.push((dotted_path(Some(&path), &name), m));
+        }
+        for p in &mut self.subpackages {
+            modules.extend(p.modules_with_paths_mut(Some(&path)));
+        }
+        modules
+    }
+}
+
+impl Module {
+    /// The name of this module, as it appears in Python module paths.
+    fn name(&self) -> String {
+        self.filepath
+            .file_stem()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn link(
+        &mut self,
+        module_path: &str,
+        definitions: &HashMap<String, Vec<(String, String)>>,
+    ) -> Result<(), LinkError> {
+        let local_names: HashSet<String> = self
+            .statements
+            .iter()
+            .filter_map(Statement::defined_symbol)
+            .map(|(_, name)| name.to_string())
+            .collect();
+        // Which module we've imported each plain name from, so we can detect clashes.
+        let mut imported_names: HashMap<String, String> = HashMap::new();
+        let mut from_imports: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut plain_imports: BTreeSet<String> = BTreeSet::new();
+        // Report the first unresolvable symbol, in statement order.
+        let mut result = Ok(());
+        let mut resolve = |sym: &mut SymbolRef| {
+            let (def_module, name) = match definitions.get(&sym.id).map(Vec::as_slice) {
+                Some([(def_module, name)]) => (def_module, name),
+                Some(candidates) if !candidates.is_empty() => {
+                    if result.is_ok() {
+                        result = Err(LinkError::AmbiguousSymbol {
+                            id: sym.id.clone(),
+                            module: module_path.to_string(),
+                            candidates: candidates.iter().map(|(m, _)| m.clone()).collect(),
+                        });
+                    }
+                    return;
+                }
+                _ => {
+                    if result.is_ok() {
+                        result = Err(LinkError::UndefinedSymbol {
+                            id: sym.id.clone(),
+                            module: module_path.to_string(),
+                        });
+                    }
+                    return;
+                }
+            };
+            if def_module == module_path {
+                sym.resolved = Some(name.clone());
+                return;
+            }
+            let clashes = local_names.contains(name)
+                || imported_names.get(name).is_some_and(|m| m != def_module);
+            if clashes {
+                plain_imports.insert(def_module.clone());
+                sym.resolved = Some(format!("{}.{}", def_module, name));
+            } else {
+                imported_names.insert(name.clone(), def_module.clone());
+                from_imports
+                    .entry(def_module.clone())
+                    .or_default()
+                    .insert(name.clone());
+                sym.resolved = Some(name.clone());
+            }
+        };
+        for stmt in &mut self.statements {
+            stmt.visit_symbols_mut(&mut resolve);
+        }
+        result?;
+        let mut imports: Vec<Statement> = vec![];
+        for module in plain_imports {
+            if !self.has_import(&module, None) {
+                imports.push(Import::new(module).into());
+            }
+        }
+        for (module, names) in from_imports {
+            let names: Vec<String> = names
+                .into_iter()
+                .filter(|name| !self.has_import(&module, Some(name)))
+                .collect();
+            if !names.is_empty() {
+                imports.push(Import::new(module).add_names(names).into());
+            }
+        }
+        self.statements.splice(0..0, imports);
+        Ok(())
+    }
+
+    /// Check whether this module already contains the given import.
+    fn has_import(&self, module: &str, name: Option<&str>) -> bool {
+        self.statements.iter().any(|stmt| match stmt {
+            Statement::Import(i) if i.module == module => match name {
+                None => i.names.is_empty(),
+                Some(name) => i.names.iter().any(|n| n == name),
+            },
+            _ => false,
+        })
+    }
+}
+
+fn dotted_path(parent: Option<&str>, name: &str) -> String {
+    match parent {
+        None => name.to_string(),
+        Some(parent) => format!("{}.{}", parent, name),
+    }
+}
@@ -0,0 +1,306 @@
+/* Copyright 2021 Ryan F Kelly
+ *
+ * Licensed under the Apache License (Version 2.0), or the MIT license,
+ * (the "Licenses") at your option. You may not use this file except in
+ * compliance with one of the Licenses. You may obtain copies of the
+ * Licenses at:
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *    http://opensource.org/licenses/MIT
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the Licenses is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the Licenses for the specific language governing permissions and
+ * limitations under the Licenses. */
+use super::*;
+use codemaker::{OutputFile, OutputFileSet};
+
+fn package_with_modules(name: &str, modules: Vec<Module>) -> Package {
+    Package::new(name).edit(|p| std::iter::Extend::extend(p, modules))
+}
+
+fn render_files(pkg: &Package) -> Vec<(String, String)> {
+    pkg.files()
+        .into_iter()
+        .map(|f| {
+            let mut buf = vec![];
+            f.write_into(&mut buf).unwrap();
+            (
+                f.path().to_string_lossy().into_owned(),
+                String::from_utf8(buf).unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_link_symbols_across_modules() {
+    let mut pkg = package_with_modules(
+        "pkg",
+        vec![
+            Module::new("a").push(
+                FunctionDefinition::new("get_default")
+                    .push(Return::new(Expression::new_symbol("DEFAULT"))),
+            ),
+            Module::new("b").push(Assignment::new("DEFAULT", "42")),
+            Module::new("c").push(
+                FunctionDefinition::new("helper")
+                    .with_symbol("c.helper")
+                    .push(Return::new(Expression::new_symbol("DEFAULT"))),
+            ),
+            Module::new("d")
+                .push(Assignment::new("helper", "None"))
+                .push(FunctionDefinition::new("uses_both").push(Return::new(
+                    Expression::new_equals(
+                        Expression::new_symbol("c.helper"),
+                        Expression::new_symbol("helper"),
+                    ),
+                ))),
+        ],
+    );
+    pkg.link().unwrap();
+    // Linking twice shouldn't add duplicate imports.
+    pkg.link().unwrap();
+    assert_eq!(
+        render_files(&pkg),
+        vec![
+            ("pkg/__init__.py".into(), "".into()),
+            (
+                "pkg/a.py".into(),
+                "from pkg.b import DEFAULT\ndef get_default():\n    return DEFAULT\n".into()
+            ),
+            ("pkg/b.py".into(), "DEFAULT = 42\n".into()),
+            (
+                "pkg/c.py".into(),
+                "from pkg.b import DEFAULT\ndef helper():\n    return DEFAULT\n".into()
+            ),
+            (
+                "pkg/d.py".into(),
+                "import pkg.c\nhelper = None\ndef uses_both():\n    return pkg.c.helper == helper\n".into()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_link_reports_bad_symbols() {
+    let mut pkg = package_with_modules(
+        "pkg",
+        vec![Module::new("a")
+            .push(FunctionDefinition::new("f").push(Return::new(SymbolRef::new("missing"))))],
+    );
+    assert_eq!(
+        pkg.link().unwrap_err().to_string(),
+        "undefined symbol \"missing\" referenced in module pkg.a"
+    );
+
+    let mut pkg = package_with_modules(
+        "pkg",
+        vec![Module::new("a")
+            .push(Return::new(SymbolRef::new("first")))
+            .push(Return::new(SymbolRef::new("second")))],
+    );
+    assert_eq!(
+        pkg.link().unwrap_err().to_string(),
+        "undefined symbol \"first\" referenced in module pkg.a"
+    );
+
+    let mut pkg = package_with_modules(
+        "pkg",
+        vec![
+            Module::new("a").push(Assignment::new("X", "1")),
+            Module::new("b").push(Assignment::new("X", "2")),
+        ],
+    )
+    .edit(|p| {
+        std::iter::Extend::extend(p, vec![Statement::from(Return::new(SymbolRef::new("X")))])
+    });
+    assert_eq!(
+        pkg.link().unwrap_err().to_string(),
+        "ambiguous symbol \"X\" referenced in module pkg, defined in: pkg.a, pkg.b"
+    );
+}
+
+#[test]
+fn test_source_map_for_rendered_module() {
+    struct TestMaker;
+
+    codemaker::define_codemaker_rules! {
+        TestMaker as self {
+            Vec<(&str, u16)> as input => Module {
+                Module::new("codes")
+                    .push(Statement::new_raw("# Generated"))
+                    .extend(self.make_from_iter(input))
+            }
+            (&str, u16) as (name, code) => Statement {
+                FunctionDefinition::new(name.to_lowercase())
+                    .push(Return::new(&code))
+                    .into()
+            }
+        }
+    }
+
+    let input = || vec![("OK", 200), ("CREATED", 201)];
+    let mut buf = vec![];
+    let map = TestMaker
+        .make_from(input())
+        .write_into_with_source_map(&mut buf, &RenderOptions::default())
+        .unwrap();
+    assert!(map.is_empty());
+
+    let module = codemaker::with_provenance(|| TestMaker.make_from(input()));
+    // Recording where things came from doesn't change what they are.
+    assert_eq!(module, TestMaker.make_from(input()));
+    let mut buf = vec![];
+    let map = module.write_into_with_source_map(&mut buf, &RenderOptions::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "# Generated\ndef ok():\n    return 200\ndef created():\n    return 201\n"
+    );
+    let lines: Vec<(usize, usize, &str)> = map
+        .entries()
+        .iter()
+        .map(|e| (e.start_line(), e.end_line(), e.origin().input_path()))
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            (2, 3, "Vec<(&str, u16)>[0] > (&str, u16)"),
+            (3, 3, "Vec<(&str, u16)>[0] > (&str, u16)"),
+            (4, 5, "Vec<(&str, u16)>[1] > (&str, u16)"),
+            (5, 5, "Vec<(&str, u16)>[1] > (&str, u16)"),
+        ]
+    );
+    assert_eq!(map.entries()[0].origin().output_type(), "Statement");
+
+    // Render options are applied without affecting the mapped lines.
+    let options = RenderOptions::new().indent(codemaker::IndentStyle::Tabs).line_ending(codemaker::LineEnding::CrLf);
+    let mut buf = vec![];
+    let styled_map = module.write_into_with_source_map(&mut buf, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "# Generated\r\ndef ok():\r\n\treturn 200\r\ndef created():\r\n\treturn 201\r\n"
+    );
+    assert_eq!(styled_map, map);
+}
+
+#[test]
+fn test_assert_makes_renders_python() {
+    use codemaker::{assert_makes, assert_makes_eq, define_codemaker_rules};
+
+    struct StatusMaker;
+
+    define_codemaker_rules! {
+        StatusMaker as self {
+            &(u16, String) as (code, name) => Statement {
+                IfElse::new(Expression::new_equals(Expression::new_variable("code"), code))
+                    .with_body_if(|b| b.push(Return::new(name)))
+                    .into()
+            }
+            &String as name => Assignment {
+                Assignment::new(name.to_uppercase(), format!("{:?}", name))
+            }
+        }
+    }
+
+    assert_makes!(
+        StatusMaker,
+        &(200, "OK".to_string()) => "if code == 200:\n    return \"OK\"\n"
+    );
+    assert_makes!(StatusMaker, &"ok".to_string() => "OK = \"ok\"\n");
+    assert_makes_eq!(StatusMaker, &"ok".to_string() => Assignment::new("OK", "\"ok\""));
+    assert_eq!(Expression::new_symbol("DEFAULT").render(), "DEFAULT");
+    assert_eq!(
+        package_with_modules("pkg", vec![Module::new("a").push(Assignment::new("A", "1"))])
+            .render(),
+        "# pkg/__init__.py\n# pkg/a.py\nA = 1\n"
+    );
+}
+
+#[test]
+fn test_render_with_options() {
+    let module = Module::new("codes").push(
+        FunctionDefinition::new("is_ok")
+            .add_arg("code")
+            .push(
+                IfElse::new(Expression::new_equals(
+                    Expression::new_variable("code"),
+                    Expression::from(&200u16),
+                ))
+                .with_body_if(|b| b.push(Return::new(Expression::new_variable("True")))),
+            )
+            .push(Statement::new_raw("return False  ")),
+    );
+    let render = |options: &codemaker::RenderOptions| {
+        let mut buf = vec![];
+        module.write_into_with_options(&mut buf, options).unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+    assert_eq!(
+        render(&codemaker::RenderOptions::default()),
+        "def is_ok(code):\n    if code == 200:\n        return True\n    return False  \n"
+    );
+    let options = codemaker::RenderOptions::new()
+        .indent(codemaker::IndentStyle::Tabs)
+        .line_ending(codemaker::LineEnding::CrLf)
+        .trim_trailing_whitespace(true);
+    assert_eq!(
+        render(&options),
+        "def is_ok(code):\r\n\tif code == 200:\r\n\t\treturn True\r\n\treturn False\r\n"
+    );
+}
+
+#[test]
+fn test_break_long_lines() {
+    let module = Module::new("codes")
+        .push(Import::new("http").add_names(vec!["HTTPStatus".to_string(), "client".to_string()]))
+        .push(
+            FunctionDefinition::new("describe_status")
+                .add_args(vec!["code".to_string(), "reason".to_string(), "verbose".to_string()])
+                .push(Return::new(Expression::new_variable("reason"))),
+        );
+    let render = |width: usize| {
+        let mut buf = vec![];
+        module
+            .write_into_with_options(&mut buf, &codemaker::RenderOptions::new().max_width(width))
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+    assert_eq!(
+        render(80),
+        "from http import HTTPStatus, client\n\
+         def describe_status(code, reason, verbose):\n    \
+             return reason\n"
+    );
+    assert_eq!(
+        render(30),
+        "from http import (\n    HTTPStatus,\n    client,\n)\n\
+         def describe_status(\n    code,\n    reason,\n    verbose,\n):\n    \
+             return reason\n"
+    );
+}
+
+#[test]
+fn test_stream_statements_into_module() {
+    let codes = [(200u16, "OK"), (404, "Not Found")];
+    let module = Module::streaming("codes", || {
+        codes.iter().map(|(code, name)| {
+            IfElse::new(Expression::new_equals(Expression::new_variable("code"), Expression::from(code)))
+                .with_body_if(|b| b.push(Return::new(Expression::from(*name))))
+                .into()
+        })
+    });
+    assert_eq!(module.path(), std::path::Path::new("codes.py"));
+    let mut buf = vec![];
+    module
+        .write_into_with_options(
+            &mut buf,
+            &codemaker::RenderOptions::new().indent(codemaker::IndentStyle::Spaces(2)),
+        )
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "if code == 200:\n  return \"OK\"\nif code == 404:\n  return \"Not Found\"\n"
+    );
+}
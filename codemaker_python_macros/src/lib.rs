@@ -39,7 +39,7 @@ impl Parse for QuotedRule {
             match ty.to_string().as_str() {
                 "Statement" => syn::parse2::<StatementBuilder>(tokens)?.to_token_stream(),
                 "FunctionDefinition" => syn::parse2::<FunctionDefinitionBuilder>(tokens)?.to_token_stream(),
-                _ => return Err(syn::Error::new_spanned(&ty, format!("unknown quoted Python syntax type: {}", ty.to_string()))),
+                _ => return Err(syn::Error::new_spanned(&ty, format!("unknown quoted Python syntax type: {}", ty))),
             }
         };
         Ok( Self { tokens })
@@ -26,27 +26,25 @@
 //! That's not a very exciting piece of generated code, but it's a nice
 //! little exercise in seeing whether this whole thing is a good idea.
 
-use codemaker::{CodeMaker, OutputFileSet};
+use codemaker::CodeMaker;
+use codemaker_cli::Cli;
 use codemaker_sample::{StatusCodes, StatusModuleMaker};
 
-fn main() -> std::io::Result<()> {
-    // Read the input data into our source data structure.
-    let codes: StatusCodes = serde_yaml::from_str(
-        std::fs::read_to_string("status_codes.yaml")
-            .unwrap()
-            .as_str(),
-    )
-    .unwrap();
+fn main() {
+    Cli::new("codemaker-sample")
+        .about("Generate a Python module of HTTP status codes.")
+        .default_input("status_codes.yaml")
+        .run(|opts| {
+            // Read the input data into our source data structure.
+            let codes: StatusCodes = opts.load_input()?;
 
-    // Configure the Maker with the name of the output module.
-    let maker = StatusModuleMaker {
-        module_name: "status_codes".into(),
-    };
+            // Configure the Maker with the name of the output module.
+            let maker = StatusModuleMaker {
+                module_name: "status_codes".into(),
+            };
 
-    // Convert the input data into a Python module.
-    let output = maker.make(&codes);
-
-    // Wwrite it out to disk.
-    output.write_into_dir("./")?;
-    Ok(())
+            // Convert the input data into a Python module, which the command line
+            // will write out to disk or otherwise handle as requested.
+            Ok(maker.make(&codes))
+        })
 }